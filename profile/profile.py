@@ -1,4 +1,7 @@
 from typing import Mapping, Sequence, Type
+import io
+import os
+import tempfile
 import time
 
 import numpy as np
@@ -96,6 +99,37 @@ def get_times_transposed_arrays(
     )
 
 
+def get_times_inputs(
+    n_rows: Sequence[int], dtype: Type, n_iters: int = 10
+) -> Mapping[str, np.ndarray]:
+    """Times the same document given as bytes, a buffer, a file object and a path. With the simd feature bytes,
+    buffers and paths are copied before simd-json parses them while file objects are read by serde_json"""
+    deserializer = NumpyDeserializer.from_dict(dict(key=dtype))
+    times = {"bytes": [], "buffer": [], "file": [], "path": []}
+    n_rows_ = []
+    for rows in n_rows:
+        data = make_data((rows, 10), dtype)
+        n_rows_.append(rows)
+        with tempfile.NamedTemporaryFile(suffix=".json", delete=False) as f:
+            f.write(data)
+        inputs = {
+            "bytes": lambda: deserializer.deserialize_json(data),
+            "buffer": lambda: deserializer.deserialize_json(memoryview(data)),
+            "file": lambda: deserializer.deserialize_json(io.BytesIO(data)),
+            "path": lambda: deserializer.read_json(f.name),
+        }
+        for name, deserialize in inputs.items():
+            times_ = []
+            for _ in range(n_iters):
+                time0 = time.time()
+                _ = deserialize()
+                times_.append(time.time() - time0)
+            times[name].append(np.median(times_))
+        os.remove(f.name)
+
+    return dict(n_rows=np.array(n_rows_), **{name: np.array(t) for name, t in times.items()})
+
+
 def get_times_jpeg() -> Mapping[str, np.ndarray]:
     sizes = [2**i for i in range(2, 14)]
     times_pillow = []
@@ -198,6 +232,21 @@ def run_profile_transposed_arrays(dtypes: Sequence[type]):
     plt.savefig("./transpose_profile.png", pad_inches=0, bbox_inches='tight')
 
 
+def run_profile_inputs():
+    print("Profiling json inputs...")
+    n_rows = (2 ** np.arange(0, 20, 2)).astype(int)
+    times = get_times_inputs(n_rows, np.float64)
+    fig = plt.figure(figsize=(6, 4))
+    for name in ["buffer", "file", "path"]:
+        plt.plot(times["n_rows"], times["bytes"] / times[name], alpha=0.5)
+    plt.grid()
+    plt.xscale("log")
+    plt.legend(["buffer", "file", "path"])
+    plt.title("json inputs (n_rows x 10 float64)", fontsize=10)
+    plt.ylabel("speed up over bytes")
+    plt.savefig("./inputs_profile.png", pad_inches=0, bbox_inches='tight')
+
+
 def run_profile_jpeg():
     print("Profiling JPEG decoding...")
     times = get_times_jpeg()
@@ -248,6 +297,7 @@ if __name__ == "__main__":
     ]
     run_profile_2d_array(dtypes)
     run_profile_transposed_arrays(dtypes)
+    run_profile_inputs()
     run_profile_jpeg()
     run_profile_png()
     print("Done.")
@@ -0,0 +1,367 @@
+//! Decompression of documents compressed with gzip, zstd or lz4, which are recognised by the magic bytes of
+//! their frames. Documents are decompressed while they are parsed, by flate2, ruzstd and lz4_flex
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use ruzstd::decoding::errors::{FrameDecoderError, ReadFrameHeaderError};
+use ruzstd::decoding::{BlockDecodingStrategy, FrameDecoder};
+
+const ZSTD_MAGIC: u32 = 0xFD2FB528;
+const LZ4_MAGIC: u32 = 0x184D2204;
+/// Magic numbers of the frames that are skipped, shared by zstd and lz4
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+
+/// Bytes needed to recognise the compression of a document
+pub const MAGIC_SIZE: usize = 4;
+
+/// Decompressed bytes handed to the parser at a time
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// How a document is compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    /// Recognised by the magic bytes at the start of the document
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl FromStr for Compression {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Compression::Auto),
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised compression {}. Expected one of: \"auto\", \"none\", \"gzip\", \"zstd\", \"lz4\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for Compression {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        Compression::from_str(object.extract()?)
+    }
+}
+
+impl Compression {
+    /// The compression given by the magic bytes at the start of `data`, `None` when there are none
+    pub fn detect(data: &[u8]) -> Compression {
+        [Compression::Gzip, Compression::Zstd, Compression::Lz4]
+            .into_iter()
+            .find(|compression| compression.starts(data))
+            .unwrap_or(Compression::None)
+    }
+
+    /// The compression of a document starting with `head`, which is recognised for `Auto` and checked otherwise
+    pub fn resolve(self, head: &[u8]) -> Result<Compression, CompressionError> {
+        let compression = match self {
+            Compression::Auto => Compression::detect(head),
+            compression => compression,
+        };
+        if !compression.starts(head) {
+            return Err(CompressionError::Decode(format!("Document isn't compressed with {}", compression.name())));
+        }
+        Ok(compression)
+    }
+
+    /// Whether `data` starts with a frame of this compression
+    fn starts(self, data: &[u8]) -> bool {
+        let magic = data.get(..4).map(|magic| u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]));
+        let skippable = magic.is_some_and(|magic| magic & 0xFFFF_FFF0 == SKIPPABLE_MAGIC);
+        match self {
+            Compression::Auto | Compression::None => true,
+            Compression::Gzip => data.starts_with(&[0x1F, 0x8B]),
+            Compression::Zstd => magic == Some(ZSTD_MAGIC) || skippable,
+            Compression::Lz4 => magic == Some(LZ4_MAGIC) || skippable,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Auto => "auto",
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CompressionError {
+    /// The document is corrupt, or isn't compressed as given
+    Decode(String),
+    /// The decompressed document is larger than the limit
+    Limit(String),
+    /// The document couldn't be read from its file
+    Read(String),
+}
+
+fn limit_error(max_size: usize) -> CompressionError {
+    CompressionError::Limit(format!(
+        "Decompressed document is larger than {} bytes (limit max_decompressed_bytes = {})",
+        max_size, max_size
+    ))
+}
+
+/// Decompresses a document as it is read. It fails once more than `max_size` bytes come out, and keeps its error
+/// so that the error is reported instead of the parse error it causes
+pub struct Decoder<'r> {
+    compression: Compression,
+    frames: Box<dyn Read + Send + 'r>,
+    size: usize,
+    max_size: usize,
+    error: Option<CompressionError>,
+}
+
+impl<'r> Decoder<'r> {
+    /// Decompresses `source`, `None` when it isn't compressed. `compression` is the one `Compression::resolve`
+    /// gave for the start of `source`
+    pub fn new<R: Read + Send + 'r>(compression: Compression, source: R, max_size: Option<usize>) -> Option<Self> {
+        let source = Source(source);
+        let frames: Box<dyn Read + Send + 'r> = match compression {
+            Compression::Auto | Compression::None => return None,
+            Compression::Gzip => Box::new(MultiGzDecoder::new(source)),
+            Compression::Zstd => Box::new(ZstdFrames::new(source)),
+            Compression::Lz4 => Box::new(Lz4Frames::new(source)),
+        };
+        Some(Decoder { compression, frames, size: 0, max_size: max_size.unwrap_or(usize::MAX), error: None })
+    }
+
+    /// Parses the decompressed document with `parse`. The parser stops at the end of the document, so the rest
+    /// is decompressed after it to check the remaining frames and checksums
+    pub fn parse<T>(mut self, parse: impl FnOnce(&mut dyn Read) -> T) -> Result<T, CompressionError> {
+        let value = parse(&mut io::BufReader::with_capacity(BUFFER_SIZE, &mut self));
+        if self.error.is_none() {
+            // the error, if any, is kept by the decoder
+            io::copy(&mut self, &mut io::sink()).ok();
+        }
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(value),
+        }
+    }
+
+    fn fail(&mut self, err: CompressionError) -> io::Error {
+        let message = match &err {
+            CompressionError::Decode(message) | CompressionError::Limit(message) | CompressionError::Read(message) => {
+                message.clone()
+            }
+        };
+        self.error = Some(err);
+        io::Error::other(message)
+    }
+}
+
+impl Read for Decoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::Error::other("the document failed to decompress"));
+        }
+        match self.frames.read(buf) {
+            Ok(read) => {
+                self.size += read;
+                if self.size > self.max_size {
+                    return Err(self.fail(limit_error(self.max_size)));
+                }
+                Ok(read)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Err(err),
+            Err(err) => {
+                let err = match read_error(&err) {
+                    Some(read_error) => CompressionError::Read(read_error.to_string()),
+                    None => CompressionError::Decode(format!("Invalid {} document: {}", self.compression.name(), err)),
+                };
+                Err(self.fail(err))
+            }
+        }
+    }
+}
+
+/// An error reading the compressed document, told apart from the errors decoding it
+#[derive(Debug)]
+struct ReadError(io::Error);
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// The compressed document, marking its read errors
+struct Source<R>(R);
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::Interrupted => err,
+            kind => io::Error::new(kind, ReadError(err)),
+        })
+    }
+}
+
+/// The read error behind `err`, which the decoders pass on as is or wrapped in errors of their own
+fn read_error(err: &io::Error) -> Option<&ReadError> {
+    let mut error: &(dyn Error + 'static) = err.get_ref()?;
+    loop {
+        if let Some(read_error) = error.downcast_ref::<ReadError>() {
+            return Some(read_error);
+        }
+        error = match error.downcast_ref::<io::Error>() {
+            Some(err) => err.get_ref()?,
+            None => error.source()?,
+        };
+    }
+}
+
+/// Decodes the concatenated zstd frames of a document, skipping the skippable frames
+struct ZstdFrames<R> {
+    source: io::BufReader<R>,
+    frame: FrameDecoder,
+    /// Whether a frame was started and not fully read yet
+    in_frame: bool,
+    /// The bytes read from the frame
+    frame_size: u64,
+}
+
+impl<R: Read> ZstdFrames<R> {
+    fn new(source: R) -> Self {
+        ZstdFrames { source: io::BufReader::new(source), frame: FrameDecoder::new(), in_frame: false, frame_size: 0 }
+    }
+}
+
+impl<R: Read> Read for ZstdFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.in_frame {
+                let read = self.frame.read(buf)?;
+                self.frame_size += read as u64;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                if !self.frame.is_finished() {
+                    self.frame
+                        .decode_blocks(&mut self.source, BlockDecodingStrategy::UptoBytes(buf.len()))
+                        .map_err(io::Error::other)?;
+                    continue;
+                }
+                if let (Some(expected), Some(found)) =
+                    (self.frame.get_checksum_from_data(), self.frame.get_calculated_checksum())
+                {
+                    if expected != found {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame checksum doesn't match"));
+                    }
+                }
+                // ruzstd gives 0 when the frame doesn't declare its size
+                let content_size = self.frame.content_size();
+                if content_size != 0 && content_size != self.frame_size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("frame declares {} bytes but has {}", content_size, self.frame_size),
+                    ));
+                }
+                self.in_frame = false;
+            }
+            if self.source.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            match self.frame.reset(&mut self.source) {
+                Ok(()) => {
+                    self.in_frame = true;
+                    self.frame_size = 0;
+                }
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame { length, .. })) => {
+                    skip(&mut self.source, length)?;
+                }
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+    }
+}
+
+/// Decodes the concatenated lz4 frames of a document. lz4_flex ends the document at the end of each frame, and
+/// where the document ends early between two blocks. It stops at the skippable frames. So the frames are read on
+/// from here
+struct Lz4Frames<R: Read> {
+    frames: lz4_flex::frame::FrameDecoder<Watched<io::BufReader<R>>>,
+    ended: bool,
+}
+
+impl<R: Read> Lz4Frames<R> {
+    fn new(source: R) -> Self {
+        let source = Watched { source: io::BufReader::new(source), ended: false };
+        Lz4Frames { frames: lz4_flex::frame::FrameDecoder::new(source), ended: false }
+    }
+}
+
+impl<R: Read> Read for Lz4Frames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.ended {
+            match self.frames.read(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    // a frame ends with its end mark, before the document does
+                    let source = self.frames.get_mut();
+                    if source.ended {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of the document"));
+                    }
+                    self.ended = source.source.fill_buf()?.is_empty();
+                }
+                Err(err) => match err.get_ref().and_then(|error| error.downcast_ref::<lz4_flex::frame::Error>()) {
+                    // the magic number and the size were read, the frame is read next
+                    Some(lz4_flex::frame::Error::SkippableFrame(length)) => {
+                        let source = &mut self.frames.get_mut().source;
+                        skip(source, *length)?;
+                        self.ended = source.fill_buf()?.is_empty();
+                    }
+                    _ => return Err(err),
+                },
+                read => return read,
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Notes whether the end of `source` was reached
+struct Watched<R> {
+    source: R,
+    ended: bool,
+}
+
+impl<R: Read> Read for Watched<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.source.read(buf)?;
+        self.ended |= read == 0 && !buf.is_empty();
+        Ok(read)
+    }
+}
+
+/// Skips the `length` bytes of a skippable frame
+fn skip(source: &mut impl Read, length: u32) -> io::Result<()> {
+    let skipped = io::copy(&mut source.take(length as u64), &mut io::sink())?;
+    if skipped < length as u64 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "skippable frame ends early"));
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use pyo3::exceptions::{PyException, PyIOError, PyTypeError, PyValueError};
+use pyo3::once_cell::GILOnceCell;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple, PyType};
+
+/// The kinds of error raised to python, each with its own exception class
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The document doesn't have the layout given by the structure (or the structure itself is invalid)
+    Structure,
+    /// A key in the structure is missing from the document
+    MissingKey,
+    /// A value can't be converted to the requested type
+    Cast,
+    /// An array isn't rectangular or doesn't fit the array it is written into
+    Shape,
+    /// The document isn't valid json / msgpack
+    Syntax,
+    /// An image couldn't be decoded
+    ImageDecode,
+    /// The input goes over one of the configured resource limits
+    Limit,
+    /// A compressed document is corrupt
+    Decompression,
+}
+
+struct Exceptions {
+    base: Py<PyType>,
+    structure: Py<PyType>,
+    missing_key: Py<PyType>,
+    cast: Py<PyType>,
+    shape: Py<PyType>,
+    syntax: Py<PyType>,
+    image_decode: Py<PyType>,
+    limit: Py<PyType>,
+    decompression: Py<PyType>,
+}
+
+static EXCEPTIONS: GILOnceCell<Exceptions> = GILOnceCell::new();
+
+/// Creates `name(*bases)` in the serde_numpy module
+fn new_exception(py: Python, name: &str, doc: &str, bases: &[&PyType]) -> PyResult<Py<PyType>> {
+    let dict = PyDict::new(py);
+    dict.set_item("__module__", "serde_numpy")?;
+    dict.set_item("__doc__", doc)?;
+    let bases = PyTuple::new(py, bases);
+    let cls = py.get_type::<PyType>().call1((name, bases, dict))?;
+    Ok(cls.downcast::<PyType>()?.into())
+}
+
+impl Exceptions {
+    fn new(py: Python) -> PyResult<Exceptions> {
+        let base = new_exception(
+            py,
+            "SerdeNumpyError",
+            "Base class of all errors raised by serde_numpy",
+            &[py.get_type::<PyException>()],
+        )?;
+        let base_type = base.as_ref(py);
+        // Deprecated: before this hierarchy existed these errors were raised as `TypeError`, which they stay until the
+        // next minor release so that `except TypeError` keeps working. `ValueError` is the base to migrate to
+        let type_error = py.get_type::<PyTypeError>();
+        let value_error = py.get_type::<PyValueError>();
+        Ok(Exceptions {
+            structure: new_exception(
+                py,
+                "StructureError",
+                "The document doesn't match the structure, or the structure is invalid",
+                &[base_type, type_error, value_error],
+            )?,
+            missing_key: new_exception(
+                py,
+                "MissingKeyError",
+                "Keys given in the structure are missing from the document",
+                &[base_type, type_error, value_error],
+            )?,
+            cast: new_exception(
+                py,
+                "CastError",
+                "A value can't be converted to the type given in the structure",
+                &[base_type, type_error, value_error],
+            )?,
+            shape: new_exception(
+                py,
+                "ShapeError",
+                "An array is irregular or doesn't match the shape of the array it is written into",
+                &[base_type, value_error],
+            )?,
+            syntax: new_exception(
+                py,
+                "DocumentSyntaxError",
+                "The document is not valid json or msgpack",
+                &[base_type, type_error, value_error],
+            )?,
+            image_decode: new_exception(
+                py,
+                "ImageDecodeError",
+                "An image couldn't be decoded",
+                &[base_type, py.get_type::<PyIOError>()],
+            )?,
+            limit: new_exception(
+                py,
+                "LimitError",
+                "The input goes over one of the configured resource limits",
+                &[base_type, value_error],
+            )?,
+            decompression: new_exception(
+                py,
+                "DecompressionError",
+                "A compressed document is corrupt, or isn't compressed as given",
+                &[base_type, value_error],
+            )?,
+            base,
+        })
+    }
+
+    fn get(&self, kind: ErrorKind) -> &Py<PyType> {
+        match kind {
+            ErrorKind::Structure => &self.structure,
+            ErrorKind::MissingKey => &self.missing_key,
+            ErrorKind::Cast => &self.cast,
+            ErrorKind::Shape => &self.shape,
+            ErrorKind::Syntax => &self.syntax,
+            ErrorKind::ImageDecode => &self.image_decode,
+            ErrorKind::Limit => &self.limit,
+            ErrorKind::Decompression => &self.decompression,
+        }
+    }
+}
+
+fn exceptions(py: Python<'_>) -> PyResult<&Exceptions> {
+    if let Some(exceptions) = EXCEPTIONS.get(py) {
+        return Ok(exceptions);
+    }
+    let exceptions = Exceptions::new(py)?;
+    Ok(EXCEPTIONS.get_or_init(py, || exceptions))
+}
+
+/// Adds `SerdeNumpyError` and its subclasses to the module
+pub fn add_exceptions(py: Python, m: &PyModule) -> PyResult<()> {
+    let exceptions = exceptions(py)?;
+    m.add("SerdeNumpyError", exceptions.base.as_ref(py))?;
+    for kind in [
+        ErrorKind::Structure,
+        ErrorKind::MissingKey,
+        ErrorKind::Cast,
+        ErrorKind::Shape,
+        ErrorKind::Syntax,
+        ErrorKind::ImageDecode,
+        ErrorKind::Limit,
+        ErrorKind::Decompression,
+    ] {
+        let cls = exceptions.get(kind).as_ref(py);
+        m.add(cls.name()?, cls)?;
+    }
+    Ok(())
+}
+
+/// Attributes set on the raised exception, `None` when not known
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorAttributes {
+    pub path: Option<String>,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Creates the exception for `kind` with the given attributes
+pub fn new_err_with(py: Python, kind: ErrorKind, message: String, attributes: ErrorAttributes) -> PyErr {
+    let create = || -> PyResult<PyErr> {
+        let err = exceptions(py)?.get(kind).as_ref(py).call1((message,))?;
+        err.setattr("path", attributes.path)?;
+        err.setattr("expected", attributes.expected)?;
+        err.setattr("found", attributes.found)?;
+        err.setattr("line", attributes.line)?;
+        err.setattr("column", attributes.column)?;
+        err.setattr("offset", attributes.offset)?;
+        Ok(PyErr::from_value(err))
+    };
+    create().unwrap_or_else(|err| err)
+}
+
+pub fn new_err(py: Python, kind: ErrorKind, message: impl Into<String>) -> PyErr {
+    new_err_with(py, kind, message.into(), ErrorAttributes::default())
+}
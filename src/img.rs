@@ -1,15 +1,102 @@
+use ndarray::ArrayViewMutD;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
 use zune_jpeg::errors::DecodeErrors;
 use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions;
 use zune_jpeg::zune_core::result::DecodingResult;
 use zune_jpeg::{JpegDecoder, ImageInfo};
 
 use zune_png::error::PngDecodeErrors;
+use zune_png::zune_core::bit_depth::{BitDepth, ByteEndian};
 use zune_png::{PngDecoder, PngInfo};
 
 use crate::parsing::OutputTypes;
 use crate::parsing::array_types::{Base, Array};
 
 
+pub enum ImageError {
+    /// The image is corrupt or unsupported
+    Decode(String),
+    /// The image is larger than the `ImageLimits`
+    Limit(String),
+    /// The image doesn't have the shape of the out array
+    Shape(String),
+}
+
+impl From<DecodeErrors> for ImageError {
+    fn from(err: DecodeErrors) -> Self {
+        ImageError::Decode(err.to_string())
+    }
+}
+
+impl From<PngDecodeErrors> for ImageError {
+    fn from(err: PngDecodeErrors) -> Self {
+        ImageError::Decode(format!("{:?}", err))
+    }
+}
+
+/// The error for header information the decoder didn't produce, which a malformed image can cause
+fn missing_header(what: &str) -> ImageError {
+    ImageError::Decode(format!("image header has no {}", what))
+}
+
+
+/// Limits on the size of an untrusted image, checked against its header before any pixels are allocated
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImageLimits {
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    pub max_pixels: Option<usize>,
+}
+
+impl<'source> FromPyObject<'source> for ImageLimits {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let mut limits = ImageLimits::default();
+        for (key, value) in object.downcast::<PyDict>()? {
+            let limit = match key.extract::<&str>()? {
+                "max_width" => &mut limits.max_width,
+                "max_height" => &mut limits.max_height,
+                "max_pixels" => &mut limits.max_pixels,
+                key => {
+                    return Err(PyValueError::new_err(format!(
+                        "unrecognised image limit {}. Expected one of: \"max_width\", \"max_height\", \"max_pixels\"",
+                        key
+                    )))
+                }
+            };
+            *limit = value.extract()?;
+        }
+        Ok(limits)
+    }
+}
+
+impl ImageLimits {
+    /// zune refuses images over its own maximum size (16384 x 16384), which is raised to our limits so
+    /// that they are the ones reported
+    fn decoder_options(&self) -> DecoderOptions {
+        let options = DecoderOptions::default();
+        let max_width = self.max_width.unwrap_or(0).max(options.get_max_width());
+        let max_height = self.max_height.unwrap_or(0).max(options.get_max_height());
+        options.set_max_width(max_width).set_max_height(max_height)
+    }
+
+    fn check(&self, width: usize, height: usize) -> Result<(), ImageError> {
+        let exceeded = |limit: &str, value: usize, max: Option<usize>| match max {
+            Some(max) if value > max => Err(ImageError::Limit(format!(
+                "Image of {} x {} pixels is over the limit {} = {}",
+                width, height, limit, max
+            ))),
+            _ => Ok(()),
+        };
+        exceeded("max_width", width, self.max_width)?;
+        exceeded("max_height", height, self.max_height)?;
+        exceeded("max_pixels", width.saturating_mul(height), self.max_pixels)
+    }
+}
 
 
 fn shape_from_info(info: ImageInfo) -> Vec<usize> {
@@ -17,39 +104,88 @@ fn shape_from_info(info: ImageInfo) -> Vec<usize> {
 }
 
 
-pub fn decode_jpeg_bytes(jpeg_bytes: &[u8]) -> Result<Array<u8>, DecodeErrors> {
-    let mut decoder = JpegDecoder::new(jpeg_bytes);
-    let result = decoder.decode();
-    let info = decoder.info();
-    match result {
-        Ok(pixels) => {
-            let shape = shape_from_info(info.unwrap());
-            Ok(Array(Base::Array(pixels), Some(shape)))
-        },
-        Err(err) => Err(err)
+pub fn decode_jpeg_bytes(jpeg_bytes: &[u8], limits: &ImageLimits) -> Result<Array<u8>, ImageError> {
+    let mut decoder = JpegDecoder::new_with_options(limits.decoder_options(), jpeg_bytes);
+    decoder.decode_headers()?;
+    let info = decoder.info().ok_or_else(|| missing_header("image info"))?;
+    limits.check(info.width as usize, info.height as usize)?;
+    let pixels = decoder.decode()?;
+    Ok(Array(Base::Array(pixels), Some(shape_from_info(info))))
+}
+
+
+/// Decodes straight into the caller's array, which must have the same shape as the image
+pub fn decode_jpeg_bytes_into(jpeg_bytes: &[u8], out: &mut ArrayViewMutD<u8>, limits: &ImageLimits) -> Result<(), ImageError> {
+    let mut decoder = JpegDecoder::new_with_options(limits.decoder_options(), jpeg_bytes);
+    decoder.decode_headers()?;
+    let info = decoder.info().ok_or_else(|| missing_header("image info"))?;
+    limits.check(info.width as usize, info.height as usize)?;
+    let shape = shape_from_info(info);
+    if out.shape() != shape.as_slice() {
+        return Err(ImageError::Shape(format!(
+            "out array has shape {:?} but the image has shape {:?}",
+            out.shape(),
+            shape
+        )));
+    }
+    match out.as_slice_mut() {
+        Some(pixels) => Ok(decoder.decode_into(pixels)?),
+        None => Err(ImageError::Decode("out array must be C-contiguous".to_string())),
     }
 }
 
 
-fn shape_from_png_info(info: &PngInfo, colorspace: ColorSpace) -> Vec<usize> {
-    vec![info.height as usize, info.width as usize, colorspace.num_components() as usize]
+fn png_shape(decoder: &PngDecoder) -> Result<Vec<usize>, ImageError> {
+    let info: &PngInfo = decoder.get_info().ok_or_else(|| missing_header("image info"))?;
+    let colorspace: ColorSpace = decoder.get_colorspace().ok_or_else(|| missing_header("colorspace"))?;
+    Ok(vec![info.height, info.width, colorspace.num_components()])
+}
+
+
+pub fn decode_png_bytes(png_bytes: &[u8], limits: &ImageLimits) -> Result<OutputTypes, ImageError> {
+    let mut decoder = PngDecoder::new_with_options(png_bytes, limits.decoder_options());
+    decoder.decode_headers()?;
+    let (width, height) = decoder.get_dimensions().ok_or_else(|| missing_header("dimensions"))?;
+    limits.check(width, height)?;
+    let decoding = decoder.decode()?;
+    let shape = png_shape(&decoder)?;
+    match decoding {
+        DecodingResult::U8(pixels) => Ok(OutputTypes::U8(Array(Base::Array(pixels), Some(shape)))),
+        DecodingResult::U16(pixels) => Ok(OutputTypes::U16(Array(Base::Array(pixels), Some(shape)))),
+        _ => Err(ImageError::Decode("unsupported png sample format".to_string())),
+    }
 }
 
 
-pub fn decode_png_bytes(png_bytes: &[u8]) -> Result<OutputTypes, PngDecodeErrors> {
-    let mut decoder = PngDecoder::new(png_bytes);
-    let result = decoder.decode();
-    let maybe_info = decoder.get_info();
-    let components = decoder.get_colorspace();
-    match result {
-        Ok(decoding) => {
-            let shape = shape_from_png_info(maybe_info.unwrap(), components.unwrap());
-            match decoding {
-                DecodingResult::U8(pixels) => Ok(OutputTypes::U8(Array(Base::Array(pixels), Some(shape)))),
-                DecodingResult::U16(pixels) => Ok(OutputTypes::U16(Array(Base::Array(pixels), Some(shape)))),
-                _ => panic!("other variants not supported yet")
-            }
-        },
-        Err(err) => Err(err)
+/// Decodes straight into the caller's array, which must have the same shape as the image and
+/// a dtype matching its bit depth (uint8 or uint16)
+pub fn decode_png_bytes_into<T: bytemuck::Pod>(png_bytes: &[u8], out: &mut ArrayViewMutD<T>, limits: &ImageLimits) -> Result<(), ImageError> {
+    let mut decoder = PngDecoder::new_with_options(png_bytes, limits.decoder_options());
+    decoder.decode_headers()?;
+    let (width, height) = decoder.get_dimensions().ok_or_else(|| missing_header("dimensions"))?;
+    limits.check(width, height)?;
+    let shape = png_shape(&decoder)?;
+    let depth = decoder.get_depth().ok_or_else(|| missing_header("bit depth"))?;
+    let bytes_per_sample = if depth == BitDepth::Sixteen { 2 } else { 1 };
+    if bytes_per_sample != std::mem::size_of::<T>() {
+        return Err(ImageError::Decode(format!(
+            "out array has dtype {} but the image has a bit depth of {:?}",
+            std::any::type_name::<T>(),
+            depth
+        )));
+    }
+    if out.shape() != shape.as_slice() {
+        return Err(ImageError::Shape(format!(
+            "out array has shape {:?} but the image has shape {:?}",
+            out.shape(),
+            shape
+        )));
+    }
+    // 16 bit samples are written in native endianness so that they can be read as u16
+    let byte_endian = if cfg!(target_endian = "little") { ByteEndian::LE } else { ByteEndian::BE };
+    decoder.set_options(decoder.get_options().set_byte_endian(byte_endian));
+    match out.as_slice_mut() {
+        Some(pixels) => Ok(decoder.decode_into(bytemuck::cast_slice_mut(pixels))?),
+        None => Err(ImageError::Decode("out array must be C-contiguous".to_string())),
     }
 }
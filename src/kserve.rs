@@ -0,0 +1,137 @@
+use half::f16;
+use numpy::{Element, PyArrayDyn};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::{pyfunction, PyAny, PyObject, PyResult, Python, ToPyObject};
+use pyo3::types::{PyBytes, PyDict};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+/// A tensor of an Open Inference Protocol (KServe v2) message
+#[derive(Serialize)]
+struct Tensor {
+    name: String,
+    shape: Vec<usize>,
+    datatype: &'static str,
+    data: Data,
+}
+
+/// The values of a tensor, flattened in row major order
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Data {
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    I64(Vec<i64>),
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    /// FP16 values are written as json numbers, which are widened to f32 first
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+    Bool(Vec<bool>),
+    Bytes(Vec<String>),
+}
+
+/// `{"model_name": ..., "id": ..., "outputs": [...]}`, leaving out the fields which aren't given
+struct Message<'a> {
+    model_name: Option<&'a str>,
+    id: Option<&'a str>,
+    key: &'a str,
+    tensors: Vec<Tensor>,
+}
+
+impl Serialize for Message<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(model_name) = self.model_name {
+            map.serialize_entry("model_name", model_name)?;
+        }
+        if let Some(id) = self.id {
+            map.serialize_entry("id", id)?;
+        }
+        map.serialize_entry(self.key, &self.tensors)?;
+        map.end()
+    }
+}
+
+/// The shape and values of a numpy array, copied in row major order whatever its memory layout
+fn values<T: Element + Copy>(array: &PyArrayDyn<T>) -> PyResult<(Vec<usize>, Vec<T>)> {
+    let array = array.try_readonly()?;
+    let array = array.as_array();
+    Ok((array.shape().to_vec(), array.iter().copied().collect()))
+}
+
+/// The elements of an object array, which must be `str` or utf-8 `bytes` to be written as json strings
+fn strings(name: &str, array: &PyArrayDyn<PyObject>) -> PyResult<(Vec<usize>, Vec<String>)> {
+    let py = array.py();
+    let array = array.try_readonly()?;
+    let array = array.as_array();
+    let mut strings = Vec::with_capacity(array.len());
+    for value in array.iter() {
+        let value = value.as_ref(py);
+        let string = match value.downcast::<PyBytes>() {
+            Ok(bytes) => std::str::from_utf8(bytes.as_bytes()).map(str::to_string).map_err(|_| {
+                PyValueError::new_err(format!("BYTES tensor {:?} holds bytes which aren't utf-8, they can't be written as json", name))
+            })?,
+            Err(_) => value.extract::<String>().map_err(|_| {
+                PyTypeError::new_err(format!("BYTES tensor {:?} must hold str or bytes, found: {}", name, value.get_type()))
+            })?,
+        };
+        strings.push(string);
+    }
+    Ok((array.shape().to_vec(), strings))
+}
+
+macro_rules! try_tensor {
+    ($name:expr, $array:expr, $type:ty, $datatype:literal, $variant:ident) => {
+        if let Ok(array) = $array.downcast::<PyArrayDyn<$type>>() {
+            let (shape, data) = values(array)?;
+            return Ok(Tensor { name: $name.to_string(), shape, datatype: $datatype, data: Data::$variant(data) });
+        }
+    };
+}
+
+fn tensor(name: &str, array: &PyAny) -> PyResult<Tensor> {
+    try_tensor!(name, array, i8, "INT8", I8);
+    try_tensor!(name, array, i16, "INT16", I16);
+    try_tensor!(name, array, i32, "INT32", I32);
+    try_tensor!(name, array, i64, "INT64", I64);
+    try_tensor!(name, array, u8, "UINT8", U8);
+    try_tensor!(name, array, u16, "UINT16", U16);
+    try_tensor!(name, array, u32, "UINT32", U32);
+    try_tensor!(name, array, u64, "UINT64", U64);
+    try_tensor!(name, array, f32, "FP32", F32);
+    try_tensor!(name, array, f64, "FP64", F64);
+    try_tensor!(name, array, bool, "BOOL", Bool);
+    if let Ok(array) = array.downcast::<PyArrayDyn<f16>>() {
+        let (shape, data) = values(array)?;
+        let data = Data::F32(data.into_iter().map(f32::from).collect());
+        return Ok(Tensor { name: name.to_string(), shape, datatype: "FP16", data });
+    }
+    if let Ok(array) = array.downcast::<PyArrayDyn<PyObject>>() {
+        let (shape, data) = strings(name, array)?;
+        return Ok(Tensor { name: name.to_string(), shape, datatype: "BYTES", data: Data::Bytes(data) });
+    }
+    Err(PyTypeError::new_err(format!(
+        "Cannot encode {:?} as a KServe v2 tensor, expected a numpy array of a numeric, bool or object dtype, found: {}",
+        name,
+        array.repr()?
+    )))
+}
+
+/// Writes the numpy arrays of `tensors` as an Open Inference Protocol (KServe v2) json message, by default a
+/// response with the tensors under "outputs". The json is written with the GIL released
+#[pyfunction]
+pub fn encode_kserve_v2(py: Python, tensors: &PyDict, key: Option<&str>, model_name: Option<&str>, id: Option<&str>) -> PyResult<PyObject> {
+    let tensors = tensors
+        .iter()
+        .map(|(name, array)| tensor(name.extract()?, array))
+        .collect::<PyResult<Vec<Tensor>>>()?;
+    let message = Message { model_name, id, key: key.unwrap_or("outputs"), tensors };
+    let json = py
+        .allow_threads(|| serde_json::to_vec(&message))
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    Ok(PyBytes::new(py, &json).to_object(py))
+}
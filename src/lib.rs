@@ -1,113 +1,408 @@
 use std::fs::read;
+use std::io;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use pyo3::{self, wrap_pyfunction, pyfunction};
-use pyo3::exceptions::{PyTypeError, PyValueError, PyIOError};
-use pyo3::prelude::{pyclass, pymethods, pymodule, IntoPy, PyModule, PyObject, PyResult, Python};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::{pyclass, pymethods, pymodule, IntoPy, PyAny, PyModule, PyObject, PyRef, PyResult, Python};
 use pyo3::types::PyType;
 
-use serde::de::DeserializeSeed;
+use numpy::PyArrayDyn;
+mod errors;
+use errors::{new_err, new_err_with, ErrorKind};
+
 mod parsing;
-use parsing::StructureDescriptor;
+use parsing::array_types::index_out;
+use parsing::out_types;
+use parsing::error_types::RecordError;
+use parsing::stream_types::{Chunk, Sink};
+use parsing::{error_types, ArrayFormat, DeserializeError, Document, extract_with_classes, intern_keys, Limits, OnError, OutputOptions, OutputTypes, Ragged, RecordFormat, Structure, StructureDescriptor};
 
+mod compression;
+use compression::{Compression, CompressionError, Decoder};
 mod img;
-use img::{decode_jpeg_bytes, decode_png_bytes};
+mod kserve;
+mod mmap;
+use img::{decode_jpeg_bytes, decode_jpeg_bytes_into, decode_png_bytes, decode_png_bytes_into, ImageError, ImageLimits};
     
 
 #[pyclass]
 struct NumpyDeserializer {
     structure_descriptor: StructureDescriptor,
+    output_options: OutputOptions,
 }
 
 #[pymethods]
 impl NumpyDeserializer {
     #[classmethod]
-    fn from_dict(_cls: &PyType, py: Python, structure: PyObject) -> PyResult<Self> {
-        match structure.extract(py) {
-            Ok(data) => Ok(NumpyDeserializer { structure_descriptor: StructureDescriptor { data } }),
-            Err(_) => Err(PyTypeError::new_err("structure unsupported. Currently sequences of nested structures are unsupported e.g. [{\"a\": {\"b\": Type}}])"))
+    #[allow(clippy::too_many_arguments)]
+    fn from_dict(_cls: &PyType, py: Python, structure: PyObject, records: Option<RecordFormat>, arrays: Option<ArrayFormat>, on_error: Option<OnError>, limits: Option<Limits>, ragged: Option<Ragged>) -> PyResult<Self> {
+        match extract_with_classes(structure.as_ref(py)) {
+            Ok((mut data, classes)) => {
+                let keys = Arc::new(intern_keys(py, &mut data));
+                let output_options = OutputOptions { records: records.unwrap_or_default(), arrays: arrays.unwrap_or_default(), classes: Arc::new(classes), keys };
+                Ok(NumpyDeserializer { structure_descriptor: StructureDescriptor { data, on_error: on_error.unwrap_or_default(), limits: limits.unwrap_or_default(), ragged: ragged.unwrap_or_default() }, output_options })
+            }
+            Err(err) => Err(unsupported(py, "structure unsupported. Currently sequences of nested structures are unsupported e.g. [{\"a\": {\"b\": Type}}])", err)),
         }
     }
 
     #[classmethod]
-    fn from_json_bytes(_cls: &PyType, _py: Python, bytes: &[u8]) -> PyResult<Self> {
+    #[allow(clippy::too_many_arguments)]
+    fn from_class(_cls: &PyType, py: Python, class: PyObject, records: Option<RecordFormat>, arrays: Option<ArrayFormat>, on_error: Option<OnError>, limits: Option<Limits>, ragged: Option<Ragged>) -> PyResult<Self> {
+        let (class, classes) = extract_with_classes(class.as_ref(py)).map_err(|err| unsupported(py, "class unsupported", err))?;
+        let mut data = Structure::Class(class);
+        let keys = Arc::new(intern_keys(py, &mut data));
+        let output_options = OutputOptions { records: records.unwrap_or_default(), arrays: arrays.unwrap_or_default(), classes: Arc::new(classes), keys };
+        Ok(NumpyDeserializer { structure_descriptor: StructureDescriptor { data, on_error: on_error.unwrap_or_default(), limits: limits.unwrap_or_default(), ragged: ragged.unwrap_or_default() }, output_options })
+    }
+
+    #[classmethod]
+    #[allow(clippy::too_many_arguments)]
+    fn from_json_bytes(_cls: &PyType, py: Python, bytes: &[u8], records: Option<RecordFormat>, arrays: Option<ArrayFormat>, on_error: Option<OnError>, limits: Option<Limits>, ragged: Option<Ragged>) -> PyResult<Self> {
         let result = serde_json::from_slice(bytes);
         match result {
-            Ok(data) => Ok(NumpyDeserializer {
-                structure_descriptor: StructureDescriptor { data },
+            Ok(mut data) => Ok(NumpyDeserializer {
+                output_options: OutputOptions {
+                    records: records.unwrap_or_default(),
+                    arrays: arrays.unwrap_or_default(),
+                    keys: Arc::new(intern_keys(py, &mut data)),
+                    ..Default::default()
+                },
+                structure_descriptor: StructureDescriptor { data, on_error: on_error.unwrap_or_default(), limits: limits.unwrap_or_default(), ragged: ragged.unwrap_or_default() },
             }),
-            Err(err) => Err(PyValueError::new_err(format!(
+            Err(err) => Err(new_err(py, ErrorKind::Structure, format!(
                 "Error parsing structure bytes {}",
                 err
-            ))), // better handling needed
+            ))),
+        }
+    }
+
+    fn deserialize_json(&self, py: Python, json_str: Document, out: Option<&PyAny>, index: Option<usize>, compression: Option<Compression>) -> PyResult<PyObject> {
+        let structure_descriptor = &self.structure_descriptor;
+        let out_arrays = self.start_out(out, index)?;
+        let result = self.parse(
+            py,
+            json_str,
+            compression,
+            |bytes| structure_descriptor.deserialize_json_slice(bytes),
+            |reader| structure_descriptor.deserialize_json_reader(reader),
+        );
+        drop(out_arrays);
+        match result? {
+            Ok(value) => self.output(py, value, out, index),
+            Err(err) => Err(raise(py, err)),
+        }
+    }
+
+    fn deserialize_msgpack(&self, py: Python, msgpack_bytes: Document, out: Option<&PyAny>, index: Option<usize>, compression: Option<Compression>) -> PyResult<PyObject> {
+        let structure_descriptor = &self.structure_descriptor;
+        let out_arrays = self.start_out(out, index)?;
+        let result = self.parse(
+            py,
+            msgpack_bytes,
+            compression,
+            |bytes| structure_descriptor.deserialize_msgpack_slice(bytes),
+            |reader| structure_descriptor.deserialize_msgpack_reader(reader),
+        );
+        drop(out_arrays);
+        match result? {
+            Ok(value) => self.output(py, value, out, index),
+            Err(err) => Err(raise(py, err)),
+        }
+    }
+
+    fn deserialize_jsonl(&self, py: Python, jsonl_str: Document, out: Option<&PyAny>, index: Option<usize>, compression: Option<Compression>) -> PyResult<PyObject> {
+        let structure_descriptor = &self.structure_descriptor;
+        let result = self.parse(
+            py,
+            jsonl_str,
+            compression,
+            |bytes| structure_descriptor.deserialize_jsonl_slice(bytes),
+            |reader| structure_descriptor.deserialize_jsonl_reader(reader),
+        )?;
+        match result {
+            Ok(value) => self.output(py, value, out, index),
+            Err(err) => Err(raise(py, err)),
         }
     }
 
-    fn deserialize_json(&self, py: Python, json_str: &[u8]) -> PyResult<PyObject> {
-        // need to get rid of clone
-        let result = self
-            .structure_descriptor
-            .clone()
-            .deserialize(&mut serde_json::Deserializer::from_slice(json_str));
+    /// Parses a json file, which is memory mapped instead of read into python and decompressed and parsed with
+    /// the GIL released
+    fn read_json(&self, py: Python, path: &str, out: Option<&PyAny>, index: Option<usize>, compression: Option<Compression>) -> PyResult<PyObject> {
+        let file = mmap::open(path).map_err(|err| PyIOError::new_err(err.to_string()))?;
+        let structure_descriptor = &self.structure_descriptor;
+        let out_arrays = self.start_out(out, index)?;
+        let result = py.allow_threads(|| {
+            self.parse_file(
+                &file,
+                compression,
+                |bytes| structure_descriptor.deserialize_json_slice(bytes),
+                |reader| structure_descriptor.deserialize_json_reader(reader),
+            )
+        });
+        drop(out_arrays);
         match result {
-            Ok(value) => value.into_py(py),
-            Err(err) => Err(PyTypeError::new_err(err.to_string())),
+            Ok(Ok(value)) => self.output(py, value, out, index),
+            Ok(Err(err)) => Err(raise(py, err)),
+            Err(err) => Err(raise_compression(py, err)),
         }
     }
 
-    fn deserialize_msgpack(&self, py: Python, msgpack_bytes: &[u8]) -> PyResult<PyObject> {
-        // need to get rid of clone
-        let md = &mut rmp_serde::decode::Deserializer::new(msgpack_bytes);
-        let result = self.structure_descriptor.clone().deserialize(md);
+    /// Parses a msgpack file, which is memory mapped instead of read into python and decompressed and parsed
+    /// with the GIL released
+    fn read_msgpack(&self, py: Python, path: &str, out: Option<&PyAny>, index: Option<usize>, compression: Option<Compression>) -> PyResult<PyObject> {
+        let file = mmap::open(path).map_err(|err| PyIOError::new_err(err.to_string()))?;
+        let structure_descriptor = &self.structure_descriptor;
+        let out_arrays = self.start_out(out, index)?;
+        let result = py.allow_threads(|| {
+            self.parse_file(
+                &file,
+                compression,
+                |bytes| structure_descriptor.deserialize_msgpack_slice(bytes),
+                |reader| structure_descriptor.deserialize_msgpack_reader(reader),
+            )
+        });
+        drop(out_arrays);
         match result {
-            Ok(value) => value.into_py(py),
-            Err(err) => Err(PyTypeError::new_err(err.to_string())),
+            Ok(Ok(value)) => self.output(py, value, out, index),
+            Ok(Err(err)) => Err(raise(py, err)),
+            Err(err) => Err(raise_compression(py, err)),
+        }
+    }
+
+    /// Streams the transposed records of a json document (or of its top level `field`), yielding their columns
+    /// every `chunk_size` records. A binary file object is read incrementally while the chunks are consumed
+    fn iter_json(&self, py: Python, file_or_bytes: Document, chunk_size: usize, field: Option<String>) -> PyResult<RecordChunks> {
+        if chunk_size == 0 {
+            return Err(PyValueError::new_err("chunk_size must be at least 1"));
+        }
+        if let Err(message) = self.structure_descriptor.streamed_records(field.as_deref()) {
+            return Err(new_err(py, ErrorKind::Structure, message));
+        }
+        let reader = file_or_bytes.reader();
+        // the document is parsed on its own thread, which waits while a chunk is waiting to be consumed
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let structure_descriptor = self.structure_descriptor.clone();
+        thread::Builder::new()
+            .name("serde_numpy iter_json".to_string())
+            .spawn(move || {
+                let chunks = sender.clone();
+                let sink: Sink = Box::new(move |chunk| chunks.send(Ok(chunk)).is_ok());
+                if let Err(err) = structure_descriptor.stream_json_reader(reader, field.as_deref(), chunk_size, sink) {
+                    // nothing is left to report to once the iterator was dropped
+                    sender.send(Err(err)).ok();
+                }
+            })
+            .map_err(|err| PyIOError::new_err(err.to_string()))?;
+        Ok(RecordChunks {
+            chunks: Mutex::new(receiver),
+            output_options: self.output_options.clone(),
+            on_error: self.structure_descriptor.on_error,
+        })
+    }
+}
+
+/// Iterator over the chunks of records streamed by `NumpyDeserializer.iter_json`
+#[pyclass]
+struct RecordChunks {
+    chunks: Mutex<Receiver<Result<Chunk, Box<DeserializeError>>>>,
+    output_options: OutputOptions,
+    on_error: OnError,
+}
+
+#[pymethods]
+impl RecordChunks {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let chunks = &self.chunks;
+        // the GIL is released so that the parsing thread can read from a python file object
+        let next = py.allow_threads(|| chunks.lock().ok().and_then(|chunks| chunks.recv().ok()));
+        match next {
+            Some(Ok(chunk)) => {
+                let records = chunk.records.into_py_with(py, &self.output_options)?;
+                with_report(py, self.on_error, records, chunk.errors).map(Some)
+            }
+            Some(Err(err)) => Err(raise(py, err)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Raises the exception class matching the kind of the deserialization error
+fn raise(py: Python, err: Box<DeserializeError>) -> pyo3::PyErr {
+    let attributes = err.attributes();
+    new_err_with(py, err.kind, err.to_string(), attributes)
+}
+
+fn raise_compression(py: Python, err: CompressionError) -> pyo3::PyErr {
+    match err {
+        CompressionError::Decode(message) => new_err(py, ErrorKind::Decompression, message),
+        CompressionError::Limit(message) => new_err(py, ErrorKind::Limit, message),
+        // as when the parser reads the file itself
+        CompressionError::Read(message) => new_err(py, ErrorKind::Syntax, message),
+    }
+}
+
+/// The `StructureError` for a structure that couldn't be extracted, caused by the extraction error which says why
+fn unsupported(py: Python, message: &str, cause: pyo3::PyErr) -> pyo3::PyErr {
+    let err = new_err(py, ErrorKind::Structure, message);
+    err.set_cause(py, Some(cause));
+    err
+}
+
+fn raise_image(py: Python, err: ImageError) -> pyo3::PyErr {
+    match err {
+        ImageError::Decode(message) => new_err(py, ErrorKind::ImageDecode, message),
+        ImageError::Limit(message) => new_err(py, ErrorKind::Limit, message),
+        ImageError::Shape(message) => new_err(py, ErrorKind::Shape, message),
+    }
+}
+
+impl NumpyDeserializer {
+    /// Parses a document given by python, see `Document::parse`. It is decompressed while it is parsed when it is
+    /// compressed, limited to `max_decompressed_bytes`
+    fn parse<T: Send>(
+        &self,
+        py: Python,
+        document: Document,
+        compression: Option<Compression>,
+        from_slice: impl FnOnce(&[u8]) -> T + Send,
+        from_reader: impl FnOnce(&mut dyn io::Read) -> T + Send,
+    ) -> PyResult<T> {
+        let max_size = self.structure_descriptor.limits.max_decompressed_bytes;
+        document
+            .parse(py, compression.unwrap_or_default(), max_size, from_slice, from_reader)
+            .map_err(|err| raise_compression(py, err))
+    }
+
+    /// Parses a mapped file with `from_slice`, or with `from_reader` while it is decompressed when it is compressed
+    fn parse_file<T>(
+        &self,
+        file: &[u8],
+        compression: Option<Compression>,
+        from_slice: impl FnOnce(&[u8]) -> T,
+        from_reader: impl FnOnce(&mut dyn io::Read) -> T,
+    ) -> Result<T, CompressionError> {
+        let max_size = self.structure_descriptor.limits.max_decompressed_bytes;
+        match Decoder::new(compression.unwrap_or_default().resolve(file)?, file, max_size) {
+            Some(decoder) => decoder.parse(from_reader),
+            None => Ok(from_slice(file)),
+        }
+    }
+
+    /// Parses the next document's arrays straight into their counterparts in `out`, until the result is dropped
+    fn start_out<'py>(&self, out: Option<&'py PyAny>, index: Option<usize>) -> PyResult<Option<out_types::OutArrays<'py>>> {
+        out.map(|out| out_types::start(&self.structure_descriptor.data, out, index)).transpose()
+    }
+
+    /// Converts the deserialized value. Unless `on_error` is "raise" the records which were skipped or filled are
+    /// returned next to it
+    fn output(&self, py: Python, value: OutputTypes, out: Option<&PyAny>, index: Option<usize>) -> PyResult<PyObject> {
+        // taken before the conversion, which runs python code that could parse another document on this thread
+        let errors = error_types::take_record_errors();
+        let output = match (out, index) {
+            (Some(out), index) => value.into_py_out(py, &self.output_options, out, index),
+            (None, None) => value.into_py_with(py, &self.output_options),
+            (None, Some(_)) => Err(PyValueError::new_err("index can only be used together with out")),
+        }?;
+        with_report(py, self.structure_descriptor.on_error, output, errors)
+    }
+}
+
+/// Unless `on_error` is "raise" the records which were skipped or filled are returned next to the output
+fn with_report(py: Python, on_error: OnError, output: PyObject, errors: Vec<RecordError>) -> PyResult<PyObject> {
+    match on_error {
+        OnError::Raise => Ok(output),
+        OnError::Skip | OnError::Fill => {
+            let report = errors
+                .into_iter()
+                .map(|err| err.into_py(py))
+                .collect::<PyResult<Vec<PyObject>>>()?;
+            Ok((output, report).into_py(py))
         }
     }
 }
 
 #[pyfunction]
-fn decode_jpeg(py: Python, jpeg_bytes: &[u8]) -> PyResult<PyObject> {
-    match decode_jpeg_bytes(jpeg_bytes) {
-        Ok(output) => output.into_py(py),
-        Err(err) => Err(PyIOError::new_err(err.to_string())),
+fn decode_jpeg(py: Python, jpeg_bytes: &[u8], out: Option<&PyAny>, index: Option<usize>, arrays: Option<ArrayFormat>, limits: Option<ImageLimits>) -> PyResult<PyObject> {
+    let limits = limits.unwrap_or_default();
+    match (out, index) {
+        (Some(out), index) => {
+            let target = index_out(out, index)?.downcast::<PyArrayDyn<u8>>()?;
+            match decode_jpeg_bytes_into(jpeg_bytes, &mut target.try_readwrite()?.as_array_mut(), &limits) {
+                Ok(()) => Ok(out.into()),
+                Err(err) => Err(raise_image(py, err)),
+            }
+        }
+        (None, None) => match decode_jpeg_bytes(jpeg_bytes, &limits) {
+            Ok(output) => output.into_py_with(py, arrays.unwrap_or_default()),
+            Err(err) => Err(raise_image(py, err)),
+        },
+        (None, Some(_)) => Err(PyValueError::new_err("index can only be used together with out")),
     }
 }
 
 #[pyfunction]
-fn read_jpeg(py: Python, path: &str) -> PyResult<PyObject> {
+fn read_jpeg(py: Python, path: &str, out: Option<&PyAny>, index: Option<usize>, arrays: Option<ArrayFormat>, limits: Option<ImageLimits>) -> PyResult<PyObject> {
     match read(path) {
-        Ok(jpeg_bytes) => decode_jpeg(py, &jpeg_bytes),
+        Ok(jpeg_bytes) => decode_jpeg(py, &jpeg_bytes, out, index, arrays, limits),
         Err(err) => Err(PyIOError::new_err(err.to_string())),
     }
 }
 
 
 #[pyfunction]
-fn decode_png(py: Python, png_bytes: &[u8]) -> PyResult<PyObject> {
-    match decode_png_bytes(png_bytes) {
-        Ok(output) => output.into_py(py),
-        Err(err) => Err(PyIOError::new_err(format!("{:?}", err))),
+fn decode_png(py: Python, png_bytes: &[u8], out: Option<&PyAny>, index: Option<usize>, arrays: Option<ArrayFormat>, limits: Option<ImageLimits>) -> PyResult<PyObject> {
+    let limits = limits.unwrap_or_default();
+    match (out, index) {
+        (Some(out), index) => {
+            let target = index_out(out, index)?;
+            let result = if let Ok(target) = target.downcast::<PyArrayDyn<u16>>() {
+                decode_png_bytes_into(png_bytes, &mut target.try_readwrite()?.as_array_mut(), &limits)
+            } else {
+                let target = target.downcast::<PyArrayDyn<u8>>()?;
+                decode_png_bytes_into(png_bytes, &mut target.try_readwrite()?.as_array_mut(), &limits)
+            };
+            match result {
+                Ok(()) => Ok(out.into()),
+                Err(err) => Err(raise_image(py, err)),
+            }
+        }
+        (None, None) => match decode_png_bytes(png_bytes, &limits) {
+            Ok(output) => output.into_py_with(py, &OutputOptions { arrays: arrays.unwrap_or_default(), ..Default::default() }),
+            Err(err) => Err(raise_image(py, err)),
+        },
+        (None, Some(_)) => Err(PyValueError::new_err("index can only be used together with out")),
     }
 }
 
 
 #[pyfunction]
-fn read_png(py: Python, path: &str) -> PyResult<PyObject> {
+fn read_png(py: Python, path: &str, out: Option<&PyAny>, index: Option<usize>, arrays: Option<ArrayFormat>, limits: Option<ImageLimits>) -> PyResult<PyObject> {
     match read(path) {
-        Ok(png_bytes) => decode_png(py, &png_bytes),
+        Ok(png_bytes) => decode_png(py, &png_bytes, out, index, arrays, limits),
         Err(err) => Err(PyIOError::new_err(err.to_string())),
     }
 }
 
 
 #[pymodule]
-fn serde_numpy(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn serde_numpy(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<NumpyDeserializer>()?;
+    m.add_class::<RecordChunks>()?;
+    errors::add_exceptions(py, m)?;
 
     m.add_function(wrap_pyfunction!(decode_jpeg, m)?)?;
     m.add_function(wrap_pyfunction!(read_jpeg, m)?)?;
     m.add_function(wrap_pyfunction!(decode_png, m)?)?;
     m.add_function(wrap_pyfunction!(read_png, m)?)?;
+    m.add_function(wrap_pyfunction!(kserve::encode_kserve_v2, m)?)?;
 
     Ok(())
 }
@@ -0,0 +1,19 @@
+use std::fs::File;
+use std::io;
+
+pub use memmap2::Mmap;
+
+/// Maps a file read only into memory, so it is parsed without first being copied into a buffer.
+///
+/// The mapping reads the file as it is on disk while it is parsed. If another process truncates the file meanwhile,
+/// reading the pages past its new end raises SIGBUS and kills the interpreter, so only map files nothing else
+/// writes to. Writes that don't change the size only change the document being parsed
+pub fn open(path: &str) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // Safety: the map is only read, see above for a file modified while it is mapped
+    let map = unsafe { Mmap::map(&file)? };
+    // the document is parsed front to back once, a failed hint changes nothing
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+    Ok(map)
+}
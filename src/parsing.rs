@@ -1,28 +1,68 @@
-use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
+use std::io;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use itertools::Itertools;
+use half::f16;
+use indexmap::IndexMap;
 
-use serde;
 use serde::de;
-use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, IntoDeserializer, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
 use serde_json::Value;
 
 use pyo3::exceptions::PyValueError;
-use pyo3::prelude::{IntoPy, PyAny, PyErr, PyObject, PyResult, Python};
-use pyo3::types::PyType;
+use pyo3::prelude::{IntoPy, Py, PyAny, PyErr, PyObject, PyResult, Python};
+use pyo3::types::{PyDict, PyString, PyType};
 use pyo3::FromPyObject;
 
+use crate::errors::ErrorKind;
+
 pub(crate) mod array_types;
+mod binary_types;
+mod bytes_types;
+mod class_types;
+mod dlpack_types;
+pub(crate) mod error_types;
+mod input_types;
+mod kserve_types;
+mod limit_types;
+mod map_types;
+mod msgpack_numpy_types;
+pub(crate) mod out_types;
+mod plan_types;
 mod python_types;
+mod record_types;
+mod reshape_types;
+pub(crate) mod stream_types;
+mod tf_serving_types;
 mod transpose_types;
+mod union_types;
 use array_types::{Array, BoolArray};
+use binary_types::{Binary, BinarySeed};
+use bytes_types::Bytes;
+pub use class_types::extract_with_classes;
+use class_types::{ClassSeed, ClassStructure, PythonClass, SequenceSeed, SequenceStructure};
+use map_types::{Fields, MapSeed, MapStructure};
+pub use map_types::intern_keys;
 use python_types::PythonType;
-use transpose_types::{TransposeMap, TransposeSeq};
-
-#[derive(Clone, Debug, Deserialize)]
+pub use dlpack_types::ArrayFormat;
+pub use error_types::{DeserializeError, OnError};
+pub use input_types::Document;
+use input_types::CountingReader;
+use kserve_types::{KServe, KServeSeed};
+use plan_types::Plan;
+pub use limit_types::Limits;
+pub use record_types::RecordFormat;
+use record_types::Records;
+use reshape_types::{Reshape, ReshapeSeed};
+use tf_serving_types::{TfServing, TfServingSeed};
+pub use transpose_types::Ragged;
+use transpose_types::{RecordColumns, RecordTypes, SequenceColumns};
+use union_types::OneOfSeed;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum InputTypes {
     int8,
@@ -35,6 +75,7 @@ pub enum InputTypes {
     uint32,
     uint64,
 
+    float16,
     float32,
     float64,
 
@@ -44,6 +85,7 @@ pub enum InputTypes {
     float,
     str,
     bool,
+    category,
 
     list,
     dict,
@@ -63,6 +105,7 @@ impl Display for InputTypes {
             Self::uint32 => write!(f, "np.uint32"),
             Self::uint64 => write!(f, "np.uint64"),
 
+            Self::float16 => write!(f, "np.float16"),
             Self::float32 => write!(f, "np.float32"),
             Self::float64 => write!(f, "np.float64"),
 
@@ -72,6 +115,7 @@ impl Display for InputTypes {
             Self::float => write!(f, "float"),
             Self::str => write!(f, "str"),
             Self::bool => write!(f, "bool"),
+            Self::category => write!(f, "category"),
 
             Self::list => write!(f, "list"),
             Self::dict => write!(f, "dict"),
@@ -82,32 +126,43 @@ impl Display for InputTypes {
 
 impl InputTypes {
     fn get_transpose_output_type(&self) -> OutputTypes {
-        match self {
-            &InputTypes::int8 => OutputTypes::I8(Array::new()),
-            &InputTypes::int16 => OutputTypes::I16(Array::new()),
-            &InputTypes::int32 => OutputTypes::I32(Array::new()),
-            &InputTypes::int64 => OutputTypes::I64(Array::new()),
-
-            &InputTypes::uint8 => OutputTypes::U8(Array::new()),
-            &InputTypes::uint16 => OutputTypes::U16(Array::new()),
-            &InputTypes::uint32 => OutputTypes::U32(Array::new()),
-            &InputTypes::uint64 => OutputTypes::U64(Array::new()),
-
-            &InputTypes::float32 => OutputTypes::F32(Array::new()),
-            &InputTypes::float64 => OutputTypes::F64(Array::new()),
-
-            &InputTypes::bool_ => OutputTypes::Bool(BoolArray::new()),
-
-            &InputTypes::int => OutputTypes::PyList(Vec::new()),
-            &InputTypes::float => OutputTypes::PyList(Vec::new()),
-            &InputTypes::str => OutputTypes::PyList(Vec::new()),
-            &InputTypes::bool => OutputTypes::PyList(Vec::new()),
-
-            &InputTypes::list => OutputTypes::PyList(Vec::new()),
-            &InputTypes::dict => OutputTypes::PyList(Vec::new()),
-            &InputTypes::any => OutputTypes::PyList(Vec::new()),
+        match *self {
+            InputTypes::int8 => OutputTypes::I8(Array::new()),
+            InputTypes::int16 => OutputTypes::I16(Array::new()),
+            InputTypes::int32 => OutputTypes::I32(Array::new()),
+            InputTypes::int64 => OutputTypes::I64(Array::new()),
+
+            InputTypes::uint8 => OutputTypes::U8(Array::new()),
+            InputTypes::uint16 => OutputTypes::U16(Array::new()),
+            InputTypes::uint32 => OutputTypes::U32(Array::new()),
+            InputTypes::uint64 => OutputTypes::U64(Array::new()),
+
+            InputTypes::float16 => OutputTypes::F16(Array::new()),
+            InputTypes::float32 => OutputTypes::F32(Array::new()),
+            InputTypes::float64 => OutputTypes::F64(Array::new()),
+
+            InputTypes::bool_ => OutputTypes::Bool(BoolArray::new()),
+
+            InputTypes::int => OutputTypes::PyList(Vec::new()),
+            InputTypes::float => OutputTypes::PyList(Vec::new()),
+            InputTypes::str => OutputTypes::PyList(Vec::new()),
+            InputTypes::bool => OutputTypes::PyList(Vec::new()),
+            InputTypes::category => OutputTypes::Categorical(Vec::new()),
+
+            InputTypes::list => OutputTypes::PyList(Vec::new()),
+            InputTypes::dict => OutputTypes::PyList(Vec::new()),
+            InputTypes::any => OutputTypes::PyList(Vec::new()),
         }
     }
+
+    /// Whether values of this type are parsed into a numpy array
+    fn is_array(&self) -> bool {
+        !matches!(
+            self,
+            InputTypes::int | InputTypes::float | InputTypes::str | InputTypes::bool | InputTypes::category
+                | InputTypes::list | InputTypes::dict | InputTypes::any
+        )
+    }
 }
 
 impl FromStr for InputTypes {
@@ -122,8 +177,9 @@ impl FromStr for InputTypes {
             "uint8" => Ok(InputTypes::uint8),
             "uint16" => Ok(InputTypes::uint16),
             "uint32" => Ok(InputTypes::uint32),
-            "uint64" => Ok(InputTypes::uint16),
+            "uint64" => Ok(InputTypes::uint64),
 
+            "float16" => Ok(InputTypes::float16),
             "float32" => Ok(InputTypes::float32),
             "float64" => Ok(InputTypes::float64),
 
@@ -133,6 +189,7 @@ impl FromStr for InputTypes {
             "float" => Ok(InputTypes::float),
             "str" => Ok(InputTypes::str),
             "bool" => Ok(InputTypes::bool),
+            "category" => Ok(InputTypes::category),
 
             "list" => Ok(InputTypes::list),
             "dict" => Ok(InputTypes::dict),
@@ -164,12 +221,59 @@ impl<'source> FromPyObject<'source> for InputTypes {
 #[serde(untagged)]
 pub enum Structure {
     ListofList(Vec<Vec<InputTypes>>),
-    ListofMap(Vec<HashMap<String, InputTypes>>),
+    ListofSequences(Vec<Vec<Vec<InputTypes>>>),
+    ListofMap(Vec<IndexMap<String, InputTypes>>),
     List(Vec<InputTypes>),
-    Map(HashMap<String, Structure>),
+    OneOf(OneOf),
+    Binary(Binary),
+    Reshape(Reshape),
+    KServe(KServe),
+    TfServing(TfServing),
+    #[serde(skip)]
+    Sequence(SequenceStructure),
+    #[serde(skip)]
+    Class(ClassStructure),
+    Map(MapStructure),
     Type(InputTypes),
 }
 
+impl Structure {
+    /// Whether the structure holds transposed records, which can be skipped or filled
+    fn holds_records(&self) -> bool {
+        match self {
+            Structure::ListofList(_) | Structure::ListofSequences(_) | Structure::ListofMap(_) => true,
+            Structure::OneOf(one_of) => one_of.alternatives.iter().any(Structure::holds_records),
+            Structure::Sequence(sequence) => sequence.0.holds_records(),
+            Structure::Class(class) => class.fields.holds_records(),
+            Structure::Map(map) => map.holds_records(),
+            _ => false,
+        }
+    }
+}
+
+/// `{"oneOf": [Structure, ...]}`: the value is parsed with the first alternative that matches it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OneOf {
+    #[serde(rename = "oneOf")]
+    pub alternatives: Vec<Structure>,
+}
+
+impl<'source> FromPyObject<'source> for OneOf {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let dict = object.downcast::<PyDict>()?;
+        match (dict.len(), dict.get_item("oneOf")) {
+            (1, Some(alternatives)) => Ok(OneOf {
+                alternatives: alternatives.extract()?,
+            }),
+            _ => Err(PyValueError::new_err(format!(
+                "cannot parse {} as oneOf structure",
+                object
+            ))),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum OutputTypes {
     I8(Array<i8>),
@@ -182,16 +286,23 @@ pub enum OutputTypes {
     U32(Array<u32>),
     U64(Array<u64>),
 
+    F16(Array<f16>),
     F32(Array<f32>),
     F64(Array<f64>),
 
     Bool(BoolArray),
+    Bytes(Bytes),
 
     PythonType(PythonType),
     PyList(Vec<PythonType>),
+    Categorical(Vec<PythonType>),
 
     List(Vec<OutputTypes>),
-    Map(HashMap<String, OutputTypes>),
+    Map(Fields),
+    Instance(PythonClass, Fields),
+    Records(Records),
+    /// An array parsed straight into its out array, see `out_types`
+    Written,
 }
 
 impl Display for OutputTypes {
@@ -207,13 +318,16 @@ impl Display for OutputTypes {
             Self::U32(_) => write!(f, "np.uint32"),
             Self::U64(_) => write!(f, "np.uint64"),
 
+            Self::F16(_) => write!(f, "np.float16"),
             Self::F32(_) => write!(f, "np.float32"),
             Self::F64(_) => write!(f, "np.float64"),
 
             Self::Bool(_) => write!(f, "np.bool_"),
+            Self::Bytes(_) => write!(f, "np.object_"),
 
             Self::PythonType(_) => write!(f, "Any"),
             Self::PyList(_) => write!(f, "List"),
+            Self::Categorical(_) => write!(f, "Categorical"),
 
             Self::List(vec) => write!(
                 f,
@@ -223,164 +337,471 @@ impl Display for OutputTypes {
                     + ", ")
             ),
             Self::Map(_) => write!(f, "Dict"),
+            Self::Instance(cls, _) => write!(f, "{}", cls),
+            Self::Records(_) => write!(f, "Records"),
+            Self::Written => write!(f, "out"),
         }
     }
 }
 
+/// Options controlling which python objects the deserialized values are returned as
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    pub records: RecordFormat,
+    pub arrays: ArrayFormat,
+    /// The classes referenced by the structure, see `extract_with_classes`
+    pub classes: Arc<Vec<Py<PyType>>>,
+    /// The keys of the structure's maps, see `intern_keys`
+    pub keys: Arc<Vec<Py<PyString>>>,
+}
+
 impl IntoPy<PyResult<PyObject>> for OutputTypes {
     fn into_py(self, py: Python) -> PyResult<PyObject> {
+        self.into_py_with(py, &OutputOptions::default())
+    }
+}
+
+impl OutputTypes {
+    pub fn into_py_with(self, py: Python, options: &OutputOptions) -> PyResult<PyObject> {
         match self {
-            OutputTypes::I8(v) => v.into_py(py),
-            OutputTypes::I16(v) => v.into_py(py),
-            OutputTypes::I32(v) => v.into_py(py),
-            OutputTypes::I64(v) => v.into_py(py),
+            OutputTypes::I8(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::I16(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::I32(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::I64(v) => v.into_py_with(py, options.arrays),
 
-            OutputTypes::U8(v) => v.into_py(py),
-            OutputTypes::U16(v) => v.into_py(py),
-            OutputTypes::U32(v) => v.into_py(py),
-            OutputTypes::U64(v) => v.into_py(py),
+            OutputTypes::U8(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::U16(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::U32(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::U64(v) => v.into_py_with(py, options.arrays),
 
-            OutputTypes::F32(v) => v.into_py(py),
-            OutputTypes::F64(v) => v.into_py(py),
+            OutputTypes::F16(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::F32(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::F64(v) => v.into_py_with(py, options.arrays),
 
-            OutputTypes::Bool(v) => v.into_py(py),
+            OutputTypes::Bool(v) => v.into_py_with(py, options.arrays),
+            OutputTypes::Bytes(v) => v.into_py_with(py, options.arrays),
 
             OutputTypes::PythonType(v) => Ok(v.into_py(py)),
             OutputTypes::PyList(v) => Ok(v.into_py(py)),
+            OutputTypes::Categorical(v) => Ok(v.into_py(py)),
 
             OutputTypes::List(v) => {
                 // consider using try_collect here instead of this:
                 let mut out = Vec::with_capacity(v.len());
                 for x in v {
-                    out.push(x.into_py(py)?)
+                    out.push(x.into_py_with(py, options)?)
                 }
                 Ok(out.into_py(py))
             }
+            OutputTypes::Map(v) => Ok(Self::fields_into_py(py, options, v)?.into()),
+            OutputTypes::Instance(cls, v) => cls.instantiate(py, &options.classes, Self::fields_into_py(py, options, v)?),
+            OutputTypes::Records(v) => v.into_py_with(py, options),
+            OutputTypes::Written => Err(PyValueError::new_err("The array was written into out")),
+        }
+    }
+}
+
+impl OutputTypes {
+    /// Like `into_py_with` but arrays which have a counterpart in `out` (a dictionary mirroring the structure
+    /// or a single array) are written into the preallocated numpy arrays, at `index` if given
+    pub fn into_py_out(self, py: Python, options: &OutputOptions, out: &PyAny, index: Option<usize>) -> PyResult<PyObject> {
+        match self {
+            OutputTypes::I8(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::I16(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::I32(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::I64(v) => v.write_into(out, index).map(|_| out.into()),
+
+            OutputTypes::U8(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::U16(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::U32(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::U64(v) => v.write_into(out, index).map(|_| out.into()),
+
+            OutputTypes::F16(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::F32(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::F64(v) => v.write_into(out, index).map(|_| out.into()),
+
+            OutputTypes::Bool(v) => v.write_into(out, index).map(|_| out.into()),
+            OutputTypes::Written => Ok(out.into()),
+
             OutputTypes::Map(v) => {
-                let mut out = HashMap::with_capacity(v.len());
-                for (k, x) in v {
-                    out.insert(k, x.into_py(py)?);
-                }
-                Ok(out.into_py(py))
+                let out_dict = out.downcast::<PyDict>()?;
+                Self::fields_into_py_out(py, options, v, out_dict, index, out_dict)?;
+                Ok(out.into())
             }
+            OutputTypes::Instance(cls, v) => {
+                let kwargs = PyDict::new(py);
+                Self::fields_into_py_out(py, options, v, out.downcast()?, index, kwargs)?;
+                cls.instantiate(py, &options.classes, kwargs)
+            }
+            other => Err(PyValueError::new_err(format!("Cannot write {} into out", other))),
         }
     }
+
+    /// Only arrays and nested structures are written into `out`, anything else is converted as usual
+    fn writes_into_out(&self) -> bool {
+        !matches!(
+            self,
+            OutputTypes::Bytes(_)
+                | OutputTypes::PythonType(_)
+                | OutputTypes::PyList(_)
+                | OutputTypes::Categorical(_)
+                | OutputTypes::List(_)
+                | OutputTypes::Records(_)
+        )
+    }
+
+    fn fields_into_py<'py>(py: Python<'py>, options: &OutputOptions, fields: Fields) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        for (k, x) in fields.into_py_items(py, &options.keys) {
+            dict.set_item(k, x.into_py_with(py, options)?)?;
+        }
+        Ok(dict)
+    }
+
+    /// Converts the fields into `result`, which may be `out` itself
+    fn fields_into_py_out(
+        py: Python,
+        options: &OutputOptions,
+        fields: Fields,
+        out: &PyDict,
+        index: Option<usize>,
+        result: &PyDict,
+    ) -> PyResult<()> {
+        for (k, x) in fields.into_py_items(py, &options.keys) {
+            let value = match out.get_item(&k) {
+                Some(field_out) if x.writes_into_out() => x.into_py_out(py, options, field_out, index)?,
+                _ => x.into_py_with(py, options)?,
+            };
+            result.set_item(k, value)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(transparent)]
 pub struct StructureDescriptor {
     pub data: Structure,
+    #[serde(skip)]
+    pub on_error: OnError,
+    #[serde(skip)]
+    pub limits: Limits,
+    #[serde(skip)]
+    pub ragged: Ragged,
 }
 
-impl<'de> DeserializeSeed<'de> for StructureDescriptor {
+impl StructureDescriptor {
+    /// Resets the per document state of the error handling and limits
+    fn start(&self) {
+        error_types::start(self.on_error);
+        limit_types::start(self.limits);
+        transpose_types::start(self.ragged);
+    }
+
+    /// Parses a json document with serde_json. Errors include the line and column
+    #[cfg(not(feature = "simd"))]
+    pub fn deserialize_json_slice(&self, json: &[u8]) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        self.deserialize(&mut serde_json::Deserializer::from_slice(json)).map_err(json_error)
+    }
+
+    /// Parses a json document read incrementally from `reader`, always with serde_json
+    pub fn deserialize_json_reader<R: io::Read>(&self, reader: R) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        self.deserialize(&mut serde_json::Deserializer::from_reader(reader)).map_err(json_error)
+    }
+
+    /// Parses newline delimited json as if the lines were the elements of a list, so a transposed structure
+    /// (`[[Type, ...]]` or `[{key: Type, ...}]`) turns each line into a record. Always parsed with serde_json
+    pub fn deserialize_jsonl_slice(&self, jsonl: &[u8]) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        let mut deserializer = serde_json::Deserializer::from_slice(jsonl);
+        StructureVisitor(&self.data, None).visit_seq(JsonLines(&mut deserializer)).map_err(json_error)
+    }
+
+    /// Parses newline delimited json read incrementally from `reader`, see `deserialize_jsonl_slice`
+    pub fn deserialize_jsonl_reader<R: io::Read>(&self, reader: R) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        StructureVisitor(&self.data, None).visit_seq(JsonLines(&mut deserializer)).map_err(json_error)
+    }
+
+    /// Parses a json document with simd-json. Syntax errors include the character offset.
+    ///
+    /// simd-json rewrites the document while it parses it, but `json` is borrowed from python or from a read only
+    /// memory map, so it is copied into a buffer of its own first: one `memcpy` of the document, next to the tape
+    /// simd-json builds anyway. The `_reader` and `jsonl` methods don't go through here and always use serde_json
+    #[cfg(feature = "simd")]
+    pub fn deserialize_json_slice(&self, json: &[u8]) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        let mut buffer = json.to_vec();
+        let result = simd_json::Deserializer::from_slice(&mut buffer)
+            .and_then(|mut deserializer| self.deserialize(&mut deserializer));
+        result.map_err(|err| Box::new(match err.error() {
+            // errors raised by the visitors don't know their offset, the path locates them instead
+            simd_json::ErrorType::Serde(message) => DeserializeError::take(ErrorKind::Structure, message.clone()),
+            error_type => DeserializeError {
+                offset: Some(err.index()),
+                ..DeserializeError::take(ErrorKind::Syntax, format!("{:?}", error_type))
+            },
+        }))
+    }
+
+    /// The transposed records streamed by `stream_json_reader`: the whole structure, or its top level `field`
+    pub fn streamed_records(&self, field: Option<&str>) -> Result<&Structure, String> {
+        let structure = match (&self.data, field) {
+            (structure, None) => Some(structure),
+            (Structure::Map(map), Some(field)) => map.fields.get(field),
+            (_, Some(_)) => None,
+        };
+        match structure {
+            Some(structure @ (Structure::ListofList(_) | Structure::ListofMap(_))) => Ok(structure),
+            _ => Err(match field {
+                Some(field) => format!("Field {:?} must have a transposed structure ([[type, ...]] or [{{key: type, ...}}]) to be streamed", field),
+                None => "The structure must be transposed ([[type, ...]] or [{key: type, ...}]) to be streamed. Use field to stream one of its fields".to_string(),
+            }),
+        }
+    }
+
+    /// Parses a json document read incrementally from `reader`. The records picked by `streamed_records` are handed
+    /// to `sink` every `chunk_size` records instead of being kept, the rest of the document is parsed and dropped
+    pub fn stream_json_reader<R: io::Read>(
+        &self,
+        reader: R,
+        field: Option<&str>,
+        chunk_size: usize,
+        sink: stream_types::Sink,
+    ) -> Result<(), Box<DeserializeError>> {
+        self.start();
+        self.streamed_records(field)
+            .map_err(|message| Box::new(DeserializeError::take(ErrorKind::Structure, message)))?;
+        stream_types::start(&self.data, field, chunk_size, sink);
+        let result = self.deserialize(&mut serde_json::Deserializer::from_reader(reader)).map_err(json_error);
+        stream_types::stop();
+        result.map(drop)
+    }
+
+    /// Parses a msgpack document. Errors include the byte offset
+    pub fn deserialize_msgpack_slice(&self, msgpack: &[u8]) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        let mut deserializer = rmp_serde::decode::Deserializer::new(msgpack);
+        self.deserialize(&mut deserializer)
+            .map_err(|err| msgpack_error(err, msgpack.len() - deserializer.get_ref().len()))
+    }
+
+    /// Parses a msgpack document read incrementally from `reader`
+    pub fn deserialize_msgpack_reader<R: io::Read>(&self, reader: R) -> Result<OutputTypes, Box<DeserializeError>> {
+        self.start();
+        let mut deserializer = rmp_serde::decode::Deserializer::new(CountingReader::new(reader));
+        self.deserialize(&mut deserializer)
+            .map_err(|err| msgpack_error(err, deserializer.get_ref().count))
+    }
+}
+
+/// Splits serde_json's message from the position it appends, which is kept separately instead
+fn json_error(err: serde_json::Error) -> Box<DeserializeError> {
+    use serde_json::error::Category;
+    let kind = match err.classify() {
+        Category::Data => ErrorKind::Structure,
+        Category::Io | Category::Syntax | Category::Eof => ErrorKind::Syntax,
+    };
+    let message = err.to_string();
+    let position = format!(" at line {} column {}", err.line(), err.column());
+    let message = message.strip_suffix(&position).map(str::to_string).unwrap_or(message);
+    // errors from reading the input have no position
+    let has_position = err.line() > 0;
+    Box::new(DeserializeError {
+        line: Some(err.line()).filter(|_| has_position),
+        column: Some(err.column()).filter(|_| has_position),
+        ..DeserializeError::take(kind, message)
+    })
+}
+
+fn msgpack_error(err: rmp_serde::decode::Error, offset: usize) -> Box<DeserializeError> {
+    let kind = match err {
+        rmp_serde::decode::Error::Syntax(_) => ErrorKind::Structure,
+        _ => ErrorKind::Syntax,
+    };
+    Box::new(DeserializeError { offset: Some(offset), ..DeserializeError::take(kind, err.to_string()) })
+}
+
+/// The values of a newline delimited json document as a sequence
+struct JsonLines<'a, R>(&'a mut serde_json::Deserializer<R>);
+
+impl<'a, 'de, R: serde_json::de::Read<'de>> SeqAccess<'de> for JsonLines<'a, R> {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        // `end` skips the whitespace before the next line and only succeeds when there are no lines left
+        if self.0.end().is_ok() {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.0).map(Some)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for &StructureDescriptor {
     type Value = OutputTypes;
 
     fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_any(StructureVisitor(self))
+        // the out arrays and streamed records of the document are looked up once, here
+        PlanSeed(&self.data, plan_types::take()).deserialize(deserializer)
     }
 }
 
-struct StructureVisitor(StructureDescriptor);
+/// Deserializes a value following a borrowed structure, so the structure is built once and never cloned while parsing
+pub struct StructureSeed<'s>(pub &'s Structure);
 
-impl<'de> Visitor<'de> for StructureVisitor {
+impl<'de, 's> DeserializeSeed<'de> for StructureSeed<'s> {
     type Value = OutputTypes;
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(formatter, "TODO")
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        PlanSeed(self.0, None).deserialize(deserializer)
     }
+}
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+/// Deserializes a value following a borrowed structure and its plan for this document, see `plan_types`
+pub struct PlanSeed<'s>(pub &'s Structure, pub Option<Plan>);
+
+impl<'de, 's> DeserializeSeed<'de> for PlanSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
     where
-        A: MapAccess<'de>,
+        D: Deserializer<'de>,
     {
-        let mut out = HashMap::new();
-        let structure = self.0.data;
-        match structure {
-            Structure::Map(structure_map) => {
-                let n_keys = structure_map.len();
-                let mut seen_keys = HashSet::with_capacity(n_keys);
-                while let Some(key) = map.next_key::<String>()? {
-                    let value = match structure_map.get(&key) {
-                        Some(Structure::Type(input_type)) => match input_type {
-                            InputTypes::int8 => OutputTypes::I8(map.next_value()?),
-                            InputTypes::int16 => OutputTypes::I16(map.next_value()?),
-                            InputTypes::int32 => OutputTypes::I32(map.next_value()?),
-                            InputTypes::int64 => OutputTypes::I64(map.next_value()?),
-
-                            InputTypes::uint8 => OutputTypes::U8(map.next_value()?),
-                            InputTypes::uint16 => OutputTypes::U16(map.next_value()?),
-                            InputTypes::uint32 => OutputTypes::U32(map.next_value()?),
-                            InputTypes::uint64 => OutputTypes::U64(map.next_value()?),
-
-                            InputTypes::float32 => OutputTypes::F32(map.next_value()?),
-                            InputTypes::float64 => OutputTypes::F64(map.next_value()?),
-
-                            InputTypes::bool_ => OutputTypes::Bool(map.next_value()?),
-
-                            InputTypes::int => {
-                                OutputTypes::PythonType(PythonType(Value::Number(map.next_value().map_err(|_| serde::de::Error::custom("Could not deserialize as int"))?)))
-                            }
-                            InputTypes::float => {
-                                OutputTypes::PythonType(PythonType(Value::Number(map.next_value().map_err(|_| serde::de::Error::custom("Could not deserialize as float"))?)))
-                            }
-                            InputTypes::bool => {
-                                OutputTypes::PythonType(PythonType(Value::Bool(map.next_value().map_err(|_| serde::de::Error::custom("Could not deserialize as bool"))?)))
-                            }
-                            InputTypes::str => {
-                                OutputTypes::PythonType(PythonType(Value::String(map.next_value().map_err(|_| serde::de::Error::custom("Could not deserialize as str"))?)))
-                            }
-                            _ => OutputTypes::PythonType(map.next_value()?),
-                        },
-                        Some(Structure::List(sub_structure_list)) => {
-                            let sub_structure = StructureDescriptor {
-                                data: Structure::List(sub_structure_list.clone()),
-                            };
-                            map.next_value_seed(sub_structure)?
-                        }
-                        Some(Structure::ListofList(sub_structure_lol)) => {
-                            let sub_structure = StructureDescriptor {
-                                data: Structure::ListofList(sub_structure_lol.clone()),
-                            };
-                            map.next_value_seed(sub_structure)?
-                        }
-                        Some(Structure::ListofMap(sub_structure_lom)) => {
-                            let sub_structure = StructureDescriptor {
-                                data: Structure::ListofMap(sub_structure_lom.clone()),
-                            };
-                            map.next_value_seed(sub_structure)?
-                        }
-                        Some(Structure::Map(sub_structure_map)) => {
-                            // TODO get rid of clone and pass as reference
-                            let sub_structure = StructureDescriptor {
-                                data: Structure::Map(sub_structure_map.clone()),
-                            };
-                            map.next_value_seed(sub_structure)?
-                        }
-                        None => {
-                            map.next_value::<serde::de::IgnoredAny>()?;
-                            continue;
-                        }
-                    };
+        let plan = match self.1 {
+            Some(Plan::Out(target)) => return target.deserialize(deserializer),
+            plan => plan,
+        };
+        let _depth = limit_types::enter()?;
+        match self.0 {
+            Structure::OneOf(one_of) => OneOfSeed(&one_of.alternatives).deserialize(deserializer),
+            Structure::Binary(binary) => BinarySeed(binary).deserialize(deserializer),
+            Structure::Reshape(reshape) => ReshapeSeed(reshape).deserialize(deserializer),
+            Structure::KServe(kserve) => KServeSeed(kserve).deserialize(deserializer),
+            Structure::TfServing(tf_serving) => TfServingSeed(tf_serving).deserialize(deserializer),
+            structure => deserializer.deserialize_any(StructureVisitor(structure, plan)),
+        }
+    }
+}
 
-                    seen_keys.insert(key.clone());
-                    out.insert(key, value);
-                }
-                if n_keys > seen_keys.len() {
-                    let not_seen_keys = structure_map
-                        .iter()
-                        .filter(|(k, _)| !seen_keys.contains(k.clone()))
-                        .map(|(k, _)| k.clone())
-                        .collect_vec();
-                    return Err(de::Error::custom(format!(
-                        "Key(s) not found: {not_seen_keys:?}"
-                    )));
-                }
-                Ok(OutputTypes::Map(out))
+pub struct TypeDescriptor(pub InputTypes);
+
+impl<'de> DeserializeSeed<'de> for TypeDescriptor {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let output_type = match self.0 {
+            InputTypes::int8 => OutputTypes::I8(Deserialize::deserialize(deserializer)?),
+            InputTypes::int16 => OutputTypes::I16(Deserialize::deserialize(deserializer)?),
+            InputTypes::int32 => OutputTypes::I32(Deserialize::deserialize(deserializer)?),
+            InputTypes::int64 => OutputTypes::I64(Deserialize::deserialize(deserializer)?),
+
+            InputTypes::uint8 => OutputTypes::U8(Deserialize::deserialize(deserializer)?),
+            InputTypes::uint16 => OutputTypes::U16(Deserialize::deserialize(deserializer)?),
+            InputTypes::uint32 => OutputTypes::U32(Deserialize::deserialize(deserializer)?),
+            InputTypes::uint64 => OutputTypes::U64(Deserialize::deserialize(deserializer)?),
+
+            InputTypes::float16 => OutputTypes::F16(Deserialize::deserialize(deserializer)?),
+            InputTypes::float32 => OutputTypes::F32(Deserialize::deserialize(deserializer)?),
+            InputTypes::float64 => OutputTypes::F64(Deserialize::deserialize(deserializer)?),
+
+            InputTypes::bool_ => OutputTypes::Bool(Deserialize::deserialize(deserializer)?),
+
+            InputTypes::int => {
+                OutputTypes::PythonType(PythonType(Value::Number(Deserialize::deserialize(deserializer).map_err(|err| error_types::cast_error("int", err))?)))
+            }
+            InputTypes::float => {
+                OutputTypes::PythonType(PythonType(Value::Number(Deserialize::deserialize(deserializer).map_err(|err| error_types::cast_error("float", err))?)))
+            }
+            InputTypes::bool => {
+                OutputTypes::PythonType(PythonType(Value::Bool(Deserialize::deserialize(deserializer).map_err(|err| error_types::cast_error("bool", err))?)))
+            }
+            InputTypes::str | InputTypes::category => {
+                let string: String = Deserialize::deserialize(deserializer).map_err(|err| error_types::cast_error("str", err))?;
+                limit_types::reserve_output_bytes(string.len())?;
+                OutputTypes::PythonType(PythonType(Value::String(string)))
             }
+            _ => OutputTypes::PythonType(Deserialize::deserialize(deserializer)?),
+        };
+        Ok(output_type)
+    }
+}
+
+/// The error for a transposed structure that doesn't give the types of its columns
+fn empty_records_error<E: de::Error>(expected: &str) -> E {
+    error_types::error(
+        ErrorKind::Structure,
+        Some(expected.to_string()),
+        Some("[]".to_string()),
+        format!("Transposed structure is empty, expected {}", expected),
+    )
+}
+
+struct StructureVisitor<'s>(&'s Structure, Option<Plan>);
+
+impl<'s> StructureVisitor<'s> {
+    fn visit_scalar<'de, D>(self, deserializer: D) -> Result<OutputTypes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match self.0 {
+            Structure::Type(input_type) => TypeDescriptor(*input_type).deserialize(deserializer),
+            Structure::OneOf(one_of) => OneOfSeed(&one_of.alternatives).deserialize(deserializer),
+            structure => Err(de::Error::custom(format!(
+                "Cannot deserialize scalar as structure: {:?}. Try using a type instead",
+                structure
+            ))),
+        }
+    }
+}
+
+macro_rules! make_scalar_visit {
+    ($dtype:ty, $name:ident) => {
+        fn $name<E: de::Error>(self, value: $dtype) -> Result<Self::Value, E> {
+            self.visit_scalar(value.into_deserializer())
+        }
+    };
+}
+
+impl<'de, 's> Visitor<'de> for StructureVisitor<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "value matching the structure: {:?}", self.0)
+    }
+
+    make_scalar_visit!(bool, visit_bool);
+    make_scalar_visit!(i64, visit_i64);
+    make_scalar_visit!(u64, visit_u64);
+    make_scalar_visit!(f64, visit_f64);
+    make_scalar_visit!(&str, visit_str);
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visit_scalar(().into_deserializer())
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        match self.0 {
+            Structure::Map(structure_map) => Ok(OutputTypes::Map(
+                MapSeed(structure_map, plan_types::fields(self.1)).deserialize(MapAccessDeserializer::new(map))?,
+            )),
             Structure::List(list) => Err(de::Error::custom(format!(
                 "Cannot deserialize map as sequence of arrays: {:?}. Try using a dictionary instead",
                 list
@@ -389,10 +810,32 @@ impl<'de> Visitor<'de> for StructureVisitor {
                 "Cannot deserialize map as transposed sequence of arrays: {:?}. Try using a dictionary instead",
                 lol
             ))),
+            Structure::ListofSequences(los) => Err(de::Error::custom(format!(
+                "Cannot deserialize map as transposed sequences of arrays: {:?}. Try using a dictionary instead",
+                los
+            ))),
             Structure::ListofMap(lom) => Err(de::Error::custom(format!(
                 "Cannot deserialize map as transposed sequence of maps: {:?}. Try using a dictionary instead",
                 lom
             ))),
+            Structure::OneOf(one_of) => {
+                OneOfSeed(&one_of.alternatives).deserialize(MapAccessDeserializer::new(map))
+            }
+            Structure::Sequence(sequence) => Err(de::Error::custom(format!(
+                "Cannot deserialize map as list: {:?}. Try using a class instead",
+                sequence.0
+            ))),
+            Structure::Class(class) => {
+                ClassSeed(class, plan_types::fields(self.1)).deserialize(MapAccessDeserializer::new(map))
+            }
+            Structure::Binary(binary) => BinarySeed(binary).deserialize(MapAccessDeserializer::new(map)),
+            Structure::Reshape(reshape) => ReshapeSeed(reshape).deserialize(MapAccessDeserializer::new(map)),
+            Structure::KServe(kserve) => KServeSeed(kserve).deserialize(MapAccessDeserializer::new(map)),
+            Structure::TfServing(tf_serving) => TfServingSeed(tf_serving).deserialize(MapAccessDeserializer::new(map)),
+            // a msgpack-numpy encoded array
+            Structure::Type(input_type) if input_type.is_array() => {
+                TypeDescriptor(*input_type).deserialize(MapAccessDeserializer::new(map))
+            }
             Structure::Type(t) => Err(de::Error::custom(format!(
                 "Cannot deserialize map as type: {:?}. Try using a dictionary instead",
                 t
@@ -405,28 +848,28 @@ impl<'de> Visitor<'de> for StructureVisitor {
         S: SeqAccess<'de>,
     {
         use serde::de::Error;
-        let structure = self.0.data;
-        match structure {
+        match self.0 {
             Structure::List(structure_list) => {
                 let mut out = Vec::<OutputTypes>::new();
                 for (i, input_type) in structure_list.iter().enumerate() {
                     let output_type = match input_type {
-                        InputTypes::int8 => seq.next_element()?.map(|arr| OutputTypes::I8(arr)),
-                        InputTypes::int16 => seq.next_element()?.map(|arr| OutputTypes::I16(arr)),
-                        InputTypes::int32 => seq.next_element()?.map(|arr| OutputTypes::I32(arr)),
-                        InputTypes::int64 => seq.next_element()?.map(|arr| OutputTypes::I64(arr)),
+                        InputTypes::int8 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::I8),
+                        InputTypes::int16 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::I16),
+                        InputTypes::int32 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::I32),
+                        InputTypes::int64 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::I64),
 
-                        InputTypes::uint8 => seq.next_element()?.map(|arr| OutputTypes::U8(arr)),
-                        InputTypes::uint16 => seq.next_element()?.map(|arr| OutputTypes::U16(arr)),
-                        InputTypes::uint32 => seq.next_element()?.map(|arr| OutputTypes::U32(arr)),
-                        InputTypes::uint64 => seq.next_element()?.map(|arr| OutputTypes::U64(arr)),
+                        InputTypes::uint8 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::U8),
+                        InputTypes::uint16 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::U16),
+                        InputTypes::uint32 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::U32),
+                        InputTypes::uint64 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::U64),
 
-                        InputTypes::float32 => seq.next_element()?.map(|arr| OutputTypes::F32(arr)),
-                        InputTypes::float64 => seq.next_element()?.map(|arr| OutputTypes::F64(arr)),
+                        InputTypes::float16 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::F16),
+                        InputTypes::float32 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::F32),
+                        InputTypes::float64 => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::F64),
 
-                        InputTypes::bool_ => seq.next_element()?.map(|arr| OutputTypes::Bool(arr)),
+                        InputTypes::bool_ => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::Bool),
 
-                        _ => seq.next_element()?.map(|arr| OutputTypes::PythonType(arr)),
+                        _ => seq.next_element().map_err(error_types::at_index(i))?.map(OutputTypes::PythonType),
                     };
                     match output_type {
                         Some(output_type) => out.push(output_type),
@@ -442,51 +885,47 @@ impl<'de> Visitor<'de> for StructureVisitor {
                         }
                     };
                 }
-                while let Some(_) = seq.next_element::<IgnoredAny>()? {
+                while seq.next_element::<IgnoredAny>()?.is_some() {
                     // empty any remaining items from the list with unspecified types
                 }
                 Ok(OutputTypes::List(out))
             }
             Structure::ListofList(structure_lol) => {
-                let mut out: Vec<OutputTypes> = structure_lol[0]
-                    .iter()
-                    .map(|input_type| -> OutputTypes { input_type.get_transpose_output_type() })
-                    .collect();
-                let mut transpose_vecs = TransposeSeq(&mut out);
-                loop {
-                    let next = seq.next_element_seed::<TransposeSeq>(transpose_vecs)?;
-                    match next {
-                        Some(next) => transpose_vecs = next,
-                        None => break,
-                    }
-                }
-                Ok(OutputTypes::List(out))
+                let types = structure_lol.first().ok_or_else(|| empty_records_error("[[type, ...]]"))?;
+                let columns = RecordColumns::new(RecordTypes::Seq(types));
+                transpose_types::transpose_records(seq, columns, plan_types::chunk_size(&self.1)).map(OutputTypes::Records)
+            }
+            Structure::ListofSequences(structure_los) => {
+                let types = structure_los
+                    .first()
+                    .and_then(|sequence| sequence.first())
+                    .ok_or_else(|| empty_records_error("[[[type, ...]]]"))?;
+                transpose_types::transpose_records(seq, SequenceColumns::new(types), None).map(OutputTypes::Records)
             }
             Structure::ListofMap(structure_lom) => {
-                let mut out: HashMap<String, OutputTypes> = structure_lom[0]
-                    .iter()
-                    .map(|(key, input_type)| -> (String, OutputTypes) {
-                        (key.clone(), input_type.get_transpose_output_type())
-                    })
-                    .collect();
-                let mut transpose_map = TransposeMap(&mut out);
-                loop {
-                    let next = seq.next_element_seed::<TransposeMap>(transpose_map)?;
-                    match next {
-                        Some(next) => transpose_map = next,
-                        None => break,
-                    }
-                }
-                Ok(OutputTypes::Map(out))
+                let types = structure_lom.first().ok_or_else(|| empty_records_error("[{key: type, ...}]"))?;
+                let columns = RecordColumns::new(RecordTypes::Map(types));
+                transpose_types::transpose_records(seq, columns, plan_types::chunk_size(&self.1)).map(OutputTypes::Records)
             }
             Structure::Map(map) => Err(de::Error::custom(format!(
                 "Cannot deserialize sequence as map of arrays: {:?}. Try using a list instead",
                 map
             ))),
-            Structure::Type(t) => Err(de::Error::custom(format!(
-                "Cannot deserialize sequence as type: {:?}. Try using a list instead",
-                t
+            Structure::OneOf(one_of) => {
+                OneOfSeed(&one_of.alternatives).deserialize(SeqAccessDeserializer::new(seq))
+            }
+            Structure::Sequence(sequence) => SequenceSeed(&sequence.0).visit_seq(seq),
+            Structure::Class(class) => Err(de::Error::custom(format!(
+                "Cannot deserialize sequence as class: {}. Try using a dictionary instead",
+                class.cls
             ))),
+            Structure::Binary(binary) => BinarySeed(binary).deserialize(SeqAccessDeserializer::new(seq)),
+            Structure::Reshape(reshape) => ReshapeSeed(reshape).deserialize(SeqAccessDeserializer::new(seq)),
+            Structure::KServe(kserve) => KServeSeed(kserve).deserialize(SeqAccessDeserializer::new(seq)),
+            Structure::TfServing(tf_serving) => TfServingSeed(tf_serving).deserialize(SeqAccessDeserializer::new(seq)),
+            Structure::Type(input_type) => {
+                TypeDescriptor(*input_type).deserialize(SeqAccessDeserializer::new(seq))
+            }
         }
     }
 }
@@ -496,6 +935,7 @@ mod tests {
 
     use super::*;
     use array_types::Base;
+    use itertools::Itertools;
     use serde_json::Value;
 
     #[test]
@@ -527,7 +967,7 @@ mod tests {
             .deserialize(&mut serde_json::Deserializer::from_str(json))
             .unwrap();
 
-        let expected = OutputTypes::Map(HashMap::from([
+        let expected = OutputTypes::Map(Fields::from_iter([
             (
                 "int".to_string(),
                 OutputTypes::I32(Array(Base::Scalar(5), None)),
@@ -586,14 +1026,14 @@ mod tests {
 
         let float_arr = OutputTypes::F32(Array(Base::Array(vec![6.7, 7.8]), Some(vec![2])));
 
-        let expected = OutputTypes::Map(HashMap::from([
+        let expected = OutputTypes::Map(Fields::from_iter([
             (
                 "int".to_string(),
-                OutputTypes::Map(HashMap::from([("int_arr2D".to_string(), int_arr)])),
+                OutputTypes::Map(Fields::from_iter([("int_arr2D".to_string(), int_arr)])),
             ),
             (
                 "float".to_string(),
-                OutputTypes::Map(HashMap::from([("float_arr2D".to_string(), float_arr)])),
+                OutputTypes::Map(Fields::from_iter([("float_arr2D".to_string(), float_arr)])),
             ),
         ]));
 
@@ -620,7 +1060,7 @@ mod tests {
             .deserialize(&mut serde_json::Deserializer::from_str(json))
             .unwrap();
 
-        let expected = OutputTypes::Map(HashMap::from([
+        let expected = OutputTypes::Map(Fields::from_iter([
             (
                 "arr1".to_string(),
                 OutputTypes::List(vec![
@@ -661,12 +1101,12 @@ mod tests {
             .deserialize(&mut serde_json::Deserializer::from_str(json))
             .unwrap();
 
-        let expected = OutputTypes::Map(HashMap::from([(
+        let expected = OutputTypes::Map(Fields::from_iter([(
             "arr1".to_string(),
-            OutputTypes::List(vec![
+            OutputTypes::Records(Records::Seq(vec![
                 OutputTypes::I32(Array(Base::Array(vec![1, 3, 5, 6]), Some(vec![4]))),
                 OutputTypes::F32(Array(Base::Array(vec![2.1, 4.3, 6.5, 7.8]), Some(vec![4]))),
-            ]),
+            ])),
         )]));
 
         assert_eq!(out, expected);
@@ -693,9 +1133,9 @@ mod tests {
             .deserialize(&mut serde_json::Deserializer::from_str(json))
             .unwrap();
 
-        let expected = OutputTypes::Map(HashMap::from([(
+        let expected = OutputTypes::Map(Fields::from_iter([(
             "arr1".to_string(),
-            OutputTypes::Map(HashMap::from([
+            OutputTypes::Records(Records::Map(IndexMap::from_iter([
                 (
                     "a".to_string(),
                     OutputTypes::I32(Array(Base::Array(vec![1, 3, 5, 6]), Some(vec![4]))),
@@ -704,9 +1144,1081 @@ mod tests {
                     "b".to_string(),
                     OutputTypes::F32(Array(Base::Array(vec![2.1, 4.3, 6.5, 7.8]), Some(vec![4]))),
                 ),
+            ]))),
+        )]));
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_one_of() {
+        let structure = r#"{
+        "arr1": {"oneOf": ["float32", {"values": "float32", "shape": "int64"}]}
+    }"#;
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(structure).unwrap();
+
+        let bare = r#"{"arr1": [1.5, 2.5]}"#;
+        let wrapped = r#"{"arr1": {"values": [1.5, 2.5], "shape": [2]}}"#;
+
+        let out = structure_descriptor
+            .clone()
+            .deserialize(&mut serde_json::Deserializer::from_str(bare))
+            .unwrap();
+
+        let expected = OutputTypes::Map(Fields::from_iter([(
+            "arr1".to_string(),
+            OutputTypes::F32(Array(Base::Array(vec![1.5, 2.5]), Some(vec![2]))),
+        )]));
+
+        assert_eq!(out, expected);
+
+        let out = structure_descriptor
+            .deserialize(&mut serde_json::Deserializer::from_str(wrapped))
+            .unwrap();
+
+        let expected = OutputTypes::Map(Fields::from_iter([(
+            "arr1".to_string(),
+            OutputTypes::Map(Fields::from_iter([
+                (
+                    "values".to_string(),
+                    OutputTypes::F32(Array(Base::Array(vec![1.5, 2.5]), Some(vec![2]))),
+                ),
+                (
+                    "shape".to_string(),
+                    OutputTypes::I64(Array(Base::Array(vec![2]), Some(vec![1]))),
+                ),
             ])),
         )]));
 
         assert_eq!(out, expected);
     }
+
+    #[test]
+    fn test_top_level_type() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#""float32""#).unwrap();
+
+        let out = structure_descriptor
+            .clone()
+            .deserialize(&mut serde_json::Deserializer::from_str("[[1, 2], [3, 4]]"))
+            .unwrap();
+
+        let expected = OutputTypes::F32(Array(Base::Array(vec![1.0, 2.0, 3.0, 4.0]), Some(vec![2, 2])));
+
+        assert_eq!(out, expected);
+
+        let out = structure_descriptor
+            .deserialize(&mut serde_json::Deserializer::from_str("-1.5"))
+            .unwrap();
+
+        assert_eq!(out, OutputTypes::F32(Array(Base::Scalar(-1.5), None)));
+    }
+
+    #[test]
+    fn test_key_order() {
+        let structure = r#"{
+        "c": "int32",
+        "a": {"z": "int32", "y": "int32"},
+        "b": [{"q": "int32", "p": "int32"}]
+    }"#;
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(structure).unwrap();
+
+        let json = r#"{
+        "a": {"y": 1, "z": 2},
+        "b": [{"p": 1, "q": 2}],
+        "c": 3
+    }"#;
+
+        let out = structure_descriptor
+            .deserialize(&mut serde_json::Deserializer::from_str(json))
+            .unwrap();
+
+        match out {
+            OutputTypes::Map(map) => {
+                assert_eq!(map.keys().collect_vec(), ["c", "a", "b"]);
+                match (&map["a"], &map["b"]) {
+                    (OutputTypes::Map(a), OutputTypes::Records(Records::Map(b))) => {
+                        assert_eq!(a.keys().collect_vec(), ["z", "y"]);
+                        assert_eq!(b.keys().collect_vec(), ["q", "p"]);
+                    }
+                    _ => panic!("expected nested maps"),
+                }
+            }
+            _ => panic!("expected map"),
+        }
+    }
+
+    #[test]
+    fn test_reuse_structure() {
+        let keys = (0..20).map(|i| format!("\"k{i}\": \"int32\"")).join(", ");
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(&format!("{{{keys}}}")).unwrap();
+
+        for offset in 0..2 {
+            let values = (0..20).rev().map(|i| format!("\"k{i}\": {}, \"ignored{i}\": [{i}]", i + offset)).join(", ");
+            let out = (&structure_descriptor)
+                .deserialize(&mut serde_json::Deserializer::from_str(&format!("{{{values}}}")))
+                .unwrap();
+
+            let expected = OutputTypes::Map(
+                (0..20)
+                    .map(|i| (format!("k{i}"), OutputTypes::I32(Array(Base::Scalar(i + offset), None))))
+                    .collect(),
+            );
+
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_json_backend() {
+        let structure = r#"{
+        "arr": "float64",
+        "ints": "uint64",
+        "name": "str",
+        "rows": [{"a": "int32", "b": "bool_"}],
+        "pairs": [["int8", "float32"]]
+    }"#;
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(structure).unwrap();
+
+        let json = br#"{
+        "name": "x\"y",
+        "arr": [[1.5e3, -2.25], [0.1, 7]],
+        "ints": [18446744073709551615, 0],
+        "rows": [{"b": true, "a": -4}, {"a": 5, "b": false}],
+        "pairs": [[1, 2.5], [-3, 4.5]],
+        "skipped": {"deep": [1, {"x": null}]}
+    }"#;
+
+        let out = structure_descriptor.deserialize_json_slice(json).unwrap();
+
+        let expected = OutputTypes::Map(Fields::from_iter([
+            (
+                "arr".to_string(),
+                OutputTypes::F64(Array(Base::Array(vec![1500.0, -2.25, 0.1, 7.0]), Some(vec![2, 2]))),
+            ),
+            (
+                "ints".to_string(),
+                OutputTypes::U64(Array(Base::Array(vec![u64::MAX, 0]), Some(vec![2]))),
+            ),
+            (
+                "name".to_string(),
+                OutputTypes::PythonType(PythonType(Value::String("x\"y".to_string()))),
+            ),
+            (
+                "rows".to_string(),
+                OutputTypes::Records(Records::Map(IndexMap::from_iter([
+                    ("a".to_string(), OutputTypes::I32(Array(Base::Array(vec![-4, 5]), Some(vec![2])))),
+                    ("b".to_string(), OutputTypes::Bool(BoolArray(Base::Array(vec![true, false]), Some(vec![2])))),
+                ]))),
+            ),
+            (
+                "pairs".to_string(),
+                OutputTypes::Records(Records::Seq(vec![
+                    OutputTypes::I8(Array(Base::Array(vec![1, -3]), Some(vec![2]))),
+                    OutputTypes::F32(Array(Base::Array(vec![2.5, 4.5]), Some(vec![2]))),
+                ])),
+            ),
+        ]));
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_error_path() {
+        let structure = r#"{
+        "records": [{"a": "int8", "b": "str"}],
+        "x": {"bbox": "uint8"}
+    }"#;
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(structure).unwrap();
+
+        let bad_record = r#"{"x": {"bbox": [1]}, "records": [{"a": 1, "b": "x"}, {"b": "y", "a": 300}]}"#;
+        let err = structure_descriptor.deserialize_json_slice(bad_record.as_bytes()).unwrap_err().to_string();
+        assert!(err.ends_with("(path: $.records[1].a)"), "{}", err);
+
+        let bad_bbox = r#"{"records": [], "x": {"bbox": [[1, 2], [3, -4]]}}"#;
+        let err = structure_descriptor.deserialize_json_slice(bad_bbox.as_bytes()).unwrap_err().to_string();
+        assert!(err.ends_with("(path: $.x.bbox[1][1])"), "{}", err);
+
+        let missing_key = r#"{"records": [{"a": 1, "b": "x"}, {"a": 2}], "x": {"bbox": 1}}"#;
+        let err = structure_descriptor.deserialize_json_slice(missing_key.as_bytes()).unwrap_err().to_string();
+        assert!(err.starts_with("Key(s) not found: [\"b\"]"), "{}", err);
+        assert!(err.ends_with("(path: $.records[1])"), "{}", err);
+
+        let msgpack = rmp_serde::to_vec(&serde_json::from_str::<Value>(bad_record).unwrap()).unwrap();
+        let err = structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap_err().to_string();
+        assert!(err.contains(&format!("at byte {}", msgpack.len())), "{}", err);
+        assert!(err.ends_with("(path: $.records[1].a)"), "{}", err);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"a": "int8", "b": "uint8", "c": "str"}"#).unwrap();
+
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": 1, "b": [1]}"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MissingKey);
+        assert_eq!(err.expected.as_deref(), Some("[\"c\"]"));
+
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": 1, "b": [1, 256], "c": "x"}"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Cast);
+        assert_eq!(err.expected.as_deref(), Some("u8"));
+        assert_eq!(err.found.as_deref(), Some("256"));
+        assert_eq!(err.path.to_string(), "$.b[1]");
+
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": "x", "b": [1], "c": "x"}"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Cast);
+        assert_eq!(err.found.as_deref(), Some("string \"x\""));
+
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": 1, "b": {"d": 1}, "c": "x"}"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Structure, "{}", err);
+
+        let err = structure_descriptor.deserialize_json_slice(b"{\"a\": 1,\n \"b\": [1,]}").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Syntax);
+        #[cfg(not(feature = "simd"))]
+        assert_eq!((err.line, err.column), (Some(2), Some(10)));
+    }
+
+    #[test]
+    fn test_on_error() {
+        let mut structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"[{"a": "uint8", "b": "bool_"}]"#).unwrap();
+        let jsonl = b"{\"a\": 1, \"b\": true}\n{\"b\": false, \"a\": 300}\n{\"a\": 3}\n\n{\"a\": 4, \"b\": false}\n";
+
+        let err = structure_descriptor.deserialize_jsonl_slice(jsonl).unwrap_err();
+        assert_eq!(err.to_string(), "invalid value: integer `300`, expected u8 at line 2 column 21 (path: $[1].a)");
+
+        structure_descriptor.on_error = OnError::Skip;
+        let out = structure_descriptor.deserialize_jsonl_slice(jsonl).unwrap();
+        let expected = OutputTypes::Records(Records::Map(IndexMap::from_iter([
+            ("a".to_string(), OutputTypes::U8(Array(Base::Array(vec![1, 4]), Some(vec![2])))),
+            ("b".to_string(), OutputTypes::Bool(BoolArray(Base::Array(vec![true, false]), Some(vec![2])))),
+        ])));
+        assert_eq!(out, expected);
+        let errors = error_types::take_record_errors();
+        assert_eq!(errors.iter().map(|err| err.index).collect_vec(), vec![1, 2]);
+        assert_eq!(errors[0].error.path.to_string(), "$[1].a");
+        assert_eq!(errors[1].error.kind, ErrorKind::MissingKey);
+
+        structure_descriptor.on_error = OnError::Fill;
+        let json = format!("[{}]", std::str::from_utf8(jsonl).unwrap().trim().replace("\n\n", "\n").replace('\n', ","));
+        let out = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap();
+        let expected = OutputTypes::Records(Records::Map(IndexMap::from_iter([
+            ("a".to_string(), OutputTypes::U8(Array(Base::Array(vec![1, 0, 0, 4]), Some(vec![4])))),
+            (
+                "b".to_string(),
+                OutputTypes::Bool(BoolArray(Base::Array(vec![true, false, false, false]), Some(vec![4]))),
+            ),
+        ])));
+        assert_eq!(out, expected);
+        assert_eq!(error_types::take_record_errors().len(), 2);
+
+        let structure_descriptor = StructureDescriptor {
+            data: serde_json::from_str(r#"[["float32", "str"]]"#).unwrap(),
+            on_error: OnError::Fill,
+            limits: Limits::default(),
+            ragged: Ragged::Raise,
+        };
+        let msgpack = rmp_serde::to_vec(&serde_json::json!([[1.5, "x"], ["y", "z"]])).unwrap();
+        match structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => match &columns[..] {
+                [OutputTypes::F32(Array(Base::Array(floats), _)), OutputTypes::PyList(strings)] => {
+                    assert_eq!(floats[0], 1.5);
+                    assert!(floats[1].is_nan());
+                    assert_eq!(strings, &vec![PythonType(Value::from("x")), PythonType(Value::Null)]);
+                }
+                other => panic!("unexpected columns {:?}", other),
+            },
+            other => panic!("unexpected output {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"x": {"arr": "float64"}, "rows": [["int32", "bool_"]]}"#).unwrap();
+        let json = br#"{"x": {"arr": [[1, 2, 3], [4, 5, 6]]}, "rows": [[1, true], [2, false], [3, true]]}"#;
+
+        structure_descriptor.limits = Limits { max_depth: Some(4), max_array_elements: Some(6), max_output_bytes: Some(63), ..Default::default() };
+        structure_descriptor.deserialize_json_slice(json).unwrap();
+
+        structure_descriptor.limits = Limits { max_depth: Some(3), ..Default::default() };
+        let err = structure_descriptor.deserialize_json_slice(json).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Limit);
+        assert_eq!(err.message, "Document is nested too deeply (limit max_depth = 3)");
+        assert_eq!(err.path.to_string(), "$.x.arr[0]");
+
+        structure_descriptor.limits = Limits { max_array_elements: Some(5), ..Default::default() };
+        let err = structure_descriptor.deserialize_json_slice(json).unwrap_err();
+        assert_eq!(err.path.to_string(), "$.x.arr[1][2]");
+        assert!(err.message.starts_with("Array has more than 5 elements"), "{}", err);
+
+        structure_descriptor.limits = Limits { max_array_elements: Some(2), ..Default::default() };
+        let err = structure_descriptor.deserialize_json_slice(br#"{"x": {"arr": 1}, "rows": [[1, true], [2, false], [3, true]]}"#).unwrap_err();
+        assert_eq!(err.path.to_string(), "$.rows[2]");
+
+        // after the 48 bytes of floats there is only room for 2 of the 3 rows of 5 bytes
+        structure_descriptor.limits = Limits { max_output_bytes: Some(62), ..Default::default() };
+        let err = structure_descriptor.deserialize_json_slice(json).unwrap_err();
+        assert_eq!(err.message, "Output is larger than 62 bytes (limit max_output_bytes = 62)");
+        assert_eq!(err.path.to_string(), "$.rows[2]");
+
+        // records going over a limit aren't skipped, nor is a oneOf value
+        for (structure, json, path) in [
+            (r#"[{"v": "float32"}]"#, r#"[{"v": [1]}, {"v": [1, 2, 3, 4, 5, 6, 7, 8, 9]}]"#, "$[1].v"),
+            (r#"[{"v": "float32"}]"#, r#"[{"v": [1]}, {"v": [[1, 2], [3, 4], [5, 6]]}]"#, "$[1].v[2][0]"),
+            (r#"{"v": {"oneOf": ["float32", "str"]}}"#, r#"{"v": [[1, 2], [3, 4], [5, 6]]}"#, "$.v[2][0]"),
+        ] {
+            let structure_descriptor = StructureDescriptor {
+                on_error: OnError::Skip,
+                limits: Limits { max_array_elements: Some(4), ..Default::default() },
+                ..serde_json::from_str(structure).unwrap()
+            };
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (ErrorKind::Limit, path.to_string()), "{}", err);
+        }
+
+        // python values count towards the limits too
+        let long = format!(r#""{}""#, "x".repeat(1000));
+        let many = format!("[{}]", vec!["1"; 100].join(", "));
+        for (structure, json, path) in [
+            (r#"{"s": "str"}"#, format!(r#"{{"s": {}}}"#, long), "$.s"),
+            (r#"[{"s": "str"}]"#, format!(r#"[{{"s": "x"}}, {{"s": {}}}]"#, long), "$[1].s"),
+            (r#"{"a": "any"}"#, format!(r#"{{"a": {}}}"#, many), "$.a"),
+            (r#"[["int32", "list"]]"#, format!(r#"[[1, {}]]"#, many), "$[0][1]"),
+        ] {
+            let structure_descriptor = StructureDescriptor {
+                limits: Limits { max_array_elements: Some(10), max_output_bytes: Some(100), ..Default::default() },
+                ..serde_json::from_str(structure).unwrap()
+            };
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (ErrorKind::Limit, path.to_string()), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_no_panics() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"{"x": "float64", "b": "bool_"}"#).unwrap();
+        for json in [r#"{"x": [], "b": true}"#, r#"{"x": 1.0, "b": []}"#, r#"{"x": [[]], "b": [[], []]}"#] {
+            let result = std::panic::catch_unwind(|| structure_descriptor.deserialize_json_slice(json.as_bytes()));
+            assert!(result.is_ok(), "panicked on {}", json);
+        }
+
+        for (structure, json) in [("[]", r#"[[1, 2]]"#), (r#"{"rows": []}"#, r#"{"rows": [[1, 2]]}"#)] {
+            let structure_descriptor: StructureDescriptor = serde_json::from_str(structure).unwrap();
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Structure, "{}", err);
+            assert_eq!(err.message, "Transposed structure is empty, expected [[type, ...]]");
+        }
+
+        let mut column = Array::<f64>::new();
+        column.push::<serde_json::Error>(1.0).unwrap();
+        let mut array = Array(Base::Array(vec![1.0, 2.0]), Some(vec![1, 2]));
+        assert!(array.push::<serde_json::Error>(3.0).is_err());
+        assert!(Array(Base::Scalar(1.0), None).push::<serde_json::Error>(3.0).is_err());
+    }
+
+    #[test]
+    fn test_stream() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"meta": "str", "rows": [{"a": "int32", "b": "bool_"}]}"#).unwrap();
+        let json = br#"{"rows": [{"a": 1, "b": true}, {"a": 2, "b": false}, {"a": 3, "b": true}], "meta": "x"}"#;
+        let chunks = Rc::new(RefCell::new(Vec::new()));
+        let sink_chunks = chunks.clone();
+        let sink: stream_types::Sink = Box::new(move |chunk| {
+            sink_chunks.borrow_mut().push(chunk.records);
+            true
+        });
+        structure_descriptor.stream_json_reader(&json[..], Some("rows"), 2, sink).unwrap();
+        let chunks = chunks.take();
+        assert_eq!(chunks.len(), 2);
+        match &chunks[1] {
+            OutputTypes::Records(Records::Map(columns)) => {
+                assert_eq!(columns["a"], OutputTypes::I32(Array(Base::Array(vec![3]), Some(vec![1]))));
+            }
+            other => panic!("unexpected chunk {:?}", other),
+        }
+        assert!(plan_types::take().is_none());
+
+        // parsing stops with an error once the sink doesn't take any more chunks
+        let err = structure_descriptor.stream_json_reader(&json[..], Some("rows"), 1, Box::new(|_| false)).unwrap_err();
+        assert_eq!(err.path.to_string(), "$.rows[0]");
+
+        assert!(structure_descriptor.streamed_records(None).is_err());
+        assert!(structure_descriptor.streamed_records(Some("meta")).is_err());
+    }
+
+    #[test]
+    fn test_readers() {
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"arr": "float64", "rows": [{"a": "int32"}]}"#).unwrap();
+        let json = br#"{"arr": [[1, 2], [3, 4]], "rows": [{"a": 1}, {"a": 2}]}"#;
+        let msgpack = rmp_serde::to_vec(&serde_json::from_slice::<Value>(json).unwrap()).unwrap();
+        let expected = structure_descriptor.deserialize_json_slice(json).unwrap();
+        assert_eq!(structure_descriptor.deserialize_json_reader(&json[..]).unwrap(), expected);
+        assert_eq!(structure_descriptor.deserialize_msgpack_reader(&msgpack[..]).unwrap(), expected);
+
+        let err = structure_descriptor.deserialize_msgpack_reader(&msgpack[..msgpack.len() - 1]).unwrap_err();
+        assert_eq!(err.offset, structure_descriptor.deserialize_msgpack_slice(&msgpack[..msgpack.len() - 1]).unwrap_err().offset);
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"[{"a": "int32"}]"#).unwrap();
+        let jsonl = b"{\"a\": 1}\n{\"a\": 2}\n";
+        assert_eq!(
+            structure_descriptor.deserialize_jsonl_reader(&jsonl[..]).unwrap(),
+            structure_descriptor.deserialize_jsonl_slice(jsonl).unwrap()
+        );
+    }
+
+    /// `{"a": [0, 1, 4, 9, ...]}` with the squares of 0 to 299 modulo 97, compressed by the zstd and lz4 tools
+    const ZSTD_DOCUMENT: &[u8] = &[
+        0x28, 0xb5, 0x2f, 0xfd, 0x64, 0x86, 0x03, 0xc5, 0x05, 0x00, 0xc2, 0x4e, 0x1e, 0x13, 0xb0, 0x57,
+        0x5a, 0x0c, 0x48, 0x81, 0x96, 0x06, 0xb8, 0x95, 0x44, 0x84, 0x56, 0xb6, 0xf0, 0x6b, 0x53, 0x02,
+        0x11, 0x07, 0x04, 0x2e, 0x5e, 0x7e, 0x6e, 0x42, 0x65, 0x24, 0x6a, 0x8c, 0x68, 0xc4, 0xa4, 0x6e,
+        0x88, 0xa9, 0x70, 0x26, 0x56, 0xe2, 0x16, 0x06, 0x09, 0x3f, 0xe1, 0x61, 0x38, 0x9d, 0x9b, 0x0f,
+        0xf1, 0x21, 0x1f, 0xe5, 0x66, 0x6e, 0xc3, 0x45, 0x3c, 0x90, 0x8b, 0xf9, 0x0d, 0x9f, 0xf1, 0x0a,
+        0x3e, 0x86, 0x8b, 0xf1, 0x60, 0x3e, 0x3c, 0x20, 0x78, 0x9d, 0x53, 0xb9, 0x9c, 0x57, 0xb9, 0x9d,
+        0x53, 0xf0, 0x11, 0x0f, 0x10, 0x4e, 0xe4, 0xe1, 0x7c, 0xcc, 0x85, 0x7c, 0x7c, 0x84, 0x8b, 0xf2,
+        0x1a, 0x27, 0x97, 0xe1, 0x65, 0x7e, 0xe5, 0x46, 0x1e, 0xc6, 0x4d, 0xfc, 0xbc, 0x5c, 0x3c, 0x60,
+        0x40, 0x01, 0x80, 0x02, 0x00, 0x83, 0x19, 0xa8, 0x20, 0x26, 0x87, 0x7d, 0xf6, 0x3b, 0x40, 0x48,
+        0x24, 0x54, 0xfb, 0x10, 0x28, 0x01, 0xf5, 0xfe, 0xff, 0xff, 0x67, 0x05, 0xf1, 0xc8, 0x15, 0x3d,
+        0xca, 0x4d, 0xa6, 0x64, 0x46, 0x5d, 0xa8, 0x7b, 0xaf, 0x63, 0xcb, 0x96, 0xbf, 0xd3, 0x2a, 0x76,
+        0xae, 0xe1, 0xfa, 0x46, 0x58, 0x9a, 0xca, 0x6a, 0xb0, 0xa3, 0x4f, 0xa0, 0x9d, 0xca, 0xcc, 0xbc,
+        0xa0, 0xa4, 0x09, 0x6b, 0xda, 0xed,
+    ];
+    const LZ4_DOCUMENT: &[u8] = &[
+        0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x33, 0x01, 0x00, 0x00, 0xf0, 0x37, 0x7b, 0x22, 0x61,
+        0x22, 0x3a, 0x20, 0x5b, 0x30, 0x2c, 0x20, 0x31, 0x2c, 0x20, 0x34, 0x2c, 0x20, 0x39, 0x2c, 0x20,
+        0x31, 0x36, 0x2c, 0x20, 0x32, 0x35, 0x2c, 0x20, 0x33, 0x36, 0x2c, 0x20, 0x34, 0x39, 0x2c, 0x20,
+        0x36, 0x34, 0x2c, 0x20, 0x38, 0x31, 0x2c, 0x20, 0x33, 0x2c, 0x20, 0x32, 0x34, 0x2c, 0x20, 0x34,
+        0x37, 0x2c, 0x20, 0x37, 0x32, 0x2c, 0x20, 0x32, 0x2c, 0x20, 0x33, 0x31, 0x2c, 0x20, 0x36, 0x32,
+        0x2c, 0x20, 0x39, 0x2e, 0x00, 0x40, 0x33, 0x2c, 0x20, 0x37, 0x47, 0x00, 0x80, 0x32, 0x2c, 0x20,
+        0x35, 0x33, 0x2c, 0x20, 0x39, 0x3e, 0x00, 0x00, 0x51, 0x00, 0x00, 0x58, 0x00, 0x00, 0x10, 0x00,
+        0xf0, 0x10, 0x34, 0x2c, 0x20, 0x35, 0x30, 0x2c, 0x20, 0x38, 0x2c, 0x20, 0x36, 0x35, 0x2c, 0x20,
+        0x32, 0x37, 0x2c, 0x20, 0x38, 0x38, 0x2c, 0x20, 0x35, 0x34, 0x2c, 0x20, 0x32, 0x32, 0x2c, 0x20,
+        0x38, 0x69, 0x00, 0x00, 0x65, 0x00, 0xc0, 0x35, 0x2c, 0x20, 0x31, 0x31, 0x2c, 0x20, 0x38, 0x36,
+        0x2c, 0x20, 0x36, 0x43, 0x00, 0x80, 0x38, 0x2c, 0x20, 0x33, 0x32, 0x2c, 0x20, 0x31, 0x38, 0x00,
+        0xf0, 0x00, 0x2c, 0x20, 0x39, 0x33, 0x2c, 0x20, 0x38, 0x35, 0x2c, 0x20, 0x37, 0x39, 0x2c, 0x20,
+        0x37, 0x08, 0x00, 0x13, 0x33, 0x04, 0x00, 0x03, 0x14, 0x00, 0x22, 0x38, 0x35, 0x24, 0x00, 0x12,
+        0x36, 0x32, 0x00, 0x00, 0x3a, 0x00, 0x22, 0x34, 0x38, 0x4a, 0x00, 0x00, 0x52, 0x00, 0x13, 0x31,
+        0x62, 0x00, 0x22, 0x36, 0x31, 0x72, 0x00, 0x22, 0x32, 0x32, 0x82, 0x00, 0x22, 0x38, 0x38, 0x92,
+        0x00, 0x00, 0x9a, 0x00, 0x12, 0x38, 0xa8, 0x00, 0x23, 0x39, 0x34, 0xb8, 0x00, 0x13, 0x31, 0xc8,
+        0x00, 0x12, 0x36, 0xd8, 0x00, 0x22, 0x31, 0x32, 0xe8, 0x00, 0x22, 0x33, 0x33, 0xf8, 0x00, 0x13,
+        0x36, 0x08, 0x01, 0x12, 0x32, 0x16, 0x01, 0x22, 0x34, 0x37, 0x26, 0x01, 0x12, 0x33, 0x34, 0x01,
+        0x22, 0x36, 0x34, 0x44, 0x01, 0x13, 0x33, 0x54, 0x01, 0x21, 0x31, 0x36, 0x63, 0x01, 0x11, 0x34,
+        0x6f, 0x01, 0x0f, 0x75, 0x01, 0xff, 0xff, 0xf4, 0x50, 0x20, 0x36, 0x34, 0x5d, 0x7d, 0x00, 0x00,
+        0x00, 0x00, 0xbe, 0xb8, 0x27, 0xce,
+    ];
+
+    #[test]
+    fn test_decompress() {
+        use crate::compression::{Compression, CompressionError, Decoder};
+
+        let decompress = |compression: Compression, data: &[u8], max_size| {
+            let decoder = match Decoder::new(compression.resolve(data)?, data, max_size) {
+                Some(decoder) => decoder,
+                None => return Ok(None),
+            };
+            let mut decompressed = Vec::new();
+            decoder.parse(|reader| reader.read_to_end(&mut decompressed))?.ok();
+            Ok::<_, CompressionError>(Some(decompressed))
+        };
+        let squares = (0..300).map(|i| (i * i % 97).to_string()).collect::<Vec<_>>();
+        let json = format!("{{\"a\": [{}]}}", squares.join(", "));
+        for (compressed, compression) in [(ZSTD_DOCUMENT, Compression::Zstd), (LZ4_DOCUMENT, Compression::Lz4)] {
+            assert_eq!(Compression::detect(compressed), compression);
+            assert_eq!(decompress(Compression::Auto, compressed, None).unwrap(), Some(json.clone().into_bytes()));
+            let frames = [compressed, compressed].concat();
+            assert_eq!(decompress(compression, &frames, None).unwrap(), Some(json.repeat(2).into_bytes()));
+            let skippable: &[u8] = &[0x50, 0x2a, 0x4d, 0x18, 0x03, 0x00, 0x00, 0x00, 1, 2, 3];
+            let frames = [skippable, compressed, skippable].concat();
+            assert_eq!(decompress(compression, &frames, None).unwrap(), Some(json.clone().into_bytes()));
+            assert!(matches!(decompress(compression, compressed, Some(1000)), Err(CompressionError::Limit(_))));
+
+            for end in 0..compressed.len() {
+                assert!(decompress(compression, &compressed[..end], None).is_err(), "{:?} truncated at {}", compression, end);
+            }
+            for i in 0..compressed.len() {
+                let mut corrupt = compressed.to_vec();
+                corrupt[i] ^= 0x55;
+                assert!(decompress(compression, &corrupt, None).is_err(), "{:?} corrupt byte {}", compression, i);
+            }
+        }
+
+        // a frame of one block repeating a byte
+        let rle = [0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x05, 0x2b, 0x00, 0x00, b'x'];
+        assert_eq!(decompress(Compression::Auto, &rle, None).unwrap(), Some(b"xxxxx".to_vec()));
+
+        assert_eq!(decompress(Compression::Auto, json.as_bytes(), None).unwrap(), None);
+        assert_eq!(decompress(Compression::None, ZSTD_DOCUMENT, None).unwrap(), None);
+        assert!(matches!(decompress(Compression::Gzip, json.as_bytes(), None), Err(CompressionError::Decode(_))));
+        assert!(matches!(decompress(Compression::Lz4, ZSTD_DOCUMENT, None), Err(CompressionError::Decode(_))));
+    }
+
+    #[test]
+    fn test_msgpack_numpy() {
+        use serde::Serialize;
+
+        struct Bytes<'a>(&'a [u8]);
+
+        impl Serialize for Bytes<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_bytes(self.0)
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Encoded<'a> {
+            nd: bool,
+            #[serde(rename = "type")]
+            dtype: &'a str,
+            kind: &'a str,
+            shape: Vec<usize>,
+            data: Bytes<'a>,
+        }
+
+        let encode = |dtype, shape, data: &[u8]| {
+            let encoded = Encoded { nd: true, dtype, kind: "", shape, data: Bytes(data) };
+            rmp_serde::to_vec_named(&encoded).unwrap()
+        };
+        let floats = [1.5f32, -2.0, 3.25, 0.0, 8.0, 1e6].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        let big_endian = [1i32, -2].iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<_>>();
+        // {b"nd": False, b"type": "<i2", b"kind": b"", b"data": b"\x05\x00"}, a numpy scalar packed with bytes keys
+        let scalar: &[u8] = &[
+            0x84, 0xc4, 0x02, b'n', b'd', 0xc2, 0xc4, 0x04, b't', b'y', b'p', b'e', 0xa3, b'<', b'i', b'2', 0xc4,
+            0x04, b'k', b'i', b'n', b'd', 0xc4, 0x00, 0xc4, 0x04, b'd', b'a', b't', b'a', 0xc4, 0x02, 0x05, 0x00,
+        ];
+        let mut msgpack = vec![0x85];
+        for (key, value) in [
+            ("arr", encode("<f4", vec![2, 3], &floats)),
+            ("ints", encode(">i4", vec![2], &big_endian)),
+            ("n", scalar.to_vec()),
+            ("b", encode("|b1", vec![3], &[1, 0, 1])),
+            ("same", encode("<f4", vec![6], &floats)),
+        ] {
+            msgpack.extend(rmp_serde::to_vec(key).unwrap());
+            msgpack.extend(value);
+        }
+
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"arr": "float64", "ints": "int8", "n": "int64", "b": "bool_", "same": "float32"}"#).unwrap();
+        match structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap() {
+            OutputTypes::Map(map) => {
+                assert_eq!(
+                    map["arr"],
+                    OutputTypes::F64(Array(Base::Array(vec![1.5, -2.0, 3.25, 0.0, 8.0, 1e6]), Some(vec![2, 3])))
+                );
+                assert_eq!(map["ints"], OutputTypes::I8(Array(Base::Array(vec![1, -2]), Some(vec![2]))));
+                assert_eq!(map["n"], OutputTypes::I64(Array(Base::Scalar(5), None)));
+                assert_eq!(map["b"], OutputTypes::Bool(BoolArray(Base::Array(vec![true, false, true]), Some(vec![3]))));
+                assert_eq!(
+                    map["same"],
+                    OutputTypes::F32(Array(Base::Array(vec![1.5, -2.0, 3.25, 0.0, 8.0, 1e6]), Some(vec![6])))
+                );
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+
+        // encoded arrays can also be the rows of a list
+        let mut rows = vec![0x92];
+        rows.extend(encode("<u1", vec![2], &[1, 2]));
+        rows.extend(encode("<u1", vec![2], &[3, 4]));
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#""uint16""#).unwrap();
+        assert_eq!(
+            structure_descriptor.deserialize_msgpack_slice(&rows).unwrap(),
+            OutputTypes::U16(Array(Base::Array(vec![1, 2, 3, 4]), Some(vec![2, 2])))
+        );
+        // and fields of records which are buffered to be skipped
+        let mut records = vec![0x92];
+        for (id, data) in [(rmp_serde::to_vec(&1).unwrap(), [1, 2]), (rmp_serde::to_vec("x").unwrap(), [3, 4])] {
+            records.extend([0x82, 0xa2, b'i', b'd']);
+            records.extend(id);
+            records.extend([0xa1, b'v']);
+            records.extend(encode("<u1", vec![2], &data));
+        }
+        let structure_descriptor = StructureDescriptor {
+            on_error: OnError::Skip,
+            ..serde_json::from_str(r#"[{"id": "int32", "v": "uint8"}]"#).unwrap()
+        };
+        match structure_descriptor.deserialize_msgpack_slice(&records).unwrap() {
+            OutputTypes::Records(Records::Map(columns)) => {
+                assert_eq!(columns["v"], OutputTypes::U8(Array(Base::Array(vec![1, 2]), Some(vec![1, 2]))))
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(error_types::take_record_errors().len(), 1);
+        // floats without a fractional part are cast to integers
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#""int32""#).unwrap();
+        assert_eq!(
+            structure_descriptor.deserialize_msgpack_slice(&encode("<f4", vec![2], &floats[12..20])).unwrap(),
+            OutputTypes::I32(Array(Base::Array(vec![0, 8]), Some(vec![2])))
+        );
+
+        for (structure, encoded, kind, message) in [
+            ("int8", encode("<f4", vec![6], &floats), ErrorKind::Cast, "Could not cast 1.5 (f32) into: \"i8\""),
+            ("float32", encode("<f4", vec![4], &floats), ErrorKind::Shape, "msgpack-numpy array of shape [4] and dtype f32 has 24 bytes of data"),
+            ("float32", encode("<c8", vec![3], &floats), ErrorKind::Cast, "Unsupported msgpack-numpy dtype \"<c8\""),
+            ("bool_", encode("<u1", vec![2], &[0, 1]), ErrorKind::Cast, "Could not cast u8 array into: \"bool\""),
+        ] {
+            let structure_descriptor = StructureDescriptor {
+                data: serde_json::from_str(&format!(r#"{{"x": "{}"}}"#, structure)).unwrap(),
+                on_error: OnError::Raise,
+                limits: Limits::default(),
+                ragged: Ragged::Raise,
+            };
+            let mut msgpack = vec![0x81];
+            msgpack.extend(rmp_serde::to_vec("x").unwrap());
+            msgpack.extend(encoded);
+            let err = structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap_err();
+            assert_eq!((err.kind, err.message.as_str()), (kind, message));
+            assert_eq!(err.path.to_string(), "$.x");
+        }
+
+        let structure_descriptor = StructureDescriptor {
+            data: serde_json::from_str(r#""float32""#).unwrap(),
+            on_error: OnError::Raise,
+            limits: Limits { max_array_elements: Some(5), ..Default::default() },
+            ragged: Ragged::Raise,
+        };
+        let err = structure_descriptor.deserialize_msgpack_slice(&encode("<f4", vec![2, 3], &floats)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Limit, "{}", err);
+    }
+
+    #[test]
+    fn test_binary() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(
+            r#"{
+                "emb": {"dtype": "float32", "encoding": "base64", "shape": [-1, 2]},
+                "ids": {"dtype": "int32", "encoding": "hex", "byteorder": ">"},
+                "mask": {"dtype": "bool_", "encoding": "base64"}
+            }"#,
+        )
+        .unwrap();
+        // [1.5, -2, 3, 4] as little endian float32, [1, -2, 300] as big endian int16 and [True, False, True]
+        let json = br#"{"emb": "AADAPwAAAMAAAEBAAACAQA==", "ids": "0001fffe012cffff", "mask": "AQAB"}"#;
+        match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Map(map) => {
+                assert_eq!(map["emb"], OutputTypes::F32(Array(Base::Array(vec![1.5, -2.0, 3.0, 4.0]), Some(vec![2, 2]))));
+                assert_eq!(map["ids"], OutputTypes::I32(Array(Base::Array(vec![0x0001fffe, 0x012cffff]), Some(vec![2]))));
+                assert_eq!(map["mask"], OutputTypes::Bool(BoolArray(Base::Array(vec![true, false, true]), Some(vec![3]))));
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"a": {"dtype": "int16", "encoding": "bin", "shape": [3]}}"#).unwrap();
+        let mut msgpack = vec![0x81, 0xa1, b'a', 0xc4, 6];
+        msgpack.extend([1i16, -2, 300].iter().flat_map(|value| value.to_le_bytes()));
+        assert_eq!(
+            structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap(),
+            OutputTypes::Map(
+                [("a".to_string(), OutputTypes::I16(Array(Base::Array(vec![1, -2, 300]), Some(vec![3]))))].into_iter().collect()
+            )
+        );
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": "AQAB"}"#).unwrap_err();
+        assert_eq!((err.kind, err.path.to_string()), (ErrorKind::Cast, "$.a".to_string()), "{}", err);
+
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"a": {"dtype": "float32", "encoding": "base64", "shape": [3, -1]}}"#).unwrap();
+        for (json, kind) in [
+            (r#"{"a": "AADAPwAAAMAAAEBAAACAQA=="}"#, ErrorKind::Shape),
+            (r#"{"a": "AADAPwAAAMA"}"#, ErrorKind::Shape),
+            (r#"{"a": "not base64!"}"#, ErrorKind::Cast),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!(err.kind, kind, "{}", err);
+            assert_eq!(err.path.to_string(), "$.a");
+        }
+
+        let mut structure_descriptor = structure_descriptor;
+        structure_descriptor.limits = Limits { max_array_elements: Some(3), ..Default::default() };
+        let err = structure_descriptor.deserialize_json_slice(br#"{"a": "AADAPwAAAMAAAEBAAACAQA=="}"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Limit);
+
+        for structure in [
+            r#"{"dtype": "str", "encoding": "base64"}"#,
+            r#"{"dtype": "float32", "encoding": "base64", "shape": [-1, -1]}"#,
+            r#"{"dtype": "float32", "encoding": "base32"}"#,
+        ] {
+            assert!(serde_json::from_str::<Structure>(structure).is_err(), "{}", structure);
+        }
+    }
+
+    #[test]
+    fn test_reshape() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(
+            r#"{"x": {"reshape": "float32", "data": "data", "shape": "shape", "dtype": "datatype"}}"#,
+        )
+        .unwrap();
+        let expected = OutputTypes::F32(Array(Base::Array(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), Some(vec![2, 3])));
+        for json in [
+            r#"{"x": {"shape": [2, 3], "data": [1, 2, 3, 4, 5, 6]}}"#,
+            r#"{"x": {"data": [1, 2, 3, 4, 5, 6], "name": "y", "shape": [2, 3]}}"#,
+            r#"{"x": {"data": [[1, 2], [3, 4], [5, 6]], "shape": [2, 3]}}"#,
+        ] {
+            match structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap() {
+                OutputTypes::Map(map) => assert_eq!(map["x"], expected, "{}", json),
+                other => panic!("unexpected output {:?}", other),
+            }
+        }
+
+        // the dtype field overrides the default dtype, before or after the data
+        for json in [
+            r#"{"x": {"datatype": "int64", "data": [1, 2, 3, 4], "shape": [2, 2]}}"#,
+            r#"{"x": {"data": [1, 2, 3, 4], "shape": [2, 2], "datatype": "int64"}}"#,
+        ] {
+            match structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap() {
+                OutputTypes::Map(map) => {
+                    assert_eq!(map["x"], OutputTypes::I64(Array(Base::Array(vec![1, 2, 3, 4]), Some(vec![2, 2]))), "{}", json)
+                }
+                other => panic!("unexpected output {:?}", other),
+            }
+        }
+        match structure_descriptor.deserialize_json_slice(br#"{"x": {"data": [7], "shape": []}}"#).unwrap() {
+            OutputTypes::Map(map) => assert_eq!(map["x"], OutputTypes::F32(Array(Base::Scalar(7.0), None))),
+            other => panic!("unexpected output {:?}", other),
+        }
+
+        for (json, kind, path) in [
+            (r#"{"x": {"data": [1, 2, 3], "shape": [2, 2]}}"#, ErrorKind::Shape, "$.x.data"),
+            (r#"{"x": {"data": [1, 2, 3, 4]}}"#, ErrorKind::MissingKey, "$.x"),
+            (r#"{"x": {"data": [1, 2], "shape": [2], "datatype": "complex64"}}"#, ErrorKind::Cast, "$.x.datatype"),
+            (r#"{"x": {"data": [1, 300], "shape": [2], "datatype": "int8"}}"#, ErrorKind::Cast, "$.x.data[1]"),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (kind, path.to_string()), "{}", err);
+        }
+
+        // a map without the data and shape keys is an ordinary map structure
+        let structure: Structure = serde_json::from_str(r#"{"reshape": "float32"}"#).unwrap();
+        assert!(matches!(structure, Structure::Map(_)));
+        assert!(serde_json::from_str::<Structure>(r#"{"reshape": "str", "data": "data", "shape": "shape"}"#).is_err());
+    }
+
+    #[test]
+    fn test_kserve_v2() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"{"kserve_v2": "inputs"}"#).unwrap();
+        let json = br#"{
+            "id": "42",
+            "inputs": [
+                {"name": "x", "shape": [2, 2], "datatype": "FP32", "data": [1, 2, 3, 4]},
+                {"name": "half", "datatype": "FP16", "shape": [3], "parameters": {"content_type": "np"}, "data": [0.5, 1.5, -2]},
+                {"data": [[1, 2], [3, 4]], "shape": [2, 2], "name": "ids", "datatype": "INT64"},
+                {"name": "mask", "shape": [2], "datatype": "BOOL", "data": [true, false]},
+                {"name": "text", "shape": [1, 2], "datatype": "BYTES", "data": ["hi", "there"]}
+            ],
+            "outputs": [{"name": "y"}]
+        }"#;
+        let map = match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Map(map) => map,
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert_eq!(map.keys().collect_vec(), vec!["x", "half", "ids", "mask", "text"]);
+        assert_eq!(map["x"], OutputTypes::F32(Array(Base::Array(vec![1.0, 2.0, 3.0, 4.0]), Some(vec![2, 2]))));
+        assert_eq!(
+            map["half"],
+            OutputTypes::F16(Array(Base::Array(vec![f16::from_f32(0.5), f16::from_f32(1.5), f16::from_f32(-2.0)]), Some(vec![3])))
+        );
+        assert_eq!(map["ids"], OutputTypes::I64(Array(Base::Array(vec![1, 2, 3, 4]), Some(vec![2, 2]))));
+        assert_eq!(map["mask"], OutputTypes::Bool(BoolArray(Base::Array(vec![true, false]), Some(vec![2]))));
+        assert_eq!(map["text"], OutputTypes::Bytes(Bytes { values: vec![b"hi".to_vec(), b"there".to_vec()], shape: vec![1, 2] }));
+
+        for (json, kind, path) in [
+            (r#"{"outputs": []}"#, ErrorKind::MissingKey, "$"),
+            (r#"{"inputs": [{"name": "x", "shape": [3], "data": [1, 2, 3]}]}"#, ErrorKind::MissingKey, "$.inputs[0]"),
+            (r#"{"inputs": [{"name": "x", "shape": [2], "datatype": "BF16", "data": [1, 2]}]}"#, ErrorKind::Cast, "$.inputs[0].datatype"),
+            (r#"{"inputs": [{"name": "x", "shape": [2, 2], "datatype": "INT32", "data": [1, 2, 3]}]}"#, ErrorKind::Shape, "$.inputs[0].data"),
+            (r#"{"inputs": [{"name": "x", "shape": [2], "data": [1, 256], "datatype": "UINT8"}]}"#, ErrorKind::Cast, "$.inputs[0].data[1]"),
+            (r#"{"inputs": [{"name": "x", "shape": [1], "datatype": "BYTES", "data": ["a", "b"]}]}"#, ErrorKind::Shape, "$.inputs[0].data"),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (kind, path.to_string()), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_tf_serving() {
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"tf_serving": {"x": "float32", "id": "int64", "image": "str"}}"#).unwrap();
+        let rows = br#"{
+            "signature_name": "serving_default",
+            "instances": [
+                {"x": [[1, 2], [3, 4]], "id": 1, "image": {"b64": "aGk="}},
+                {"id": 2, "image": "there", "x": [[5, 6], [7, 8]], "extra": null}
+            ]
+        }"#;
+        let columns = br#"{"inputs": {"image": [{"b64": "aGk="}, "there"], "id": [1, 2], "x": [[[1, 2], [3, 4]], [[5, 6], [7, 8]]]}}"#;
+        for json in [&rows[..], &columns[..]] {
+            let map = match structure_descriptor.deserialize_json_slice(json).unwrap() {
+                OutputTypes::Map(map) => map,
+                other => panic!("unexpected output {:?}", other),
+            };
+            assert_eq!(map.keys().collect_vec(), vec!["x", "id", "image"]);
+            assert_eq!(map["x"], OutputTypes::F32(Array(Base::Array(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]), Some(vec![2, 2, 2]))));
+            assert_eq!(map["id"], OutputTypes::I64(Array(Base::Array(vec![1, 2]), Some(vec![2]))));
+            assert_eq!(map["image"], OutputTypes::Bytes(Bytes { values: vec![b"hi".to_vec(), b"there".to_vec()], shape: vec![2] }));
+        }
+
+        // a single unnamed input
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"{"tf_serving": "int32"}"#).unwrap();
+        for json in [r#"{"instances": [[1, 2], [3, 4], [5, 6]]}"#, r#"{"inputs": [[1, 2], [3, 4], [5, 6]]}"#] {
+            assert_eq!(
+                structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap(),
+                OutputTypes::I32(Array(Base::Array(vec![1, 2, 3, 4, 5, 6]), Some(vec![3, 2]))),
+                "{}",
+                json
+            );
+        }
+        assert_eq!(
+            structure_descriptor.deserialize_json_slice(br#"{"instances": []}"#).unwrap(),
+            OutputTypes::I32(Array(Base::Array(vec![]), Some(vec![0])))
+        );
+
+        for (json, kind, path) in [
+            (r#"{"instances": [[1, 2], [3]]}"#, ErrorKind::Shape, "$.instances[1]"),
+            (r#"{"instances": [[1, 2], [[3, 4]]]}"#, ErrorKind::Shape, "$.instances[1]"),
+            (r#"{"signature_name": "serving_default"}"#, ErrorKind::MissingKey, "$"),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (kind, path.to_string()), "{}", err);
+        }
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"{"tf_serving": {"x": "float32", "s": "str"}}"#).unwrap();
+        for (json, kind, path) in [
+            (r#"{"instances": [{"x": 1, "s": "a"}, {"x": 2}]}"#, ErrorKind::MissingKey, "$.instances[1]"),
+            (r#"{"instances": [{"x": 1, "s": {"b64": "not base64!"}}]}"#, ErrorKind::Cast, "$.instances[0].s.b64"),
+            (r#"{"inputs": {"x": [1, 2]}}"#, ErrorKind::MissingKey, "$.inputs"),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (kind, path.to_string()), "{}", err);
+        }
+        assert!(serde_json::from_str::<TfServing>(r#"{"tf_serving": {"x": "dict"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_tensor_columns() {
+        let structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"[{"id": "int32", "bbox": "float32", "mask": "bool_"}]"#).unwrap();
+        let json = br#"[
+            {"id": 1, "bbox": [0, 0, 10, 20], "mask": [[true, false], [false, true]]},
+            {"id": 2, "bbox": [5, 5, 15, 25], "mask": [[false, false], [true, true]]}
+        ]"#;
+        let columns = match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Map(columns)) => columns,
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert_eq!(columns["id"], OutputTypes::I32(Array(Base::Array(vec![1, 2]), Some(vec![2]))));
+        assert_eq!(
+            columns["bbox"],
+            OutputTypes::F32(Array(Base::Array(vec![0.0, 0.0, 10.0, 20.0, 5.0, 5.0, 15.0, 25.0]), Some(vec![2, 4])))
+        );
+        assert_eq!(
+            columns["mask"],
+            OutputTypes::Bool(BoolArray(Base::Array(vec![true, false, false, true, false, false, true, true]), Some(vec![2, 2, 2])))
+        );
+
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"[["str", "float16"]]"#).unwrap();
+        match structure_descriptor.deserialize_json_slice(br#"[["a", [1, 2, 3]], ["b", [4, 5, 6]]]"#).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => assert_eq!(
+                columns[1],
+                OutputTypes::F16(Array(Base::Array([1.0, 2.0, 3.0, 4.0, 5.0, 6.0].map(f16::from_f32).to_vec()), Some(vec![2, 3])))
+            ),
+            other => panic!("unexpected output {:?}", other),
+        }
+
+        for (json, path) in [
+            (r#"[["a", [1, 2, 3]], ["b", [4, 5]]]"#, "$[1][1]"),
+            (r#"[["a", [1, 2, 3]], ["b", 4]]"#, "$[1][1]"),
+            (r#"[["a", 1], ["b", [4]]]"#, "$[1][1]"),
+            (r#"[["a", [[1, 2], [3]]]]"#, "$[0][1]"),
+        ] {
+            let err = structure_descriptor.deserialize_json_slice(json.as_bytes()).unwrap_err();
+            assert_eq!((err.kind, err.path.to_string()), (ErrorKind::Shape, path.to_string()), "{}", err);
+        }
+
+        // failed records are undone and filled with an item of missing values
+        let structure_descriptor = StructureDescriptor {
+            on_error: OnError::Fill,
+            ..serde_json::from_str(r#"[{"id": "int32", "bbox": "float64"}]"#).unwrap()
+        };
+        let json = br#"[{"id": 1, "bbox": [1, 2]}, {"bbox": [3, 4], "id": "x"}, {"id": 3, "bbox": [5, 6]}]"#;
+        match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Map(columns)) => match &columns["bbox"] {
+                OutputTypes::F64(Array(Base::Array(values), shape)) => {
+                    assert_eq!(shape, &Some(vec![3, 2]));
+                    assert_eq!((values[..2].to_vec(), values[4..].to_vec()), (vec![1.0, 2.0], vec![5.0, 6.0]));
+                    assert!(values[2].is_nan() && values[3].is_nan());
+                }
+                other => panic!("unexpected column {:?}", other),
+            },
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(error_types::take_record_errors().len(), 1);
+
+        // records filled before the first one that parsed get the shape of its values
+        let json = br#"[{"id": "x", "bbox": [1, 2]}, {"id": 2, "bbox": [3, 4]}, {"id": 3, "bbox": [5, 6]}]"#;
+        match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Map(columns)) => {
+                assert_eq!(columns["id"], OutputTypes::I32(Array(Base::Array(vec![0, 2, 3]), Some(vec![3]))));
+                match &columns["bbox"] {
+                    OutputTypes::F64(Array(Base::Array(values), shape)) => {
+                        assert_eq!(shape, &Some(vec![3, 2]));
+                        assert_eq!(values[2..].to_vec(), vec![3.0, 4.0, 5.0, 6.0]);
+                        assert!(values[0].is_nan() && values[1].is_nan());
+                    }
+                    other => panic!("unexpected column {:?}", other),
+                }
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(error_types::take_record_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_sequences() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(r#"[[["float32", "int32", "str"]]]"#).unwrap();
+        let json = br#"[
+            [[0.5, 1, "a"], [1.5, 2, "b"], [2.5, 3, "c"]],
+            [[3.5, 4, "d"], [4.5, 5, "e"], [5.5, 6, "f"]]
+        ]"#;
+        let columns = match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => columns,
+            other => panic!("unexpected output {:?}", other),
+        };
+        assert_eq!(columns[0], OutputTypes::F32(Array(Base::Array(vec![0.5, 1.5, 2.5, 3.5, 4.5, 5.5]), Some(vec![2, 3]))));
+        assert_eq!(columns[1], OutputTypes::I32(Array(Base::Array(vec![1, 2, 3, 4, 5, 6]), Some(vec![2, 3]))));
+        assert_eq!(
+            columns[2],
+            OutputTypes::PyList(vec![
+                PythonType(serde_json::json!(["a", "b", "c"])),
+                PythonType(serde_json::json!(["d", "e", "f"])),
+            ])
+        );
+
+        let json = br#"[[[0.5, 1, "a"], [1.5, 2, "b"]], [[3.5, 4, "d"]]]"#;
+        let err = structure_descriptor.deserialize_json_slice(json).unwrap_err();
+        assert_eq!((err.kind, err.path.to_string()), (ErrorKind::Shape, "$[1]".to_string()), "{}", err);
+        let err = structure_descriptor.deserialize_json_slice(br#"[[[0.5, "x", "a"]]]"#).unwrap_err();
+        assert_eq!(err.path.to_string(), "$[0][0][1]", "{}", err);
+
+        // the shorter sequences are padded with missing values up to the longest one
+        let structure_descriptor = StructureDescriptor { ragged: Ragged::Pad, ..structure_descriptor };
+        let columns = match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => columns,
+            other => panic!("unexpected output {:?}", other),
+        };
+        match &columns[0] {
+            OutputTypes::F32(Array(Base::Array(values), shape)) => {
+                assert_eq!(shape, &Some(vec![2, 2]));
+                assert_eq!(values[..3].to_vec(), vec![0.5, 1.5, 3.5]);
+                assert!(values[3].is_nan());
+            }
+            other => panic!("unexpected column {:?}", other),
+        }
+        assert_eq!(columns[1], OutputTypes::I32(Array(Base::Array(vec![1, 2, 4, 0]), Some(vec![2, 2]))));
+        assert_eq!(
+            columns[2],
+            OutputTypes::PyList(vec![PythonType(serde_json::json!(["a", "b"])), PythonType(serde_json::json!(["d", null]))])
+        );
+        // 2 records read, but padding the empty sequences would make 6
+        let limited = StructureDescriptor { limits: Limits { max_array_elements: Some(3), ..Default::default() }, ..structure_descriptor.clone() };
+        let err = limited.deserialize_json_slice(br#"[[[0.5, 1, "a"], [1.5, 2, "b"]], [], []]"#).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Limit, "{}", err);
+
+        // a failing sequence is skipped as a whole
+        let structure_descriptor = StructureDescriptor { on_error: OnError::Skip, ..structure_descriptor };
+        let json = br#"[[[0.5, 1, "a"]], [[1.5, 2, "b"], [2.5, "x", "c"]], [[3.5, 4, "d"], [4.5, 5, "e"]]]"#;
+        match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => {
+                assert_eq!(columns[1], OutputTypes::I32(Array(Base::Array(vec![1, 0, 4, 5]), Some(vec![2, 2]))))
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        let errors = error_types::take_record_errors();
+        assert_eq!(errors.iter().map(|error| error.index).collect_vec(), vec![1]);
+        assert_eq!(errors[0].error.path.to_string(), "$[1][1][1]");
+
+        // a filled sequence before the first parsed one takes its length
+        let structure_descriptor = StructureDescriptor { on_error: OnError::Fill, ragged: Ragged::Raise, ..structure_descriptor };
+        let json = br#"[[[1.5, "x", "b"]], [[0.5, 1, "a"], [2.5, 3, "c"]], [[3.5, "y", "d"]]]"#;
+        match structure_descriptor.deserialize_json_slice(json).unwrap() {
+            OutputTypes::Records(Records::Seq(columns)) => {
+                assert_eq!(columns[1], OutputTypes::I32(Array(Base::Array(vec![0, 0, 1, 3, 0, 0]), Some(vec![3, 2]))))
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+        assert_eq!(error_types::take_record_errors().len(), 2);
+    }
+
+    #[test]
+    fn test_one_of_bin() {
+        let structure_descriptor: StructureDescriptor = serde_json::from_str(
+            r#"{"a": {"oneOf": ["int16", {"dtype": "int16", "encoding": "bin"}]}, "b": {"oneOf": ["str", {"k": "any"}]}}"#,
+        )
+        .unwrap();
+        // {"a": bin([1, -2, 300] as little endian int16), "b": {"k": [1, "x"]}}
+        let mut msgpack = vec![0x82, 0xa1, b'a', 0xc4, 6];
+        msgpack.extend([1i16, -2, 300].iter().flat_map(|value| value.to_le_bytes()));
+        msgpack.extend([0xa1, b'b', 0x81, 0xa1, b'k', 0x92, 0x01, 0xa1, b'x']);
+        match structure_descriptor.deserialize_msgpack_slice(&msgpack).unwrap() {
+            OutputTypes::Map(map) => {
+                assert_eq!(map["a"], OutputTypes::I16(Array(Base::Array(vec![1, -2, 300]), Some(vec![3]))));
+                assert_eq!(
+                    map["b"],
+                    OutputTypes::Map(
+                        [("k".to_string(), OutputTypes::PythonType(PythonType(serde_json::json!([1, "x"]))))].into_iter().collect()
+                    )
+                );
+            }
+            other => panic!("unexpected output {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_into() {
+        let mut out = [0f32; 4];
+        Array::<f32>::deserialize_into(&mut serde_json::Deserializer::from_str("[[1, 2], [3, 4.5]]"), &mut out, &[2, 2]).unwrap();
+        assert_eq!(out, [1.0, 2.0, 3.0, 4.5]);
+
+        // a value past the end of out fails as it arrives, before the rest of the array is read
+        let err = Array::<f32>::deserialize_into(&mut serde_json::Deserializer::from_str("[1, 2, 3, 4, 5, 6]"), &mut out, &[2, 2])
+            .unwrap_err();
+        assert!(err.to_string().contains("more values"), "{}", err);
+        let err = DeserializeError::take(ErrorKind::Structure, err.to_string());
+        assert_eq!(err.kind, ErrorKind::Shape);
+
+        // as many values but another shape
+        let err = Array::<f32>::deserialize_into(&mut serde_json::Deserializer::from_str("[1, 2, 3, 4]"), &mut out, &[2, 2])
+            .unwrap_err();
+        assert!(err.to_string().contains("has shape [2, 2] but the deserialized array has shape [4]"), "{}", err);
+
+        let mut out = [false; 3];
+        BoolArray::deserialize_into(&mut serde_json::Deserializer::from_str("[true, false, true]"), &mut out, &[3]).unwrap();
+        assert_eq!(out, [true, false, true]);
+    }
+
+    #[test]
+    fn test_record_error_paths() {
+        let mut structure_descriptor: StructureDescriptor =
+            serde_json::from_str(r#"{"meta": "str", "rows": [{"a": "uint8"}]}"#).unwrap();
+        structure_descriptor.on_error = OnError::Skip;
+        let json = br#"{"meta": "x", "rows": [{"a": 1}, {"a": 300}, {"a": 3}]}"#;
+        structure_descriptor.deserialize_json_slice(json).unwrap();
+        let errors = error_types::take_record_errors();
+        assert_eq!(errors.iter().map(|err| err.index).collect_vec(), vec![1]);
+        assert_eq!(errors[0].error.path.to_string(), "$.rows[1].a");
+
+        // the records skipped by an alternative which then failed aren't reported
+        let structure_descriptor = StructureDescriptor {
+            data: serde_json::from_str(r#"{"oneOf": [{"rows": [{"a": "uint8"}], "n": "int"}, {"rows": "any", "n": "str"}]}"#).unwrap(),
+            ..structure_descriptor
+        };
+        let json = br#"{"rows": [{"a": 1}, {"a": 300}], "n": "not an int"}"#;
+        assert!(matches!(structure_descriptor.deserialize_json_slice(json).unwrap(), OutputTypes::Map(_)));
+        assert!(error_types::take_record_errors().is_empty());
+    }
 }
@@ -1,13 +1,21 @@
 use pyo3::exceptions::PyValueError;
 use serde::de;
-use serde::de::{DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
 
+use half::f16;
 use ndarray::ShapeBuilder;
 use num_traits::cast::FromPrimitive;
-use numpy::IntoPyArray;
+use numpy::{IntoPyArray, PyArrayDyn};
 use pyo3::prelude::*;
 
+use crate::errors::{new_err, ErrorKind};
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::msgpack_numpy_types::{EncodedArray, NativeType};
+use crate::parsing::dlpack_types::{into_tensor, ArrayFormat, DLPackElement};
+
 #[derive(Debug, PartialEq)]
 pub enum Base<T> {
     Scalar(T),
@@ -17,15 +25,40 @@ pub enum Base<T> {
 #[derive(Debug, PartialEq)]
 pub struct Array<T>(pub Base<T>, pub Option<Vec<usize>>);
 
-impl<T: IntoPy<PyObject> + numpy::Element> IntoPy<PyResult<PyObject>> for Array<T> {
+/// Converts a scalar value of an array to a python int or float
+pub trait IntoPyScalar {
+    fn into_py_scalar(self, py: Python) -> PyObject;
+}
+
+macro_rules! impl_into_py_scalar {
+    ($($type:ty),*) => {
+        $(impl IntoPyScalar for $type {
+            fn into_py_scalar(self, py: Python) -> PyObject {
+                self.into_py(py)
+            }
+        })*
+    };
+}
+
+impl_into_py_scalar!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl IntoPyScalar for f16 {
+    fn into_py_scalar(self, py: Python) -> PyObject {
+        self.to_f64().into_py(py)
+    }
+}
+
+impl<T: IntoPyScalar + numpy::Element> IntoPy<PyResult<PyObject>> for Array<T> {
     fn into_py(self, py: Python) -> PyResult<PyObject> {
         match self {
-            Array(Base::Scalar(val), _) => Ok(val.into_py(py)),
+            Array(Base::Scalar(val), _) => Ok(val.into_py_scalar(py)),
             Array(Base::Array(arr), shape) => {
-                let expected_shape = shape.clone().unwrap();
+                let expected_shape = shape.unwrap_or_else(|| vec![arr.len()]);
                 let n_elements = arr.len();
-                ndarray::ArrayBase::from_shape_vec(shape.unwrap().into_shape(), arr).map_or(
-                    Err(PyValueError::new_err(
+                ndarray::ArrayBase::from_shape_vec(expected_shape.clone().into_shape(), arr).map_or(
+                    Err(new_err(
+                        py,
+                        ErrorKind::Shape,
                         format!("Irregular shape found cannot parse as {} array. Expected shape: {:?}  Total elements: {}", std::any::type_name::<T>(), expected_shape, n_elements),
                     )),
                     |arr| Ok(arr.into_pyarray(py).into_py(py)),
@@ -35,26 +68,238 @@ impl<T: IntoPy<PyObject> + numpy::Element> IntoPy<PyResult<PyObject>> for Array<
     }
 }
 
+/// Selects `out[index]` when writing a single sample into a batch of preallocated arrays
+pub fn index_out(out: &PyAny, index: Option<usize>) -> PyResult<&PyAny> {
+    match index {
+        Some(index) => out.get_item(index),
+        None => Ok(out),
+    }
+}
+
+fn write_array_into<T: numpy::Element>(arr: Vec<T>, shape: Vec<usize>, out: &PyAny) -> PyResult<()> {
+    let out = out.downcast::<PyArrayDyn<T>>().map_err(|_| {
+        PyValueError::new_err(format!(
+            "out must be a numpy array of {}, found: {}",
+            std::any::type_name::<T>(),
+            out
+        ))
+    })?;
+    if out.shape() != shape.as_slice() {
+        return Err(new_err(out.py(), ErrorKind::Shape, format!(
+            "out array has shape {:?} but the deserialized array has shape {:?}",
+            out.shape(),
+            shape
+        )));
+    }
+    let n_elements = arr.len();
+    let source = ndarray::ArrayViewD::from_shape(shape.clone(), &arr).map_err(|_| {
+        new_err(out.py(), ErrorKind::Shape, format!("Irregular shape found cannot parse as {} array. Expected shape: {:?}  Total elements: {}", std::any::type_name::<T>(), shape, n_elements))
+    })?;
+    out.try_readwrite()?.as_array_mut().assign(&source);
+    Ok(())
+}
+
+/// Converts an array buffer to a DLPack capsule or tensor, checking that the shape matches the number of elements
+fn array_into_tensor<T: DLPackElement>(py: Python, arr: Vec<T>, shape: Option<Vec<usize>>, format: ArrayFormat, type_name: &str) -> PyResult<PyObject> {
+    let shape = shape.unwrap_or_default();
+    if shape.iter().product::<usize>() != arr.len() {
+        return Err(new_err(py, ErrorKind::Shape, format!("Irregular shape found cannot parse as {} array. Expected shape: {:?}  Total elements: {}", type_name, shape, arr.len())));
+    }
+    into_tensor(py, arr, &shape, format)
+}
+
+impl<T: IntoPyScalar + numpy::Element + DLPackElement> Array<T> {
+    pub fn into_py_with(self, py: Python, format: ArrayFormat) -> PyResult<PyObject> {
+        match (self, format) {
+            (Array(Base::Array(arr), shape), ArrayFormat::DLPack | ArrayFormat::Torch) => {
+                array_into_tensor(py, arr, shape, format, std::any::type_name::<T>())
+            }
+            (array, _) => array.into_py(py),
+        }
+    }
+
+    /// Writes the array into the preallocated numpy array `out` (or `out[index]`) instead of allocating a new one
+    pub fn write_into(self, out: &PyAny, index: Option<usize>) -> PyResult<()> {
+        match (self, index) {
+            (Array(Base::Scalar(val), _), Some(index)) => out.set_item(index, val.into_py_scalar(out.py())),
+            (Array(Base::Scalar(_), _), None) => Err(PyValueError::new_err(
+                "Cannot write a scalar into out without an index",
+            )),
+            (Array(Base::Array(arr), shape), index) => {
+                write_array_into(arr, shape.unwrap_or_default(), index_out(out, index)?)
+            }
+        }
+    }
+}
+
+fn push_error<E: de::Error>(shape: Option<&[usize]>) -> E {
+    error_types::error(
+        ErrorKind::Shape,
+        Some("a 1D array".to_string()),
+        Some(format!("shape {:?}", shape.unwrap_or_default())),
+        "Can only append values to a 1D array",
+    )
+}
+
+impl<T> Base<T> {
+    /// The values with a new shape holding as many values, a scalar for the empty shape
+    fn reshape<E: de::Error>(self, shape: Vec<usize>) -> Result<(Base<T>, Option<Vec<usize>>), E> {
+        let values = match self {
+            Base::Scalar(value) => vec![value],
+            Base::Array(values) => values,
+        };
+        let len = values.len();
+        match (shape.iter().product::<usize>() == len, shape.is_empty()) {
+            (true, false) => Ok((Base::Array(values), Some(shape))),
+            (true, true) => Ok((Base::Scalar(values.into_iter().next().ok_or_else(|| reshape_error(len, &shape))?), None)),
+            (false, _) => Err(reshape_error(len, &shape)),
+        }
+    }
+}
+
+/// Appends an item of shape `item_shape` to `values`, a stack of items of shape `shape` along its first axis. The first
+/// item sets the shape of the rest, which must all have the same shape
+pub fn stack<T, E: de::Error>(values: &mut Vec<T>, shape: &mut Vec<usize>, item: Vec<T>, item_shape: &[usize]) -> Result<(), E> {
+    if item.len() != item_shape.iter().product::<usize>() {
+        return Err(error_types::error(
+            ErrorKind::Shape,
+            Some(format!("shape {:?}", item_shape)),
+            Some(format!("{} values", item.len())),
+            format!("Irregular shape found, {} values don't have shape {:?}", item.len(), item_shape),
+        ));
+    }
+    if shape.first() == Some(&0) {
+        shape.truncate(1);
+        shape.extend_from_slice(item_shape);
+    } else if shape.get(1..) != Some(item_shape) {
+        return Err(error_types::error(
+            ErrorKind::Shape,
+            Some(format!("shape {:?}", shape.get(1..).unwrap_or_default())),
+            Some(format!("shape {:?}", item_shape)),
+            format!("Cannot stack an item of shape {:?} onto items of shape {:?}", item_shape, shape.get(1..).unwrap_or_default()),
+        ));
+    }
+    values.extend(item);
+    shape[0] += 1;
+    Ok(())
+}
+
+/// Appends a scalar to a stack, quickly when it is a stack of scalars
+fn push_value<T, E: de::Error>(values: &mut Vec<T>, shape: &mut Vec<usize>, value: T) -> Result<(), E> {
+    match shape.len() {
+        1 => {
+            values.push(value);
+            shape[0] += 1;
+            Ok(())
+        }
+        _ => stack(values, shape, vec![value], &[]),
+    }
+}
+
+/// Drops the items of a stack after the first `len`
+fn truncate_items<T>(values: &mut Vec<T>, shape: &mut [usize], len: usize) {
+    if let Some((first, item_shape)) = shape.split_first_mut() {
+        let len = len.min(*first);
+        values.truncate(len * item_shape.iter().product::<usize>());
+        *first = len;
+    }
+}
+
+/// Inserts `count` items of `missing` values before the items of a stack, with the shape of its items
+fn insert_items<T: Clone>(values: &mut Vec<T>, shape: &mut [usize], count: usize, missing: T) {
+    if let Some((first, item_shape)) = shape.split_first_mut() {
+        let len = count * item_shape.iter().product::<usize>();
+        values.splice(0..0, std::iter::repeat_n(missing, len));
+        *first += count;
+    }
+}
+
+impl<T> Base<T> {
+    /// The values and shape of an item, which is a scalar without a shape or a flat array without one
+    fn into_item(self, shape: Option<Vec<usize>>) -> (Vec<T>, Vec<usize>) {
+        match self {
+            Base::Scalar(value) => (vec![value], shape.unwrap_or_default()),
+            Base::Array(values) => {
+                let shape = shape.unwrap_or_else(|| vec![values.len()]);
+                (values, shape)
+            }
+        }
+    }
+}
+
+fn reshape_error<E: de::Error>(len: usize, shape: &[usize]) -> E {
+    error_types::error(
+        ErrorKind::Shape,
+        Some(format!("shape {:?}", shape)),
+        Some(format!("{} values", len)),
+        format!("Cannot reshape {} values into shape {:?}", len, shape),
+    )
+}
+
 impl<T> Array<T> {
     pub fn new() -> Array<T> {
         Array(Base::Array(vec![]), Some(vec![0]))
     }
 
-    pub fn push(&mut self, value: T) {
+    /// Appends a value to a stack of scalars, such as a transposed column
+    pub fn push<E: de::Error>(&mut self, value: T) -> Result<(), E> {
         match self {
-            Array(Base::Array(ref mut vec), Some(ref mut shape)) => {
-                vec.push(value);
-                if shape.len() != 1 {
-                    panic!("not working for non 1D arrays")
-                };
-                shape[0] += 1
+            Array(Base::Array(ref mut vec), Some(ref mut shape)) if !shape.is_empty() => push_value(vec, shape, value),
+            Array(_, shape) => Err(push_error(shape.as_deref())),
+        }
+    }
+
+    /// Appends an array (or scalar) as the next item along the first axis, see `stack`
+    pub fn stack<E: de::Error>(&mut self, item: Array<T>) -> Result<(), E> {
+        match self {
+            Array(Base::Array(ref mut vec), Some(ref mut shape)) if !shape.is_empty() => {
+                let (values, item_shape) = item.0.into_item(item.1);
+                stack(vec, shape, values, &item_shape)
+            }
+            Array(_, shape) => Err(push_error(shape.as_deref())),
+        }
+    }
+
+    pub fn reshape<E: de::Error>(self, shape: Vec<usize>) -> Result<Array<T>, E> {
+        let (base, shape) = self.0.reshape(shape)?;
+        Ok(Array(base, shape))
+    }
+
+    /// Drops the items pushed after the first `len` of a stack
+    pub fn truncate(&mut self, len: usize) {
+        if let Array(Base::Array(ref mut vec), Some(ref mut shape)) = self {
+            truncate_items(vec, shape, len);
+        }
+    }
+}
+
+impl<T: NativeType> Array<T> {
+    /// Pushes the item standing in for a missing one, filled with NaN for floats and 0 for integers
+    pub fn push_missing<E: de::Error>(&mut self) -> Result<(), E> {
+        let missing = || T::from_f64(f64::NAN).or_else(|| T::from_u8(0));
+        match self {
+            Array(Base::Array(ref mut vec), Some(ref mut shape)) if shape.len() > 1 => {
+                let item_shape = shape[1..].to_vec();
+                let item = (0..item_shape.iter().product()).filter_map(|_| missing()).collect();
+                stack(vec, shape, item, &item_shape)
             }
-            _ => panic!("not implemented"),
+            _ => match missing() {
+                Some(value) => self.push(value),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Inserts `count` missing items before the items of a stack, see `push_missing`
+    pub fn insert_missing(&mut self, count: usize) {
+        let missing = T::from_f64(f64::NAN).or_else(|| T::from_u8(0));
+        if let (Array(Base::Array(ref mut vec), Some(ref mut shape)), Some(missing)) = (self, missing) {
+            insert_items(vec, shape, count, missing);
         }
     }
 }
 
-impl<'de, T: FromPrimitive + Clone + Deserialize<'de>> Deserialize<'de> for Array<T> {
+impl<'de, T: NativeType> Deserialize<'de> for Array<T> {
     fn deserialize<D>(deserializer: D) -> Result<Array<T>, D::Error>
     where
         D: serde::Deserializer<'de>,
@@ -65,11 +310,17 @@ impl<'de, T: FromPrimitive + Clone + Deserialize<'de>> Deserialize<'de> for Arra
             values: &mut values,
             shape: &mut shape,
             compute_shape: true,
+            capacity: limit_types::capacity(std::mem::size_of::<T>()),
+            _values: std::marker::PhantomData,
         };
         let visitor = ExtendVecVisitor(builder);
         deserializer.deserialize_any(visitor)?;
+        limit_types::add_output_bytes(values.len() * std::mem::size_of::<T>());
         match shape.len() {
-            0 => Ok(Array(Base::Scalar(values[0].clone()), None)),
+            0 => match values.pop() {
+                Some(value) => Ok(Array(Base::Scalar(value), None)),
+                None => Err(empty_error(std::any::type_name::<T>())),
+            },
             _ => Ok(Array(
                 Base::Array(values),
                 Some(shape.into_iter().rev().collect()),
@@ -78,27 +329,154 @@ impl<'de, T: FromPrimitive + Clone + Deserialize<'de>> Deserialize<'de> for Arra
     }
 }
 
-struct ArrayBuilder<'a, T: 'a> {
-    values: &'a mut Vec<T>,
+impl<T: NativeType> Array<T> {
+    /// Parses an array straight into `out`, the values of a preallocated array of shape `shape`, instead of a new
+    /// `Vec`. The values are written as they arrive, so `out` is left partly written when this fails
+    pub fn deserialize_into<'de, D>(deserializer: D, out: &mut [T], shape: &[usize]) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut values = OutValues { values: out, len: 0, shape };
+        let mut computed_shape = Vec::new();
+        let builder = ArrayBuilder {
+            values: &mut values,
+            shape: &mut computed_shape,
+            compute_shape: true,
+            // the array is already allocated, so it isn't limited
+            capacity: usize::MAX,
+            _values: std::marker::PhantomData,
+        };
+        deserializer.deserialize_any(ExtendVecVisitor(builder))?;
+        check_out_shape(values.len, computed_shape, shape, std::any::type_name::<T>())
+    }
+}
+
+/// The error for a document that produced neither a value nor a shape
+fn empty_error<E: de::Error>(type_name: &str) -> E {
+    error_types::error(
+        ErrorKind::Structure,
+        Some(type_name.to_string()),
+        Some("no value".to_string()),
+        format!("Expected {} value or array, found no value", type_name),
+    )
+}
+
+/// Where the values of an array go as they are parsed: a new `Vec`, or the caller's preallocated array
+trait Values<T> {
+    fn count(&self) -> usize;
+    fn push_value<E: de::Error>(&mut self, value: T) -> Result<(), E>;
+    fn extend_values<E: de::Error>(&mut self, values: Vec<T>) -> Result<(), E>;
+}
+
+impl<T> Values<T> for Vec<T> {
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn push_value<E: de::Error>(&mut self, value: T) -> Result<(), E> {
+        self.push(value);
+        Ok(())
+    }
+
+    fn extend_values<E: de::Error>(&mut self, values: Vec<T>) -> Result<(), E> {
+        self.extend(values);
+        Ok(())
+    }
+}
+
+/// The values of an out array, filled in order. Failing as soon as a value doesn't fit means a document can't
+/// write past the array, whatever its shape turns out to be
+struct OutValues<'o, T> {
+    values: &'o mut [T],
+    len: usize,
+    /// Shape of the out array, for the errors
+    shape: &'o [usize],
+}
+
+impl<'o, T> Values<T> for OutValues<'o, T> {
+    fn count(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn push_value<E: de::Error>(&mut self, value: T) -> Result<(), E> {
+        match self.values.get_mut(self.len) {
+            Some(slot) => {
+                *slot = value;
+                self.len += 1;
+                Ok(())
+            }
+            None => Err(out_overflow_error(self.shape)),
+        }
+    }
+
+    fn extend_values<E: de::Error>(&mut self, values: Vec<T>) -> Result<(), E> {
+        match self.values.get_mut(self.len..self.len + values.len()) {
+            Some(slots) => {
+                for (slot, value) in slots.iter_mut().zip(values) {
+                    *slot = value;
+                }
+                self.len += slots.len();
+                Ok(())
+            }
+            None => Err(out_overflow_error(self.shape)),
+        }
+    }
+}
+
+fn out_overflow_error<E: de::Error>(shape: &[usize]) -> E {
+    error_types::error(
+        ErrorKind::Shape,
+        Some(format!("{:?}", shape)),
+        None,
+        format!("out array has shape {:?} but the deserialized array has more values", shape),
+    )
+}
+
+/// Checks that the values parsed into an out array filled it with the same shape. `shape` is innermost first, as
+/// the builders compute it
+fn check_out_shape<E: de::Error>(len: usize, shape: Vec<usize>, out_shape: &[usize], type_name: &str) -> Result<(), E> {
+    let shape: Vec<usize> = shape.into_iter().rev().collect();
+    if shape.iter().product::<usize>() != len {
+        return Err(error_types::error(
+            ErrorKind::Shape,
+            Some(format!("{:?}", shape)),
+            Some(format!("{} elements", len)),
+            format!("Irregular shape found cannot parse as {} array. Expected shape: {:?}  Total elements: {}", type_name, shape, len),
+        ));
+    }
+    if shape != out_shape {
+        return Err(error_types::error(
+            ErrorKind::Shape,
+            Some(format!("{:?}", out_shape)),
+            Some(format!("{:?}", shape)),
+            format!("out array has shape {:?} but the deserialized array has shape {:?}", out_shape, shape),
+        ));
+    }
+    Ok(())
+}
+
+struct ArrayBuilder<'a, T: 'a, V> {
+    values: &'a mut V,
     shape: &'a mut Vec<usize>,
     compute_shape: bool,
+    /// Number of values allowed by the limits
+    capacity: usize,
+    _values: std::marker::PhantomData<T>,
 }
 
-impl<'de, 'a, T> DeserializeSeed<'de> for ArrayBuilder<'a, T>
+impl<'de, 'a, T, V> DeserializeSeed<'de> for ArrayBuilder<'a, T, V>
 where
-    T: FromPrimitive + Deserialize<'de>,
+    T: NativeType,
+    V: Values<T>,
 {
     type Value = ();
-    fn deserialize<D>(mut self, deserializer: D) -> Result<Self::Value, D::Error>
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let builder = ArrayBuilder {
-            values: &mut self.values,
-            shape: &mut self.shape,
-            compute_shape: self.compute_shape,
-        };
-        deserializer.deserialize_any(ExtendVecVisitor(builder))?;
+        deserializer.deserialize_any(ExtendVecVisitor(self))?;
         Ok(())
     }
 }
@@ -107,23 +485,30 @@ macro_rules! make_extend_visit {
     ($dtype:ty, $name:ident, $conversion:tt) => {
         #[inline]
         fn $name<E: de::Error>(self, value: $dtype) -> Result<Self::Value, E> {
-            if let Some(scalar) = FromPrimitive::$conversion(value) {
-                Ok(self.0.values.push(scalar))
+            if self.0.values.count() >= self.0.capacity {
+                Err(limit_types::capacity_error(self.0.capacity))
+            } else if let Some(scalar) = FromPrimitive::$conversion(value) {
+                self.0.values.push_value(scalar)
             } else {
-                Err(E::custom(format!(
-                    "Could not cast {} ({}) into: {:?}",
-                    value,
-                    std::any::type_name::<$dtype>(),
-                    std::any::type_name::<T>()
-                )))
+                Err(error_types::error(
+                    ErrorKind::Cast,
+                    Some(std::any::type_name::<T>().to_string()),
+                    Some(value.to_string()),
+                    format!(
+                        "Could not cast {} ({}) into: {:?}",
+                        value,
+                        std::any::type_name::<$dtype>(),
+                        std::any::type_name::<T>()
+                    ),
+                ))
             }
         }
     };
 }
 
-struct ExtendVecVisitor<'a, T: 'a>(ArrayBuilder<'a, T>);
+struct ExtendVecVisitor<'a, T: 'a, V>(ArrayBuilder<'a, T, V>);
 
-impl<'de, 'a, T: FromPrimitive + Deserialize<'de>> Visitor<'de> for ExtendVecVisitor<'a, T> {
+impl<'de, 'a, T: NativeType, V: Values<T>> Visitor<'de> for ExtendVecVisitor<'a, T, V> {
     type Value = ();
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -141,20 +526,42 @@ impl<'de, 'a, T: FromPrimitive + Deserialize<'de>> Visitor<'de> for ExtendVecVis
     make_extend_visit!(u32, visit_u32, from_u32);
     make_extend_visit!(u64, visit_u64, from_u64);
 
-    fn visit_seq<S>(mut self, mut seq: S) -> Result<Self::Value, S::Error>
+    /// A msgpack-numpy encoded array, cast to the dtype of the structure
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let array = EncodedArray::from_map(map)?;
+        if self.0.values.count() + array.len() > self.0.capacity {
+            return Err(limit_types::capacity_error(self.0.capacity));
+        }
+        self.0.values.extend_values(array.values::<T, A::Error>()?)?;
+        if self.0.compute_shape {
+            self.0.shape.extend(array.shape.iter().flatten().rev());
+        }
+        Ok(())
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
     where
         S: SeqAccess<'de>,
     {
+        let _depth = limit_types::enter()?;
         // only compute shape of first inner array visited
         if self.0.compute_shape {
             let mut outer_size: usize = 0;
             let mut compute_shape: bool = true;
 
-            while let Some(_) = seq.next_element_seed(ArrayBuilder {
-                values: &mut self.0.values,
-                shape: &mut self.0.shape,
-                compute_shape: compute_shape,
-            })? {
+            while seq.next_element_seed(ArrayBuilder {
+                values: &mut *self.0.values,
+                shape: &mut *self.0.shape,
+                compute_shape,
+                capacity: self.0.capacity,
+                _values: std::marker::PhantomData,
+            })
+            .map_err(error_types::at_index(outer_size))?
+            .is_some()
+            {
                 outer_size += 1;
                 compute_shape = false;
             }
@@ -164,11 +571,19 @@ impl<'de, 'a, T: FromPrimitive + Deserialize<'de>> Visitor<'de> for ExtendVecVis
             Ok(())
         // we now know the shape of the following arrays at this dimension
         } else {
-            while let Some(_) = seq.next_element_seed(ArrayBuilder {
-                values: &mut self.0.values,
-                shape: &mut self.0.shape,
+            let mut index: usize = 0;
+            while seq.next_element_seed(ArrayBuilder {
+                values: &mut *self.0.values,
+                shape: &mut *self.0.shape,
                 compute_shape: false,
-            })? {}
+                capacity: self.0.capacity,
+                _values: std::marker::PhantomData,
+            })
+            .map_err(error_types::at_index(index))?
+            .is_some()
+            {
+                index += 1;
+            }
             Ok(())
         }
     }
@@ -185,10 +600,12 @@ impl IntoPy<PyResult<PyObject>> for BoolArray {
         match self {
             BoolArray(Base::<bool>::Scalar(val), _) => Ok(val.into_py(py)),
             BoolArray(Base::<bool>::Array(arr), shape) => {
-                let expected_shape = shape.clone().unwrap();
+                let expected_shape = shape.unwrap_or_else(|| vec![arr.len()]);
                 let n_elements = arr.len();
-                ndarray::ArrayBase::from_shape_vec(shape.unwrap().into_shape(), arr).map_or(
-                    Err(PyValueError::new_err(
+                ndarray::ArrayBase::from_shape_vec(expected_shape.clone().into_shape(), arr).map_or(
+                    Err(new_err(
+                        py,
+                        ErrorKind::Shape,
                         format!("Irregular shape found cannot parse as {} array. Expected shape: {:?}  Total elements: {}", "bool", expected_shape, n_elements),
                     )),
                     |arr| Ok(arr.into_pyarray(py).into_py(py)),
@@ -199,20 +616,77 @@ impl IntoPy<PyResult<PyObject>> for BoolArray {
 }
 
 impl BoolArray {
+    pub fn into_py_with(self, py: Python, format: ArrayFormat) -> PyResult<PyObject> {
+        match (self, format) {
+            (BoolArray(Base::Array(arr), shape), ArrayFormat::DLPack | ArrayFormat::Torch) => {
+                array_into_tensor(py, arr, shape, format, "bool")
+            }
+            (array, _) => array.into_py(py),
+        }
+    }
+
+    pub fn write_into(self, out: &PyAny, index: Option<usize>) -> PyResult<()> {
+        match (self, index) {
+            (BoolArray(Base::Scalar(val), _), Some(index)) => out.set_item(index, val),
+            (BoolArray(Base::Scalar(_), _), None) => Err(PyValueError::new_err(
+                "Cannot write a scalar into out without an index",
+            )),
+            (BoolArray(Base::Array(arr), shape), index) => {
+                write_array_into(arr, shape.unwrap_or_default(), index_out(out, index)?)
+            }
+        }
+    }
+
     pub fn new() -> BoolArray {
         BoolArray(Base::Array(vec![]), Some(vec![0]))
     }
 
-    pub fn push(&mut self, value: bool) {
+    /// Appends a value to a stack of scalars, such as a transposed column
+    pub fn push<E: de::Error>(&mut self, value: bool) -> Result<(), E> {
+        match self {
+            BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) if !shape.is_empty() => push_value(vec, shape, value),
+            BoolArray(_, shape) => Err(push_error(shape.as_deref())),
+        }
+    }
+
+    /// Pushes the item standing in for a missing one, filled with `false`
+    pub fn push_missing<E: de::Error>(&mut self) -> Result<(), E> {
+        match self {
+            BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) if shape.len() > 1 => {
+                let item_shape = shape[1..].to_vec();
+                stack(vec, shape, vec![false; item_shape.iter().product()], &item_shape)
+            }
+            _ => self.push(false),
+        }
+    }
+
+    /// Inserts `count` missing items before the items of a stack, see `push_missing`
+    pub fn insert_missing(&mut self, count: usize) {
+        if let BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) = self {
+            insert_items(vec, shape, count, false);
+        }
+    }
+
+    /// Appends an array (or scalar) as the next item along the first axis, see `stack`
+    pub fn stack<E: de::Error>(&mut self, item: BoolArray) -> Result<(), E> {
         match self {
-            BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) => {
-                vec.push(value);
-                if shape.len() != 1 {
-                    panic!("not working for non 1D arrays")
-                };
-                shape[0] += 1
+            BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) if !shape.is_empty() => {
+                let (values, item_shape) = item.0.into_item(item.1);
+                stack(vec, shape, values, &item_shape)
             }
-            _ => panic!("not implemented"),
+            BoolArray(_, shape) => Err(push_error(shape.as_deref())),
+        }
+    }
+
+    pub fn reshape<E: de::Error>(self, shape: Vec<usize>) -> Result<BoolArray, E> {
+        let (base, shape) = self.0.reshape(shape)?;
+        Ok(BoolArray(base, shape))
+    }
+
+    /// Drops the items pushed after the first `len` of a stack
+    pub fn truncate(&mut self, len: usize) {
+        if let BoolArray(Base::Array(ref mut vec), Some(ref mut shape)) = self {
+            truncate_items(vec, shape, len);
         }
     }
 }
@@ -228,11 +702,16 @@ impl<'de> Deserialize<'de> for BoolArray {
             values: &mut values,
             shape: &mut shape,
             compute_shape: true,
+            capacity: limit_types::capacity(std::mem::size_of::<bool>()),
         };
         let visitor = BoolExtendVecVisitor(builder);
         deserializer.deserialize_any(visitor)?;
+        limit_types::add_output_bytes(values.len());
         match shape.len() {
-            0 => Ok(BoolArray(Base::Scalar(values[0].clone()), None)),
+            0 => match values.pop() {
+                Some(value) => Ok(BoolArray(Base::Scalar(value), None)),
+                None => Err(empty_error("bool")),
+            },
             _ => Ok(BoolArray(
                 Base::Array(values),
                 Some(shape.into_iter().rev().collect()),
@@ -241,31 +720,47 @@ impl<'de> Deserialize<'de> for BoolArray {
     }
 }
 
-struct BoolArrayBuilder<'a> {
-    values: &'a mut Vec<bool>,
+impl BoolArray {
+    /// Parses a bool array straight into `out`, see `Array::deserialize_into`
+    pub fn deserialize_into<'de, D>(deserializer: D, out: &mut [bool], shape: &[usize]) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut values = OutValues { values: out, len: 0, shape };
+        let mut computed_shape = Vec::new();
+        let builder = BoolArrayBuilder {
+            values: &mut values,
+            shape: &mut computed_shape,
+            compute_shape: true,
+            capacity: usize::MAX,
+        };
+        deserializer.deserialize_any(BoolExtendVecVisitor(builder))?;
+        check_out_shape(values.len, computed_shape, shape, "bool")
+    }
+}
+
+struct BoolArrayBuilder<'a, V> {
+    values: &'a mut V,
     shape: &'a mut Vec<usize>,
     compute_shape: bool,
+    /// Number of values allowed by the limits
+    capacity: usize,
 }
 
-impl<'de, 'a> DeserializeSeed<'de> for BoolArrayBuilder<'a> {
+impl<'de, 'a, V: Values<bool>> DeserializeSeed<'de> for BoolArrayBuilder<'a, V> {
     type Value = ();
-    fn deserialize<D>(mut self, deserializer: D) -> Result<Self::Value, D::Error>
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let builder = BoolArrayBuilder {
-            values: &mut self.values,
-            shape: &mut self.shape,
-            compute_shape: self.compute_shape,
-        };
-        deserializer.deserialize_any(BoolExtendVecVisitor(builder))?;
+        deserializer.deserialize_any(BoolExtendVecVisitor(self))?;
         Ok(())
     }
 }
 
-struct BoolExtendVecVisitor<'a>(BoolArrayBuilder<'a>);
+struct BoolExtendVecVisitor<'a, V>(BoolArrayBuilder<'a, V>);
 
-impl<'de, 'a> Visitor<'de> for BoolExtendVecVisitor<'a> {
+impl<'de, 'a, V: Values<bool>> Visitor<'de> for BoolExtendVecVisitor<'a, V> {
     type Value = ();
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -274,22 +769,46 @@ impl<'de, 'a> Visitor<'de> for BoolExtendVecVisitor<'a> {
 
     #[inline]
     fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
-        Ok(self.0.values.push(value))
+        if self.0.values.count() >= self.0.capacity {
+            return Err(limit_types::capacity_error(self.0.capacity));
+        }
+        self.0.values.push_value(value)
+    }
+
+    /// A msgpack-numpy encoded array, which must have a bool dtype
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let array = EncodedArray::from_map(map)?;
+        if self.0.values.count() + array.len() > self.0.capacity {
+            return Err(limit_types::capacity_error(self.0.capacity));
+        }
+        self.0.values.extend_values(array.bools::<A::Error>()?)?;
+        if self.0.compute_shape {
+            self.0.shape.extend(array.shape.iter().flatten().rev());
+        }
+        Ok(())
     }
 
-    fn visit_seq<S>(mut self, mut seq: S) -> Result<Self::Value, S::Error>
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
     where
         S: SeqAccess<'de>,
     {
+        let _depth = limit_types::enter()?;
         if self.0.compute_shape {
             let mut outer_size: usize = 0;
             let mut compute_shape: bool = true;
 
-            while let Some(_) = seq.next_element_seed(BoolArrayBuilder {
-                values: &mut self.0.values,
-                shape: &mut self.0.shape,
-                compute_shape: compute_shape,
-            })? {
+            while seq.next_element_seed(BoolArrayBuilder {
+                values: &mut *self.0.values,
+                shape: &mut *self.0.shape,
+                compute_shape,
+                capacity: self.0.capacity,
+            })
+            .map_err(error_types::at_index(outer_size))?
+            .is_some()
+            {
                 outer_size += 1;
                 compute_shape = false;
             }
@@ -298,12 +817,129 @@ impl<'de, 'a> Visitor<'de> for BoolExtendVecVisitor<'a> {
 
             Ok(())
         } else {
-            while let Some(_) = seq.next_element_seed(BoolArrayBuilder {
-                values: &mut self.0.values,
-                shape: &mut self.0.shape,
+            let mut index: usize = 0;
+            while seq.next_element_seed(BoolArrayBuilder {
+                values: &mut *self.0.values,
+                shape: &mut *self.0.shape,
                 compute_shape: false,
-            })? {}
+                capacity: self.0.capacity,
+            })
+            .map_err(error_types::at_index(index))?
+            .is_some()
+            {
+                index += 1;
+            }
             Ok(())
         }
     }
 }
+
+/// Deserializes a single value of an array. `f16` has no `Deserialize` impl and is read as an `f64`
+pub trait DeserializeScalar<'de>: Sized {
+    fn deserialize_scalar<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+macro_rules! impl_deserialize_scalar {
+    ($($type:ty),*) => {
+        $(impl<'de> DeserializeScalar<'de> for $type {
+            fn deserialize_scalar<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <$type>::deserialize(deserializer)
+            }
+        })*
+    };
+}
+
+impl_deserialize_scalar!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl<'de> DeserializeScalar<'de> for f16 {
+    fn deserialize_scalar<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(f16::from_f64)
+    }
+}
+
+/// A transposed column, onto which each record pushes a scalar or stacks an array of the same shape as the others
+pub trait Column<'de> {
+    fn push_scalar<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error>;
+    fn stack_array<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error>;
+}
+
+impl<'de, T: DeserializeScalar<'de> + NativeType> Column<'de> for Array<T> {
+    fn push_scalar<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+        let value = T::deserialize_scalar(deserializer)?;
+        self.push(value)
+    }
+
+    fn stack_array<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+        let item = Array::<T>::deserialize(deserializer)?;
+        self.stack(item)
+    }
+}
+
+impl<'de> Column<'de> for BoolArray {
+    fn push_scalar<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+        let value = bool::deserialize(deserializer)?;
+        self.push(value)
+    }
+
+    fn stack_array<D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+        let item = BoolArray::deserialize(deserializer)?;
+        self.stack(item)
+    }
+}
+
+/// Pushes a record's value onto its column: scalars as they are, arrays (nested lists or msgpack-numpy arrays)
+/// stacked along the first axis
+pub struct ColumnSeed<'c, C>(pub &'c mut C);
+
+impl<'de, 'c, C: Column<'de>> DeserializeSeed<'de> for ColumnSeed<'c, C> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+macro_rules! make_push_scalar {
+    ($dtype:ty, $name:ident) => {
+        fn $name<E: de::Error>(self, value: $dtype) -> Result<Self::Value, E> {
+            self.0.push_scalar(value.into_deserializer())
+        }
+    };
+}
+
+impl<'de, 'c, C: Column<'de>> Visitor<'de> for ColumnSeed<'c, C> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a value or an array of values")
+    }
+
+    // any other scalar is handed to the scalar's own deserialize, so the errors are the usual ones
+    make_push_scalar!(bool, visit_bool);
+    make_push_scalar!(i64, visit_i64);
+    make_push_scalar!(u64, visit_u64);
+    make_push_scalar!(f64, visit_f64);
+    make_push_scalar!(&str, visit_str);
+    make_push_scalar!(&[u8], visit_bytes);
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.0.push_scalar(().into_deserializer())
+    }
+
+    fn visit_seq<S>(self, seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        self.0.stack_array(SeqAccessDeserializer::new(seq))
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        self.0.stack_array(MapAccessDeserializer::new(map))
+    }
+}
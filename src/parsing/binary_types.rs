@@ -0,0 +1,335 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use bytemuck::Pod;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+use serde::de::{DeserializeSeed, Deserializer, Unexpected, Visitor};
+use serde::Deserialize;
+
+use crate::errors::ErrorKind;
+use crate::parsing::array_types::{Array, Base, BoolArray};
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::{InputTypes, OutputTypes};
+
+/// Standard base64, with or without the trailing padding
+pub const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// `{"dtype": "float32", "encoding": "base64", "shape": [-1, 4], "byteorder": "<"}`: an array packed into a base64
+/// or hex string, or a msgpack `bin` value, whose bytes are copied into the array without parsing the values.
+/// One dimension of the shape may be -1, taking the rest of the values; without a shape the array is 1D
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "BinaryFields")]
+pub struct Binary {
+    pub dtype: InputTypes,
+    pub encoding: Encoding,
+    pub shape: Option<Vec<i64>>,
+    pub byteorder: ByteOrder,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BinaryFields {
+    dtype: InputTypes,
+    encoding: Encoding,
+    #[serde(default)]
+    shape: Option<Vec<i64>>,
+    #[serde(default)]
+    byteorder: ByteOrder,
+}
+
+impl TryFrom<BinaryFields> for Binary {
+    type Error = String;
+
+    fn try_from(fields: BinaryFields) -> Result<Self, Self::Error> {
+        if !fields.dtype.is_array() {
+            return Err(format!("binary arrays can't have dtype {}, expected a numpy dtype", fields.dtype));
+        }
+        if let Some(shape) = &fields.shape {
+            if shape.iter().any(|&dim| dim < -1) || shape.iter().filter(|&&dim| dim == -1).count() > 1 {
+                return Err(format!(
+                    "invalid binary array shape {:?}, dimensions can't be negative except for a single -1",
+                    shape
+                ));
+            }
+        }
+        Ok(Binary { dtype: fields.dtype, encoding: fields.encoding, shape: fields.shape, byteorder: fields.byteorder })
+    }
+}
+
+impl<'source> FromPyObject<'source> for Binary {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let dict = object.downcast::<PyDict>()?;
+        let known = |key: &PyAny| matches!(key.extract(), Ok("dtype" | "encoding" | "shape" | "byteorder"));
+        match (dict.get_item("dtype"), dict.get_item("encoding")) {
+            (Some(dtype), Some(encoding)) if dict.keys().iter().all(known) => {
+                let fields = BinaryFields {
+                    dtype: dtype.extract()?,
+                    encoding: encoding.extract()?,
+                    shape: dict.get_item("shape").map(PyAny::extract).transpose()?,
+                    byteorder: dict.get_item("byteorder").map(PyAny::extract).transpose()?.unwrap_or_default(),
+                };
+                Binary::try_from(fields).map_err(PyValueError::new_err)
+            }
+            _ => Err(PyValueError::new_err(format!("cannot parse {} as binary array structure", object))),
+        }
+    }
+}
+
+/// How the bytes of a binary array are written in the document
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Base64,
+    /// A msgpack `bin` value
+    Bin,
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(Encoding::Base64),
+            "bin" => Ok(Encoding::Bin),
+            "hex" => Ok(Encoding::Hex),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised encoding {}. Expected one of: \"base64\", \"bin\", \"hex\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for Encoding {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        Encoding::from_str(object.extract()?)
+    }
+}
+
+/// Byte order of the values of a binary array, as given by numpy
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum ByteOrder {
+    #[default]
+    #[serde(rename = "<")]
+    Little,
+    #[serde(rename = ">")]
+    Big,
+    #[serde(rename = "=")]
+    Native,
+}
+
+impl FromStr for ByteOrder {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "<" => Ok(ByteOrder::Little),
+            ">" => Ok(ByteOrder::Big),
+            "=" => Ok(ByteOrder::Native),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised byteorder {}. Expected one of: \"<\", \">\", \"=\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for ByteOrder {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        ByteOrder::from_str(object.extract()?)
+    }
+}
+
+impl ByteOrder {
+    /// Whether the bytes of each value need to be reversed on this machine
+    fn swap(self) -> bool {
+        match self {
+            ByteOrder::Little => cfg!(target_endian = "big"),
+            ByteOrder::Big => cfg!(target_endian = "little"),
+            ByteOrder::Native => false,
+        }
+    }
+}
+
+pub struct BinarySeed<'s>(pub &'s Binary);
+
+impl<'de, 's> DeserializeSeed<'de> for BinarySeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // not `deserialize_bytes`, which serde_json answers with the bytes of a string
+        deserializer.deserialize_any(BinaryVisitor(self.0))
+    }
+}
+
+struct BinaryVisitor<'s>(&'s Binary);
+
+impl<'de, 's> Visitor<'de> for BinaryVisitor<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.encoding {
+            Encoding::Base64 => write!(formatter, "base64 string of {} values", self.0.dtype),
+            Encoding::Bin => write!(formatter, "msgpack bin value of {} values", self.0.dtype),
+            Encoding::Hex => write!(formatter, "hex string of {} values", self.0.dtype),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        match self.0.encoding {
+            Encoding::Bin => Err(de::Error::invalid_type(Unexpected::Str(value), &self)),
+            Encoding::Base64 | Encoding::Hex => self.0.decode(value.as_bytes()),
+        }
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        match self.0.encoding {
+            Encoding::Bin => self.0.decode(value),
+            Encoding::Base64 | Encoding::Hex => Err(de::Error::invalid_type(Unexpected::Bytes(value), &self)),
+        }
+    }
+}
+
+impl Binary {
+    fn decode<E: de::Error>(&self, input: &[u8]) -> Result<OutputTypes, E> {
+        let output = match self.dtype {
+            InputTypes::int8 => OutputTypes::I8(self.array(input)?),
+            InputTypes::int16 => OutputTypes::I16(self.array(input)?),
+            InputTypes::int32 => OutputTypes::I32(self.array(input)?),
+            InputTypes::int64 => OutputTypes::I64(self.array(input)?),
+
+            InputTypes::uint8 => OutputTypes::U8(self.array(input)?),
+            InputTypes::uint16 => OutputTypes::U16(self.array(input)?),
+            InputTypes::uint32 => OutputTypes::U32(self.array(input)?),
+            InputTypes::uint64 => OutputTypes::U64(self.array(input)?),
+
+            InputTypes::float16 => OutputTypes::F16(self.array(input)?),
+            InputTypes::float32 => OutputTypes::F32(self.array(input)?),
+            InputTypes::float64 => OutputTypes::F64(self.array(input)?),
+
+            InputTypes::bool_ => {
+                let (bytes, shape) = self.values::<u8, E>(input)?;
+                OutputTypes::Bool(BoolArray(Base::Array(bytes.into_iter().map(|byte| byte != 0).collect()), Some(shape)))
+            }
+            dtype => {
+                return Err(error_types::error(
+                    ErrorKind::Structure,
+                    Some("a numpy dtype".to_string()),
+                    Some(dtype.to_string()),
+                    format!("binary arrays can't have dtype {}", dtype),
+                ))
+            }
+        };
+        Ok(output)
+    }
+
+    fn array<T: Pod, E: de::Error>(&self, input: &[u8]) -> Result<Array<T>, E> {
+        let (values, shape) = self.values(input)?;
+        Ok(Array(Base::Array(values), Some(shape)))
+    }
+
+    /// Decodes the bytes straight into the buffer of the values, returning them with their shape
+    fn values<T: Pod, E: de::Error>(&self, input: &[u8]) -> Result<(Vec<T>, Vec<usize>), E> {
+        let size = std::mem::size_of::<T>();
+        // the decoded size is only known exactly after decoding base64, which can come up to 2 bytes short
+        let (min_bytes, max_bytes) = match self.encoding {
+            Encoding::Base64 => {
+                let estimate = base64::decoded_len_estimate(input.len());
+                (estimate.saturating_sub(2), estimate)
+            }
+            Encoding::Bin => (input.len(), input.len()),
+            Encoding::Hex => (input.len() / 2, input.len() / 2),
+        };
+        let capacity = limit_types::capacity(size);
+        if min_bytes / size > capacity {
+            return Err(limit_types::capacity_error(capacity));
+        }
+
+        let mut values = vec![T::zeroed(); max_bytes.div_ceil(size)];
+        let buffer: &mut [u8] = bytemuck::cast_slice_mut(&mut values);
+        let written = match self.encoding {
+            Encoding::Base64 => BASE64.decode_slice(input, buffer).map_err(|err| self.decode_error(err))?,
+            Encoding::Bin => {
+                buffer[..input.len()].copy_from_slice(input);
+                input.len()
+            }
+            Encoding::Hex => {
+                hex::decode_to_slice(input, &mut buffer[..max_bytes]).map_err(|err| self.decode_error(err))?;
+                max_bytes
+            }
+        };
+        if written % size != 0 {
+            return Err(error_types::error(
+                ErrorKind::Shape,
+                Some(format!("a multiple of {} bytes", size)),
+                Some(format!("{} bytes", written)),
+                format!("Binary array of {} bytes isn't a whole number of {} values", written, self.dtype),
+            ));
+        }
+        let len = written / size;
+        if len > capacity {
+            return Err(limit_types::capacity_error(capacity));
+        }
+        values.truncate(len);
+        if self.byteorder.swap() {
+            for value in bytemuck::cast_slice_mut::<T, u8>(&mut values).chunks_exact_mut(size) {
+                value.reverse();
+            }
+        }
+        let shape = self.resolve_shape(len)?;
+        limit_types::add_output_bytes(written);
+        Ok((values, shape))
+    }
+
+    fn decode_error<E: de::Error>(&self, err: impl fmt::Display) -> E {
+        let encoding = match self.encoding {
+            Encoding::Base64 => "base64",
+            Encoding::Bin => "bin",
+            Encoding::Hex => "hex",
+        };
+        error_types::error(
+            ErrorKind::Cast,
+            Some(format!("{} string", encoding)),
+            None,
+            format!("Invalid {} string: {}", encoding, err),
+        )
+    }
+
+    /// The shape of an array of `len` values, filling in the -1 dimension
+    fn resolve_shape<E: de::Error>(&self, len: usize) -> Result<Vec<usize>, E> {
+        let shape = match &self.shape {
+            None => return Ok(vec![len]),
+            Some(shape) => shape,
+        };
+        let known: usize = shape.iter().filter(|&&dim| dim >= 0).map(|&dim| dim as usize).product();
+        let resolved = shape
+            .iter()
+            .map(|&dim| match dim {
+                -1 if known > 0 && len.is_multiple_of(known) => Some(len / known),
+                -1 => None,
+                dim => Some(dim as usize),
+            })
+            .collect::<Option<Vec<usize>>>();
+        match resolved {
+            Some(resolved) if resolved.iter().product::<usize>() == len => Ok(resolved),
+            _ => Err(error_types::error(
+                ErrorKind::Shape,
+                Some(format!("shape {:?}", shape)),
+                Some(format!("{} values", len)),
+                format!("Binary array of {} values can't have shape {:?}", len, shape),
+            )),
+        }
+    }
+}
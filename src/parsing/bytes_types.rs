@@ -0,0 +1,194 @@
+use std::fmt;
+
+use base64::Engine;
+use numpy::PyArray1;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList};
+use serde::de;
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::errors::{new_err, ErrorKind};
+use crate::parsing::array_types::stack;
+use crate::parsing::binary_types::BASE64;
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::ArrayFormat;
+
+/// An array of binary strings, such as a KServe v2 `BYTES` tensor or a TF Serving string tensor, whose elements are
+/// given as strings (or msgpack `bin` values) and returned as `bytes`
+#[derive(Debug, PartialEq)]
+pub struct Bytes {
+    pub values: Vec<Vec<u8>>,
+    /// A scalar for the empty shape
+    pub shape: Vec<usize>,
+}
+
+impl Bytes {
+    pub fn new() -> Bytes {
+        Bytes { values: vec![], shape: vec![0] }
+    }
+
+    pub fn reshape<E: de::Error>(self, shape: Vec<usize>) -> Result<Bytes, E> {
+        let len = self.values.len();
+        if shape.iter().product::<usize>() != len {
+            return Err(error_types::error(
+                ErrorKind::Shape,
+                Some(format!("shape {:?}", shape)),
+                Some(format!("{} values", len)),
+                format!("Cannot reshape {} values into shape {:?}", len, shape),
+            ));
+        }
+        Ok(Bytes { values: self.values, shape })
+    }
+
+    /// Appends an array (or scalar) as the next item along the first axis, see `array_types::stack`
+    pub fn stack<E: de::Error>(&mut self, item: Bytes) -> Result<(), E> {
+        stack(&mut self.values, &mut self.shape, item.values, &item.shape)
+    }
+
+    /// A numpy array of `bytes` objects, or nested lists of them with `arrays="dlpack"` or `"torch"` which can't
+    /// hold objects
+    pub fn into_py_with(self, py: Python, format: ArrayFormat) -> PyResult<PyObject> {
+        let len = self.values.len();
+        if self.shape.iter().product::<usize>() != len {
+            return Err(new_err(
+                py,
+                ErrorKind::Shape,
+                format!("Irregular shape found cannot parse as bytes array. Expected shape: {:?}  Total elements: {}", self.shape, len),
+            ));
+        }
+        let mut values = self.values.into_iter().map(|value| PyBytes::new(py, &value).into_py(py));
+        if self.shape.is_empty() {
+            return Ok(values.next().unwrap_or_else(|| py.None()));
+        }
+        match format {
+            ArrayFormat::Numpy => {
+                let array = PyArray1::from_vec(py, values.collect());
+                Ok(array.reshape(self.shape)?.into_py(py))
+            }
+            ArrayFormat::DLPack | ArrayFormat::Torch => Ok(nest(py, &mut values, &self.shape)),
+        }
+    }
+}
+
+fn nest(py: Python, values: &mut impl Iterator<Item = PyObject>, shape: &[usize]) -> PyObject {
+    match shape.split_first() {
+        Some((&len, [])) => PyList::new(py, values.take(len).collect::<Vec<_>>()).into_py(py),
+        Some((&len, inner)) => PyList::new(py, (0..len).map(|_| nest(py, values, inner))).into_py(py),
+        None => py.None(),
+    }
+}
+
+/// Parses a string, or nested lists of them, into `Bytes`. With `b64` an element may also be a `{"b64": ...}` map
+/// holding base64 encoded binary data, as in TF Serving requests
+pub struct BytesSeed {
+    pub b64: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for BytesSeed {
+    type Value = Bytes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut values = Vec::new();
+        let mut shape = Vec::new();
+        let builder = BytesBuilder { values: &mut values, shape: &mut shape, compute_shape: true, b64: self.b64 };
+        builder.deserialize(deserializer)?;
+        shape.reverse();
+        Ok(Bytes { values, shape })
+    }
+}
+
+/// Collects the elements into `values`, flattening nested lists. The shape is read from the first element of each
+/// list, innermost dimension first
+struct BytesBuilder<'a> {
+    values: &'a mut Vec<Vec<u8>>,
+    shape: &'a mut Vec<usize>,
+    compute_shape: bool,
+    b64: bool,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for BytesBuilder<'a> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'a> BytesBuilder<'a> {
+    /// Appends an element, counting it towards the limits
+    fn push<E: de::Error>(self, value: Vec<u8>) -> Result<(), E> {
+        limit_types::reserve_output_bytes(value.len() + std::mem::size_of::<Vec<u8>>())?;
+        self.values.push(value);
+        limit_types::check_elements(self.values.len())
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for BytesBuilder<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.b64 {
+            true => formatter.write_str("list of strings or {\"b64\": string} values"),
+            false => formatter.write_str("list of strings"),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        self.push(value.as_bytes().to_vec())
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        self.push(value.to_vec())
+    }
+
+    /// `{"b64": "aGVsbG8="}`
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let encoded = match (self.b64, map.next_key::<String>()?.as_deref()) {
+            (true, Some("b64")) => map.next_value::<String>().map_err(error_types::at_key("b64"))?,
+            _ => return Err(de::Error::invalid_type(de::Unexpected::Map, &self)),
+        };
+        if map.next_key::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_type(de::Unexpected::Map, &self));
+        }
+        let decoded = BASE64.decode(encoded.as_bytes()).map_err(|err| {
+            error_types::at_key("b64")(error_types::error(
+                ErrorKind::Cast,
+                Some("base64 string".to_string()),
+                None,
+                format!("Invalid base64 string: {}", err),
+            ))
+        })?;
+        self.push(decoded)
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut index = 0;
+        let mut compute_shape = self.compute_shape;
+        while seq
+            .next_element_seed(BytesBuilder { values: self.values, shape: self.shape, compute_shape, b64: self.b64 })
+            .map_err(error_types::at_index(index))?
+            .is_some()
+        {
+            index += 1;
+            compute_shape = false;
+        }
+        if self.compute_shape {
+            self.shape.push(index);
+        }
+        Ok(())
+    }
+}
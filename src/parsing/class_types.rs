@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter};
+
+use indexmap::IndexMap;
+use serde::de::{DeserializeSeed, Deserializer, SeqAccess, Visitor};
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyTuple, PyType};
+
+use crate::parsing::map_types::{MapSeed, MapStructure};
+use crate::parsing::plan_types::Plan;
+use crate::parsing::error_types::{self, PathSegment};
+use crate::parsing::{OutputTypes, Structure, StructureSeed};
+
+/// A dataclass, NamedTuple, attrs class or TypedDict whose annotated fields describe a map
+#[derive(Clone, Debug)]
+pub struct ClassStructure {
+    pub cls: PythonClass,
+    pub fields: MapStructure,
+}
+
+impl<'source> FromPyObject<'source> for ClassStructure {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let py = object.py();
+        let cls = object.downcast::<PyType>()?;
+        let names: Vec<String> = if py
+            .import("dataclasses")?
+            .call_method1("is_dataclass", (cls,))?
+            .is_true()?
+        {
+            py.import("dataclasses")?
+                .call_method1("fields", (cls,))?
+                .iter()?
+                .map(|field| field?.getattr("name")?.extract())
+                .collect::<PyResult<_>>()?
+        } else if cls.hasattr("__attrs_attrs__")? {
+            cls.getattr("__attrs_attrs__")?
+                .iter()?
+                .map(|attribute| attribute?.getattr("name")?.extract())
+                .collect::<PyResult<_>>()?
+        } else if cls.is_subclass_of::<PyTuple>()? && cls.hasattr("_fields")? {
+            cls.getattr("_fields")?.extract()?
+        } else if cls.is_subclass_of::<PyDict>()? && cls.hasattr("__annotations__")? {
+            cls.getattr("__annotations__")?.extract::<&PyDict>()?.keys().extract()?
+        } else {
+            return Err(PyValueError::new_err(format!(
+                "cannot parse {} as class structure. Expected a dataclass, NamedTuple, attrs class or TypedDict",
+                cls
+            )));
+        };
+
+        let annotations: &PyDict = py
+            .import("typing")?
+            .call_method1("get_type_hints", (cls,))?
+            .extract()?;
+        let mut fields = IndexMap::with_capacity(names.len());
+        for name in names {
+            let annotation = annotations.get_item(name.as_str()).ok_or_else(|| {
+                PyValueError::new_err(format!("field {} of {} has no type annotation", name, cls))
+            })?;
+            fields.insert(name, annotation.extract()?);
+        }
+        Ok(ClassStructure {
+            cls: PythonClass::register(cls)?,
+            fields: MapStructure::new(fields),
+        })
+    }
+}
+
+thread_local! {
+    // the classes of the structure being extracted by `extract_with_classes`
+    static INTERNED: RefCell<Option<Vec<Py<PyType>>>> = const { RefCell::new(None) };
+}
+
+/// Extracts a structure from python, along with the classes it references. The classes are owned by the
+/// deserializer so that they are released with it, and the structure refers to them by index which keeps
+/// `Structure` and `OutputTypes` plain rust data
+pub fn extract_with_classes<'source, T: FromPyObject<'source>>(object: &'source PyAny) -> PyResult<(T, Vec<Py<PyType>>)> {
+    let outer = INTERNED.with(|interned| interned.replace(Some(Vec::new())));
+    let result = object.extract();
+    let classes = INTERNED.with(|interned| interned.replace(outer)).unwrap_or_default();
+    result.map(|structure| (structure, classes))
+}
+
+/// Handle to a class of the structure that the deserialized fields are passed to as keyword arguments
+#[derive(Clone, Debug, PartialEq)]
+pub struct PythonClass {
+    index: usize,
+    name: String,
+}
+
+impl PythonClass {
+    fn register(cls: &PyType) -> PyResult<Self> {
+        let name = cls.name()?.to_string();
+        let index = INTERNED.with(|interned| match &mut *interned.borrow_mut() {
+            Some(classes) => Ok(match classes.iter().position(|registered| registered.is(cls)) {
+                Some(index) => index,
+                None => {
+                    classes.push(cls.into());
+                    classes.len() - 1
+                }
+            }),
+            None => Err(PyValueError::new_err(format!("class {} must be extracted with extract_with_classes", name))),
+        })?;
+        Ok(PythonClass { index, name })
+    }
+
+    pub fn instantiate(&self, py: Python, classes: &[Py<PyType>], kwargs: &PyDict) -> PyResult<PyObject> {
+        match classes.get(self.index) {
+            Some(cls) => Ok(cls.as_ref(py).call((), Some(kwargs))?.into()),
+            None => Err(PyValueError::new_err(format!("class {} is not registered", self.name))),
+        }
+    }
+}
+
+impl Display for PythonClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// `List[X]` (or `list[X]`) in a class annotation: a list whose elements each follow the structure X, such as a
+/// list of dataclasses
+#[derive(Clone, Debug)]
+pub struct SequenceStructure(pub Box<Structure>);
+
+impl<'source> FromPyObject<'source> for SequenceStructure {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let typing = object.py().import("typing")?;
+        let origin = typing.call_method1("get_origin", (object,))?;
+        let args = typing.call_method1("get_args", (object,))?.downcast::<PyTuple>()?;
+        match (origin.is(object.py().get_type::<PyList>()), args.len()) {
+            (true, 1) => Ok(SequenceStructure(Box::new(args.get_item(0)?.extract()?))),
+            _ => Err(PyValueError::new_err(format!("cannot parse {} as List[...] structure", object))),
+        }
+    }
+}
+
+pub struct SequenceSeed<'s>(pub &'s Structure);
+
+impl<'de, 's> DeserializeSeed<'de> for SequenceSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's> Visitor<'de> for SequenceSeed<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "list of {:?}", self.0)
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        let holds_records = self.0.holds_records();
+        loop {
+            let record_errors = holds_records.then(error_types::record_errors_len);
+            let Some(value) = seq.next_element_seed(StructureSeed(self.0)).map_err(error_types::at_index(out.len()))? else { break };
+            if let Some(record_errors) = record_errors {
+                error_types::prefix_record_errors(record_errors, || PathSegment::Index(out.len()));
+            }
+            out.push(value);
+        }
+        Ok(OutputTypes::List(out))
+    }
+}
+
+/// Deserializes a map into an instance of the class, its fields parsed with their plans for this document if any
+pub struct ClassSeed<'s>(pub &'s ClassStructure, pub Vec<Option<Plan>>);
+
+impl<'de, 's> DeserializeSeed<'de> for ClassSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ClassStructure { cls, fields } = self.0;
+        Ok(OutputTypes::Instance(cls.clone(), MapSeed(fields, self.1).deserialize(deserializer)?))
+    }
+}
@@ -0,0 +1,177 @@
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::str::FromStr;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::ffi;
+
+/// Which python objects arrays are returned as
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArrayFormat {
+    #[default]
+    Numpy,
+    DLPack,
+    Torch,
+}
+
+impl FromStr for ArrayFormat {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numpy" => Ok(ArrayFormat::Numpy),
+            "dlpack" => Ok(ArrayFormat::DLPack),
+            "torch" => Ok(ArrayFormat::Torch),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised array format {}. Expected one of: \"numpy\", \"dlpack\", \"torch\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for ArrayFormat {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        ArrayFormat::from_str(object.extract()?)
+    }
+}
+
+// Structs from dlpack.h (v0.8), see https://github.com/dmlc/dlpack/blob/main/include/dlpack/dlpack.h
+
+const DL_CPU: i32 = 1;
+
+const DL_INT: u8 = 0;
+const DL_UINT: u8 = 1;
+const DL_FLOAT: u8 = 2;
+const DL_BOOL: u8 = 6;
+
+const DLTENSOR_NAME: &[u8] = b"dltensor\0";
+
+#[repr(C)]
+struct DLDevice {
+    device_type: i32,
+    device_id: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DLDataType {
+    code: u8,
+    bits: u8,
+    lanes: u16,
+}
+
+#[repr(C)]
+struct DLTensor {
+    data: *mut c_void,
+    device: DLDevice,
+    ndim: i32,
+    dtype: DLDataType,
+    shape: *mut i64,
+    strides: *mut i64,
+    byte_offset: u64,
+}
+
+#[repr(C)]
+struct DLManagedTensor {
+    dl_tensor: DLTensor,
+    manager_ctx: *mut c_void,
+    deleter: Option<unsafe extern "C" fn(*mut DLManagedTensor)>,
+}
+
+/// Element types which can be described by a `DLDataType`
+pub trait DLPackElement: Sized {
+    const DTYPE: DLDataType;
+}
+
+macro_rules! impl_dlpack_element {
+    ($type:ty, $code:expr) => {
+        impl DLPackElement for $type {
+            const DTYPE: DLDataType = DLDataType {
+                code: $code,
+                bits: (std::mem::size_of::<$type>() * 8) as u8,
+                lanes: 1,
+            };
+        }
+    };
+}
+
+impl_dlpack_element!(i8, DL_INT);
+impl_dlpack_element!(i16, DL_INT);
+impl_dlpack_element!(i32, DL_INT);
+impl_dlpack_element!(i64, DL_INT);
+impl_dlpack_element!(u8, DL_UINT);
+impl_dlpack_element!(u16, DL_UINT);
+impl_dlpack_element!(u32, DL_UINT);
+impl_dlpack_element!(u64, DL_UINT);
+impl_dlpack_element!(half::f16, DL_FLOAT);
+impl_dlpack_element!(f32, DL_FLOAT);
+impl_dlpack_element!(f64, DL_FLOAT);
+impl_dlpack_element!(bool, DL_BOOL);
+
+/// Owns the buffers the `DLTensor` points into until the consumer calls the deleter
+struct DLPackContext<T> {
+    _data: Vec<T>,
+    _shape: Vec<i64>,
+}
+
+unsafe extern "C" fn delete_managed_tensor<T>(managed: *mut DLManagedTensor) {
+    let managed = Box::from_raw(managed);
+    drop(Box::from_raw(managed.manager_ctx as *mut DLPackContext<T>));
+}
+
+/// Called when the capsule is garbage collected. Consumers rename the capsule to "used_dltensor" and take
+/// over calling the deleter, so the tensor is only freed here if it was never consumed
+unsafe extern "C" fn capsule_destructor(capsule: *mut ffi::PyObject) {
+    let name = DLTENSOR_NAME.as_ptr() as *const c_char;
+    if ffi::PyCapsule_IsValid(capsule, name) == 1 {
+        let managed = ffi::PyCapsule_GetPointer(capsule, name) as *mut DLManagedTensor;
+        if let Some(deleter) = (*managed).deleter {
+            deleter(managed);
+        }
+    }
+}
+
+/// Hands a C-contiguous buffer over to a DLPack capsule without copying. The shape must already be validated
+pub fn into_dlpack<T: DLPackElement>(py: Python, mut data: Vec<T>, shape: &[usize]) -> PyResult<PyObject> {
+    let mut shape: Vec<i64> = shape.iter().map(|&dim| dim as i64).collect();
+    let managed = Box::new(DLManagedTensor {
+        dl_tensor: DLTensor {
+            data: data.as_mut_ptr() as *mut c_void,
+            device: DLDevice { device_type: DL_CPU, device_id: 0 },
+            ndim: shape.len() as i32,
+            dtype: T::DTYPE,
+            shape: shape.as_mut_ptr(),
+            // NULL strides means the tensor is compact and row-major
+            strides: std::ptr::null_mut(),
+            byte_offset: 0,
+        },
+        manager_ctx: Box::into_raw(Box::new(DLPackContext { _data: data, _shape: shape })) as *mut c_void,
+        deleter: Some(delete_managed_tensor::<T>),
+    });
+    let managed = Box::into_raw(managed);
+    unsafe {
+        let capsule = ffi::PyCapsule_New(
+            managed as *mut c_void,
+            DLTENSOR_NAME.as_ptr() as *const c_char,
+            Some(capsule_destructor),
+        );
+        if capsule.is_null() {
+            delete_managed_tensor::<T>(managed);
+            return Err(PyErr::fetch(py));
+        }
+        Ok(PyObject::from_owned_ptr(py, capsule))
+    }
+}
+
+/// Converts a buffer into the requested non-numpy format
+pub fn into_tensor<T: DLPackElement>(py: Python, data: Vec<T>, shape: &[usize], format: ArrayFormat) -> PyResult<PyObject> {
+    let capsule = into_dlpack(py, data, shape)?;
+    match format {
+        ArrayFormat::Torch => Ok(py
+            .import("torch.utils.dlpack")?
+            .call_method1("from_dlpack", (capsule,))?
+            .into()),
+        _ => Ok(capsule),
+    }
+}
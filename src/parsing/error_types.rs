@@ -0,0 +1,295 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+
+use crate::errors::{ErrorAttributes, ErrorKind};
+
+/// What happens to a record of a transposed list (`[[Type, ...]]` and `[{key: Type, ...}]`) which fails to deserialize
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnError {
+    /// Abort and raise the error
+    #[default]
+    Raise,
+    /// Drop the record
+    Skip,
+    /// Keep the record with every column set to its missing value (NaN, 0, False or None)
+    Fill,
+}
+
+impl FromStr for OnError {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raise" => Ok(OnError::Raise),
+            "skip" => Ok(OnError::Skip),
+            "fill" => Ok(OnError::Fill),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised on_error {}. Expected one of: \"raise\", \"skip\", \"fill\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for OnError {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        OnError::from_str(object.extract()?)
+    }
+}
+
+/// A record that was skipped or filled
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordError {
+    /// Position of the record in its list
+    pub index: usize,
+    pub error: DeserializeError,
+}
+
+impl IntoPy<PyResult<PyObject>> for RecordError {
+    fn into_py(self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("index", self.index)?;
+        dict.set_item("path", self.error.path.to_string())?;
+        dict.set_item("message", self.error.message)?;
+        Ok(dict.into())
+    }
+}
+
+/// One step from a container into one of its values
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// What went wrong, recorded by the visitors when they raise an error since serde errors only carry a message
+#[derive(Clone, Debug, PartialEq)]
+struct ErrorDetail {
+    kind: ErrorKind,
+    expected: Option<String>,
+    found: Option<String>,
+}
+
+thread_local! {
+    // Segments are only pushed while an error unwinds out of the visitors, so parsing that succeeds never
+    // touches this. As a consequence the segments are stored innermost first
+    static ERROR_PATH: RefCell<Vec<PathSegment>> = const { RefCell::new(Vec::new()) };
+    static ERROR_DETAIL: RefCell<Option<ErrorDetail>> = const { RefCell::new(None) };
+    static ON_ERROR: Cell<OnError> = const { Cell::new(OnError::Raise) };
+    static RECORD_ERRORS: RefCell<Vec<RecordError>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Forgets any error left over from a previous document and sets how failing records are handled,
+/// call before parsing a document
+pub fn start(on_error: OnError) {
+    ERROR_PATH.with(|path| path.borrow_mut().clear());
+    ERROR_DETAIL.with(|detail| detail.borrow_mut().take());
+    ON_ERROR.with(|cell| cell.set(on_error));
+    RECORD_ERRORS.with(|errors| errors.borrow_mut().clear());
+}
+
+pub fn on_error() -> OnError {
+    ON_ERROR.with(Cell::get)
+}
+
+/// Records that the record at `index` of a transposed list failed with `message` and was skipped or filled
+pub fn record_error(index: usize, message: String) {
+    ERROR_PATH.with(|path| path.borrow_mut().push(PathSegment::Index(index)));
+    let error = DeserializeError::take(ErrorKind::Structure, message);
+    RECORD_ERRORS.with(|errors| errors.borrow_mut().push(RecordError { index, error }));
+}
+
+/// Number of records skipped or filled so far, to pass to `prefix_record_errors` or `truncate_record_errors`
+pub fn record_errors_len() -> usize {
+    RECORD_ERRORS.with(|errors| errors.borrow().len())
+}
+
+/// Prepends `segment` to the paths of the records skipped or filled since there were `len`. A container calls it
+/// once its value at `segment` was parsed, as the paths of errors that unwind are built with `at_key` and `at_index`
+pub fn prefix_record_errors(len: usize, segment: impl FnOnce() -> PathSegment) {
+    RECORD_ERRORS.with(|errors| {
+        let mut errors = errors.borrow_mut();
+        if let Some(added) = errors.get_mut(len..).filter(|added| !added.is_empty()) {
+            let segment = segment();
+            for error in added {
+                error.error.path.0.insert(0, segment.clone());
+            }
+        }
+    })
+}
+
+/// Forgets the records skipped or filled since there were `len`, when the value holding them was discarded
+pub fn truncate_record_errors(len: usize) {
+    RECORD_ERRORS.with(|errors| errors.borrow_mut().truncate(len));
+}
+
+/// Whether records were skipped or filled since they were last taken
+pub fn has_record_errors() -> bool {
+    RECORD_ERRORS.with(|errors| !errors.borrow().is_empty())
+}
+
+/// Takes the records skipped or filled while parsing the last document
+pub fn take_record_errors() -> Vec<RecordError> {
+    RECORD_ERRORS.with(|errors| std::mem::take(&mut *errors.borrow_mut()))
+}
+
+/// Whether the error unwinding is a limit violation, which is raised whatever `on_error` is
+pub fn is_limit_error() -> bool {
+    ERROR_DETAIL.with(|detail| matches!(&*detail.borrow(), Some(ErrorDetail { kind: ErrorKind::Limit, .. })))
+}
+
+/// Creates a serde error and records its kind for the exception raised to python
+pub fn error<E: de::Error>(kind: ErrorKind, expected: Option<String>, found: Option<String>, message: impl Display) -> E {
+    ERROR_DETAIL.with(|detail| *detail.borrow_mut() = Some(ErrorDetail { kind, expected, found }));
+    E::custom(message)
+}
+
+/// Wraps an error from deserializing a value as `expected`, keeping what was found from serde's message.
+/// Errors that aren't type mismatches (e.g. a syntax error inside the value) are passed through unchanged
+pub fn cast_error<E: de::Error>(expected: &str, err: E) -> E {
+    match parse_serde_message(&err.to_string()) {
+        Some((found, _)) => {
+            let kind = if is_container(&found) { ErrorKind::Structure } else { ErrorKind::Cast };
+            error(kind, Some(expected.to_string()), Some(found), format!("Could not deserialize as {}", expected))
+        }
+        None => err,
+    }
+}
+
+/// Splits serde's "invalid type: {found}, expected {expected}" messages (also "invalid value" and "invalid length")
+fn parse_serde_message(message: &str) -> Option<(String, String)> {
+    let rest = ["invalid type: ", "invalid value: ", "invalid length "]
+        .iter()
+        .find_map(|prefix| message.strip_prefix(prefix))?;
+    let (found, expected) = rest.split_once(", expected ")?;
+    Some((found.to_string(), expected.to_string()))
+}
+
+/// Whether serde describes a map or sequence (rather than a scalar), making a type mismatch a structure error
+fn is_container(description: &str) -> bool {
+    ["map", "sequence", "a sequence", "tuple"]
+        .iter()
+        .any(|container| description.starts_with(container))
+}
+
+/// Number of segments recorded so far. Used by `oneOf` to discard paths from alternatives that failed
+pub fn depth() -> usize {
+    ERROR_PATH.with(|path| path.borrow().len())
+}
+
+/// Discards the path below `depth` and the recorded detail of an error that was handled
+pub fn truncate(depth: usize) {
+    ERROR_PATH.with(|path| path.borrow_mut().truncate(depth));
+    ERROR_DETAIL.with(|detail| detail.borrow_mut().take());
+}
+
+/// For use with `map_err` when the value of `key` failed to deserialize
+pub fn at_key<E>(key: &str) -> impl FnOnce(E) -> E + '_ {
+    move |err| {
+        ERROR_PATH.with(|path| path.borrow_mut().push(PathSegment::Key(key.to_string())));
+        err
+    }
+}
+
+/// For use with `map_err` when the element at `index` failed to deserialize
+pub fn at_index<E>(index: usize) -> impl FnOnce(E) -> E {
+    move |err| {
+        ERROR_PATH.with(|path| path.borrow_mut().push(PathSegment::Index(index)));
+        err
+    }
+}
+
+/// Path from the root of the document to the value that failed, e.g. `$.records[1532].bbox[2]`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ErrorPath(pub Vec<PathSegment>);
+
+impl ErrorPath {
+    /// Takes the path recorded by the last error
+    pub fn take() -> ErrorPath {
+        let mut segments = ERROR_PATH.with(|path| std::mem::take(&mut *path.borrow_mut()));
+        segments.reverse();
+        ErrorPath(segments)
+    }
+}
+
+impl Display for ErrorPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "$")?;
+        for segment in &self.0 {
+            match segment {
+                PathSegment::Key(key) if key.chars().all(|c| c.is_alphanumeric() || c == '_') && !key.is_empty() => {
+                    write!(f, ".{}", key)?
+                }
+                PathSegment::Key(key) => write!(f, "[{:?}]", key)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error from deserializing a document, with everything known about where and why it happened
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeserializeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub path: ErrorPath,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl DeserializeError {
+    /// Builds the error from the message given by the backend and the path and detail recorded while it unwound.
+    /// `kind` is used when the visitors didn't record one (i.e. the backend raised the error itself)
+    pub fn take(kind: ErrorKind, message: String) -> DeserializeError {
+        let path = ErrorPath::take();
+        let detail = ERROR_DETAIL.with(|detail| detail.borrow_mut().take());
+        let (kind, expected, found) = match (detail, parse_serde_message(&message)) {
+            (Some(detail), _) => (detail.kind, detail.expected, detail.found),
+            (None, Some((found, expected))) => {
+                let kind = if is_container(&found) || is_container(&expected) || message.starts_with("invalid length") {
+                    ErrorKind::Structure
+                } else {
+                    ErrorKind::Cast
+                };
+                (kind, Some(expected), Some(found))
+            }
+            (None, None) => (kind, None, None),
+        };
+        DeserializeError { kind, message, path, expected, found, line: None, column: None, offset: None }
+    }
+
+    pub fn attributes(&self) -> ErrorAttributes {
+        ErrorAttributes {
+            path: Some(self.path.to_string()),
+            expected: self.expected.clone(),
+            found: self.found.clone(),
+            line: self.line,
+            column: self.column,
+            offset: self.offset,
+        }
+    }
+}
+
+impl Display for DeserializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (self.line, self.column, self.offset) {
+            (Some(line), Some(column), _) => write!(f, " at line {} column {}", line, column)?,
+            (_, _, Some(offset)) => write!(f, " at byte {}", offset)?,
+            _ => {}
+        }
+        if !self.path.0.is_empty() {
+            write!(f, " (path: {})", self.path)?;
+        }
+        Ok(())
+    }
+}
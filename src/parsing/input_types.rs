@@ -0,0 +1,159 @@
+use std::io::{self, Read};
+
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use crate::compression::{Compression, CompressionError, Decoder, MAGIC_SIZE};
+
+/// Bytes read from a file object at a time, each read takes the GIL
+const READ_SIZE: usize = 1 << 16;
+
+/// A document passed in from python: `bytes` or `str`, any other object exposing its bytes through the buffer
+/// protocol (`bytearray`, `memoryview`, `mmap`, numpy uint8 arrays), or a binary file object which is read
+/// incrementally
+pub enum Document<'py> {
+    Bytes(&'py [u8]),
+    Buffer(PyBuffer<u8>),
+    /// A file object, after the bytes that were already read from it to recognise its compression
+    File(&'py PyAny, Vec<u8>),
+}
+
+impl<'source> FromPyObject<'source> for Document<'source> {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        if let Ok(bytes) = object.downcast::<PyBytes>() {
+            Ok(Document::Bytes(bytes.as_bytes()))
+        } else if let Ok(string) = object.downcast::<PyString>() {
+            Ok(Document::Bytes(string.to_str()?.as_bytes()))
+        } else if let Ok(buffer) = PyBuffer::<u8>::get(object) {
+            if buffer.is_c_contiguous() {
+                Ok(Document::Buffer(buffer))
+            } else {
+                Err(PyValueError::new_err("buffer must be C-contiguous"))
+            }
+        } else if object.hasattr("read")? {
+            Ok(Document::File(object, Vec::new()))
+        } else {
+            Err(PyTypeError::new_err(format!(
+                "expected bytes, str, a buffer of bytes or a binary file object, found: {}",
+                object.get_type().name()?
+            )))
+        }
+    }
+}
+
+impl<'py> Document<'py> {
+    /// The whole document, `None` for a file object which has to be read instead
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Document::Bytes(bytes) => Some(bytes),
+            // a writable buffer is only read while the GIL is held, so nothing can mutate it meanwhile
+            Document::Buffer(buffer) => {
+                Some(unsafe { std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, buffer.len_bytes()) })
+            }
+            Document::File(..) => None,
+        }
+    }
+
+    /// Whether python code can't change the document while the GIL is released
+    fn is_immutable(&self) -> bool {
+        match self {
+            Document::Buffer(buffer) => buffer.readonly(),
+            Document::File(..) => false,
+            Document::Bytes(_) => true,
+        }
+    }
+
+    /// Reads the document, incrementally from the file object. A document in memory is copied, so that the
+    /// reader can be moved to another thread
+    pub fn reader(&self) -> Box<dyn io::Read + Send> {
+        match self {
+            Document::File(file, head) => Box::new(
+                io::Cursor::new(head.clone())
+                    .chain(io::BufReader::with_capacity(READ_SIZE, PyFileReader((*file).into()))),
+            ),
+            document => Box::new(io::Cursor::new(document.as_bytes().unwrap_or_default().to_vec())),
+        }
+    }
+
+    /// Parses the document with `from_slice`, or with `from_reader` when it is read from a file object or is
+    /// compressed, which `Compression::Auto` recognises from its first bytes. A compressed document is
+    /// decompressed while it is parsed, with the GIL released unless python code could change it meanwhile, and
+    /// can't be larger than `max_size` bytes decompressed
+    pub fn parse<T: Send>(
+        self,
+        py: Python,
+        compression: Compression,
+        max_size: Option<usize>,
+        from_slice: impl FnOnce(&[u8]) -> T + Send,
+        from_reader: impl FnOnce(&mut dyn io::Read) -> T + Send,
+    ) -> Result<T, CompressionError> {
+        match self {
+            Document::File(file, mut head) => {
+                (&mut PyFileReader(file.into()))
+                    .take((MAGIC_SIZE - head.len()) as u64)
+                    .read_to_end(&mut head)
+                    .map_err(|err| CompressionError::Read(err.to_string()))?;
+                let compression = compression.resolve(&head)?;
+                let document = Document::File(file, head);
+                match Decoder::new(compression, document.reader(), max_size) {
+                    // the file object is read taking the GIL for each read
+                    Some(decoder) => py.allow_threads(|| decoder.parse(from_reader)),
+                    None => Ok(from_reader(&mut document.reader())),
+                }
+            }
+            document => {
+                let data = document.as_bytes().unwrap_or_default();
+                match Decoder::new(compression.resolve(data)?, data, max_size) {
+                    Some(decoder) if document.is_immutable() => py.allow_threads(|| decoder.parse(from_reader)),
+                    Some(decoder) => decoder.parse(from_reader),
+                    None => Ok(from_slice(data)),
+                }
+            }
+        }
+    }
+}
+
+/// Reads a python file-like object in binary mode through its `read` method, taking the GIL for each read
+pub struct PyFileReader(pub PyObject);
+
+impl io::Read for PyFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Python::with_gil(|py| {
+            let data = self
+                .0
+                .call_method1(py, "read", (buf.len(),))
+                .map_err(|err| io::Error::other(err.to_string()))?;
+            let bytes = data.as_ref(py).downcast::<PyBytes>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "file must be opened in binary mode, read() didn't return bytes")
+            })?;
+            let bytes = bytes.as_bytes();
+            if bytes.len() > buf.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "read() returned more bytes than requested"));
+            }
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Ok(bytes.len())
+        })
+    }
+}
+
+/// Counts the bytes read, to give the offset of an error in a document that is read incrementally
+pub struct CountingReader<R> {
+    inner: R,
+    pub count: usize,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        CountingReader { inner, count: 0 }
+    }
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
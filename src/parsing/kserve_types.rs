@@ -0,0 +1,262 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::errors::ErrorKind;
+use crate::parsing::bytes_types::BytesSeed;
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::reshape_types::reshape;
+use crate::parsing::union_types::{Content, ContentDeserializer};
+use crate::parsing::{InputTypes, OutputTypes, TypeDescriptor};
+
+/// `{"kserve_v2": "inputs"}`: an Open Inference Protocol (KServe v2) request, or a response with `"outputs"`, whose
+/// tensors `{"name": "x", "shape": [2, 3], "datatype": "FP32", "data": [...]}` become a dict of arrays by name.
+/// The other fields of the message and the parameters of the tensors are ignored
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KServe {
+    #[serde(rename = "kserve_v2")]
+    pub tensors_key: String,
+}
+
+impl<'source> FromPyObject<'source> for KServe {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let dict = object.downcast::<PyDict>()?;
+        match (dict.len(), dict.get_item("kserve_v2")) {
+            (1, Some(tensors_key)) => Ok(KServe { tensors_key: tensors_key.extract()? }),
+            _ => Err(PyValueError::new_err(format!("cannot parse {} as kserve_v2 structure", object))),
+        }
+    }
+}
+
+/// The `datatype` of a tensor
+#[derive(Clone, Copy, Debug)]
+enum Datatype {
+    Array(InputTypes),
+    Bytes,
+}
+
+fn parse_datatype<E: de::Error>(name: &str) -> Result<Datatype, E> {
+    let datatype = match name {
+        "BOOL" => Datatype::Array(InputTypes::bool_),
+        "UINT8" => Datatype::Array(InputTypes::uint8),
+        "UINT16" => Datatype::Array(InputTypes::uint16),
+        "UINT32" => Datatype::Array(InputTypes::uint32),
+        "UINT64" => Datatype::Array(InputTypes::uint64),
+        "INT8" => Datatype::Array(InputTypes::int8),
+        "INT16" => Datatype::Array(InputTypes::int16),
+        "INT32" => Datatype::Array(InputTypes::int32),
+        "INT64" => Datatype::Array(InputTypes::int64),
+        "FP16" => Datatype::Array(InputTypes::float16),
+        "FP32" => Datatype::Array(InputTypes::float32),
+        "FP64" => Datatype::Array(InputTypes::float64),
+        "BYTES" => Datatype::Bytes,
+        _ => {
+            return Err(error_types::error(
+                ErrorKind::Cast,
+                Some("a KServe v2 datatype".to_string()),
+                Some(name.to_string()),
+                format!("Unsupported KServe v2 datatype {:?}", name),
+            ))
+        }
+    };
+    Ok(datatype)
+}
+
+pub struct KServeSeed<'s>(pub &'s KServe);
+
+impl<'de, 's> DeserializeSeed<'de> for KServeSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(KServeVisitor(self.0))
+    }
+}
+
+struct KServeVisitor<'s>(&'s KServe);
+
+impl<'de, 's> Visitor<'de> for KServeVisitor<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "KServe v2 message with a list of tensors under {:?}", self.0.tensors_key)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut tensors = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.0.tensors_key {
+                tensors = Some(map.next_value_seed(TensorsSeed).map_err(error_types::at_key(&key))?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        match tensors {
+            Some(tensors) => Ok(OutputTypes::Map(tensors.into_iter().collect())),
+            None => Err(missing_keys_error(vec![self.0.tensors_key.as_str()])),
+        }
+    }
+}
+
+fn missing_keys_error<E: de::Error>(not_seen_keys: Vec<&str>) -> E {
+    error_types::error(
+        ErrorKind::MissingKey,
+        Some(format!("{not_seen_keys:?}")),
+        None,
+        format!("Key(s) not found: {not_seen_keys:?}"),
+    )
+}
+
+struct TensorsSeed;
+
+impl<'de> DeserializeSeed<'de> for TensorsSeed {
+    type Value = IndexMap<String, OutputTypes>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TensorsSeed {
+    type Value = IndexMap<String, OutputTypes>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("list of KServe v2 tensors")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut tensors = IndexMap::new();
+        let mut index = 0;
+        while let Some((name, tensor)) = seq.next_element_seed(TensorSeed).map_err(error_types::at_index(index))? {
+            if tensors.contains_key(&name) {
+                return Err(error_types::at_index(index)(error_types::error(
+                    ErrorKind::Structure,
+                    None,
+                    Some(name.clone()),
+                    format!("Duplicate tensor name {:?}", name),
+                )));
+            }
+            tensors.insert(name, tensor);
+            index += 1;
+        }
+        Ok(tensors)
+    }
+}
+
+/// The data of a tensor, parsed once its datatype is known and buffered until then
+enum Data {
+    Parsed(OutputTypes),
+    Buffered(Content),
+}
+
+struct TensorSeed;
+
+impl<'de> DeserializeSeed<'de> for TensorSeed {
+    type Value = (String, OutputTypes);
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TensorSeed {
+    type Value = (String, OutputTypes);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("KServe v2 tensor with a name, shape, datatype and data")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut name = None;
+        let mut shape = None;
+        let mut datatype = None;
+        let mut data = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => name = Some(map.next_value::<String>().map_err(error_types::at_key(&key))?),
+                "shape" => shape = Some(map.next_value::<Vec<usize>>().map_err(error_types::at_key(&key))?),
+                "datatype" => {
+                    let value = map.next_value::<String>().map_err(error_types::at_key(&key))?;
+                    datatype = Some(parse_datatype(&value).map_err(error_types::at_key(&key))?);
+                }
+                // the datatype usually comes first, so the data is parsed straight into its array
+                "data" => {
+                    let value = match datatype {
+                        Some(datatype) => map.next_value_seed(DataSeed(datatype)).map(Data::Parsed),
+                        None => limit_types::buffering(|| map.next_value::<Content>()).map(Data::Buffered),
+                    };
+                    data = Some(value.map_err(error_types::at_key(&key))?);
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let (name, shape, datatype, data) = match (name, shape, datatype, data) {
+            (Some(name), Some(shape), Some(datatype), Some(data)) => (name, shape, datatype, data),
+            (name, shape, datatype, data) => {
+                let not_seen_keys = [("name", name.is_none()), ("shape", shape.is_none()), ("datatype", datatype.is_none()), ("data", data.is_none())]
+                    .into_iter()
+                    .filter(|(_, missing)| *missing)
+                    .map(|(key, _)| key)
+                    .collect();
+                return Err(missing_keys_error(not_seen_keys));
+            }
+        };
+        let data = match data {
+            Data::Parsed(output) => output,
+            Data::Buffered(value) => DataSeed(datatype)
+                .deserialize(ContentDeserializer(&value))
+                .map_err(|err| de::Error::custom(err.to_string()))
+                .map_err(error_types::at_key("data"))?,
+        };
+        let tensor = match data {
+            OutputTypes::Bytes(bytes) => bytes.reshape(shape).map(OutputTypes::Bytes),
+            data => reshape(data, shape),
+        };
+        Ok((name, tensor.map_err(error_types::at_key("data"))?))
+    }
+}
+
+/// The data of a tensor, nested or flattened in row major order
+struct DataSeed(Datatype);
+
+impl<'de> DeserializeSeed<'de> for DataSeed {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match self.0 {
+            Datatype::Array(dtype) => TypeDescriptor(dtype).deserialize(deserializer),
+            Datatype::Bytes => BytesSeed { b64: false }.deserialize(deserializer).map(OutputTypes::Bytes),
+        }
+    }
+}
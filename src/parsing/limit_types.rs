@@ -0,0 +1,163 @@
+use std::cell::Cell;
+use std::fmt::Display;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+
+use crate::errors::ErrorKind;
+use crate::parsing::error_types;
+
+/// Limits on what an untrusted document can make the deserializer allocate, `None` for no limit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum nesting of the values in the document, counting each level of a nested array
+    pub max_depth: Option<usize>,
+    /// Maximum number of elements of an array (or rows of transposed records)
+    pub max_array_elements: Option<usize>,
+    /// Maximum number of bytes of all the arrays and transposed columns in the output together
+    pub max_output_bytes: Option<usize>,
+    /// Maximum number of bytes of a compressed document once decompressed
+    pub max_decompressed_bytes: Option<usize>,
+}
+
+impl<'source> FromPyObject<'source> for Limits {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let mut limits = Limits::default();
+        for (key, value) in object.downcast::<PyDict>()? {
+            let limit = match key.extract::<&str>()? {
+                "max_depth" => &mut limits.max_depth,
+                "max_array_elements" => &mut limits.max_array_elements,
+                "max_output_bytes" => &mut limits.max_output_bytes,
+                "max_decompressed_bytes" => &mut limits.max_decompressed_bytes,
+                key => {
+                    return Err(PyValueError::new_err(format!(
+                        "unrecognised limit {}. Expected one of: \"max_depth\", \"max_array_elements\", \"max_output_bytes\", \"max_decompressed_bytes\"",
+                        key
+                    )))
+                }
+            };
+            *limit = value.extract()?;
+        }
+        Ok(limits)
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<Limits> = const { Cell::new(Limits { max_depth: None, max_array_elements: None, max_output_bytes: None, max_decompressed_bytes: None }) };
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static OUTPUT_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Sets the limits for the next document, call before parsing a document
+pub fn start(limits: Limits) {
+    LIMITS.with(|cell| cell.set(limits));
+    DEPTH.with(|depth| depth.set(0));
+    OUTPUT_BYTES.with(|bytes| bytes.set(0));
+}
+
+fn limits() -> Limits {
+    LIMITS.with(Cell::get)
+}
+
+fn limit_error<E: de::Error>(limit: &str, max: usize, message: impl Display) -> E {
+    error_types::error(
+        ErrorKind::Limit,
+        Some(format!("{} <= {}", limit, max)),
+        None,
+        format!("{} (limit {} = {})", message, limit, max),
+    )
+}
+
+/// Leaves a level of nesting when dropped, including when an error unwinds
+pub struct DepthGuard;
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Enters a level of nesting, failing when it is deeper than `max_depth`
+pub fn enter<E: de::Error>() -> Result<DepthGuard, E> {
+    let depth = DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get()
+    });
+    let guard = DepthGuard;
+    match limits().max_depth {
+        Some(max) if depth > max => Err(limit_error("max_depth", max, "Document is nested too deeply")),
+        _ => Ok(guard),
+    }
+}
+
+/// How many elements of `element_size` bytes an array may have before going over `max_array_elements` or
+/// the bytes left of `max_output_bytes`
+pub fn capacity(element_size: usize) -> usize {
+    let limits = limits();
+    let remaining_bytes = limits
+        .max_output_bytes
+        .map(|max| max.saturating_sub(OUTPUT_BYTES.with(Cell::get)) / element_size.max(1));
+    limits
+        .max_array_elements
+        .unwrap_or(usize::MAX)
+        .min(remaining_bytes.unwrap_or(usize::MAX))
+}
+
+/// The error for an array that reached its `capacity`
+pub fn capacity_error<E: de::Error>(capacity: usize) -> E {
+    match limits() {
+        Limits { max_array_elements: Some(max), .. } if capacity >= max => {
+            limit_error("max_array_elements", max, format!("Array has more than {} elements", max))
+        }
+        Limits { max_output_bytes: Some(max), .. } => {
+            limit_error("max_output_bytes", max, format!("Output is larger than {} bytes", max))
+        }
+        _ => de::Error::custom(format!("Array has more than {} elements", capacity)),
+    }
+}
+
+/// Counts the bytes of an array that was completed towards `max_output_bytes`
+pub fn add_output_bytes(bytes: usize) {
+    OUTPUT_BYTES.with(|total| total.set(total.get().saturating_add(bytes)));
+}
+
+/// Counts `bytes` of a value while it is built towards `max_output_bytes`, failing as soon as the output goes over it
+pub fn reserve_output_bytes<E: de::Error>(bytes: usize) -> Result<(), E> {
+    let total = OUTPUT_BYTES.with(|total| {
+        total.set(total.get().saturating_add(bytes));
+        total.get()
+    });
+    match limits().max_output_bytes {
+        Some(max) if total > max => Err(limit_error("max_output_bytes", max, format!("Output is larger than {} bytes", max))),
+        _ => Ok(()),
+    }
+}
+
+/// Fails when a list or map of `len` elements which is being built has more than `max_array_elements`
+pub fn check_elements<E: de::Error>(len: usize) -> Result<(), E> {
+    match limits().max_array_elements {
+        Some(max) if len > max => Err(capacity_error(max)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs `buffer`, which counts a value it buffers towards the limits while building it. The bytes are forgotten
+/// afterwards, as the buffered value only lasts until it is parsed again into the output
+pub fn buffering<T>(buffer: impl FnOnce() -> T) -> T {
+    let before = output_bytes();
+    let result = buffer();
+    reset_output_bytes(before);
+    result
+}
+
+/// Bytes counted towards `max_output_bytes` so far
+pub fn output_bytes() -> usize {
+    OUTPUT_BYTES.with(Cell::get)
+}
+
+/// Forgets the bytes counted since there were `bytes`, when what they were counted for was handed over or dropped
+pub fn reset_output_bytes(bytes: usize) {
+    OUTPUT_BYTES.with(|total| total.set(bytes));
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::ops::Index;
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use pyo3::prelude::{FromPyObject, Py, PyAny, PyObject, PyResult, Python, ToPyObject};
+use pyo3::types::PyString;
+use serde::de;
+use serde::de::{Deserialize, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+
+use crate::errors::ErrorKind;
+use crate::parsing::error_types::{self, PathSegment};
+use crate::parsing::plan_types::Plan;
+use crate::parsing::{OutputTypes, PlanSeed, Structure, TypeDescriptor};
+
+/// Below this many keys a linear scan beats hashing the key
+const LINEAR_SCAN_KEYS: usize = 8;
+
+/// Matches a key against the keys of `fields` without allocating, returning its index (`None` for keys not in `fields`)
+pub struct KeySeed<'s, V>(pub &'s IndexMap<String, V>);
+
+impl<'s, V> KeySeed<'s, V> {
+    fn index_of(&self, key: &str) -> Option<usize> {
+        if self.0.len() <= LINEAR_SCAN_KEYS {
+            self.0.keys().position(|k| k == key)
+        } else {
+            self.0.get_index_of(key)
+        }
+    }
+}
+
+impl<'de, 's, V> DeserializeSeed<'de> for KeySeed<'s, V> {
+    type Value = Option<usize>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(KeyVisitor(self))
+    }
+}
+
+struct KeyVisitor<'s, V>(KeySeed<'s, V>);
+
+impl<'de, 's, V> Visitor<'de> for KeyVisitor<'s, V> {
+    type Value = Option<usize>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "a string key")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Self::Value, E> {
+        Ok(self.0.index_of(key))
+    }
+
+    fn visit_bytes<E: de::Error>(self, key: &[u8]) -> Result<Self::Value, E> {
+        Ok(std::str::from_utf8(key).ok().and_then(|key| self.0.index_of(key)))
+    }
+}
+
+/// The keys of a map, shared by every map parsed with the same structure instead of copied into each
+#[derive(Clone, Debug)]
+pub struct Keys {
+    names: Arc<[String]>,
+    /// Index of the first key in the python strings interned by `intern_keys`, `None` for keys that aren't part of
+    /// the structure
+    interned: Option<usize>,
+}
+
+impl Keys {
+    fn new(names: Vec<String>) -> Self {
+        Keys { names: names.into(), interned: None }
+    }
+
+    /// The python string of the key at `index`, the interned one when there is one
+    fn to_py(&self, py: Python, interned: &[Py<PyString>], index: usize) -> PyObject {
+        match self.interned.and_then(|first| interned.get(first + index)) {
+            Some(key) => key.to_object(py),
+            None => PyString::new(py, &self.names[index]).into(),
+        }
+    }
+}
+
+impl PartialEq for Keys {
+    fn eq(&self, other: &Self) -> bool {
+        self.names == other.names
+    }
+}
+
+/// A map of the structure, `{key: Structure, ...}`
+#[derive(Clone)]
+pub struct MapStructure {
+    pub fields: IndexMap<String, Structure>,
+    pub keys: Keys,
+    /// Whether each field holds transposed records, whose skipped or filled records get the field's key in their path
+    records: Vec<bool>,
+}
+
+impl MapStructure {
+    pub fn new(fields: IndexMap<String, Structure>) -> Self {
+        let keys = Keys::new(fields.keys().cloned().collect());
+        let records = fields.values().map(Structure::holds_records).collect();
+        MapStructure { fields, keys, records }
+    }
+
+    /// Whether any field holds transposed records
+    pub fn holds_records(&self) -> bool {
+        self.records.iter().any(|&records| records)
+    }
+}
+
+impl fmt::Debug for MapStructure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fields.fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for MapStructure {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        IndexMap::deserialize(deserializer).map(MapStructure::new)
+    }
+}
+
+impl<'source> FromPyObject<'source> for MapStructure {
+    fn extract(object: &'source PyAny) -> PyResult<Self> {
+        object.extract().map(MapStructure::new)
+    }
+}
+
+/// Interns the keys of the maps and classes of `structure` as python strings, which the parsed maps are returned
+/// with instead of new strings. Like the classes they are owned by the deserializer, the structure refers to them by
+/// index
+pub fn intern_keys(py: Python, structure: &mut Structure) -> Vec<Py<PyString>> {
+    let mut interned = Vec::new();
+    intern(py, structure, &mut interned);
+    interned
+}
+
+fn intern(py: Python, structure: &mut Structure, interned: &mut Vec<Py<PyString>>) {
+    let map = match structure {
+        Structure::Map(map) => map,
+        Structure::Class(class) => &mut class.fields,
+        Structure::OneOf(one_of) => {
+            for alternative in &mut one_of.alternatives {
+                intern(py, alternative, interned);
+            }
+            return;
+        }
+        Structure::Sequence(sequence) => return intern(py, &mut sequence.0, interned),
+        _ => return,
+    };
+    map.keys.interned = Some(interned.len());
+    interned.extend(map.keys.names.iter().map(|name| Py::from(PyString::intern(py, name))));
+    for field in map.fields.values_mut() {
+        intern(py, field, interned);
+    }
+}
+
+/// The values of a map parsed with a structure, in the order of its keys
+#[derive(Debug, PartialEq)]
+pub struct Fields {
+    keys: Keys,
+    values: Vec<OutputTypes>,
+}
+
+impl Fields {
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.names.iter().map(String::as_str)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&OutputTypes> {
+        self.keys().position(|k| k == key).map(|index| &self.values[index])
+    }
+
+    /// The fields with their keys as python strings, see `intern_keys`
+    pub fn into_py_items<'py>(self, py: Python<'py>, interned: &'py [Py<PyString>]) -> impl Iterator<Item = (PyObject, OutputTypes)> + 'py {
+        let keys = self.keys;
+        self.values.into_iter().enumerate().map(move |(index, value)| (keys.to_py(py, interned, index), value))
+    }
+}
+
+impl FromIterator<(String, OutputTypes)> for Fields {
+    fn from_iter<I: IntoIterator<Item = (String, OutputTypes)>>(iter: I) -> Self {
+        let (names, values) = iter.into_iter().unzip();
+        Fields { keys: Keys::new(names), values }
+    }
+}
+
+impl Index<&str> for Fields {
+    type Output = OutputTypes;
+
+    fn index(&self, key: &str) -> &OutputTypes {
+        self.get(key).unwrap_or_else(|| panic!("no field {key:?}"))
+    }
+}
+
+/// Deserializes a map into the fields given by the structure, in structure order. The fields are parsed with their
+/// plans for this document, if any
+pub struct MapSeed<'s>(pub &'s MapStructure, pub Vec<Option<Plan>>);
+
+impl<'de, 's> DeserializeSeed<'de> for MapSeed<'s> {
+    type Value = Fields;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(MapVisitor(self))
+    }
+}
+
+struct MapVisitor<'s>(MapSeed<'s>);
+
+impl<'de, 's> Visitor<'de> for MapVisitor<'s> {
+    type Value = Fields;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "map with keys: {:?}", self.0 .0.fields.keys().collect::<Vec<_>>())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let MapSeed(MapStructure { fields, keys, records }, mut plans) = self.0;
+        let mut values: Vec<Option<OutputTypes>> = fields.iter().map(|_| None).collect();
+        while let Some(index) = map.next_key_seed(KeySeed(fields))? {
+            match index.and_then(|index| Some((index, fields.get_index(index)?))) {
+                Some((index, (key, structure))) => {
+                    // the plan is handed out once, a repeated key is parsed as usual
+                    let plan = plans.get_mut(index).and_then(Option::take);
+                    let record_errors = records[index].then(error_types::record_errors_len);
+                    let value = match (plan, structure) {
+                        (None, Structure::Type(input_type)) => map.next_value_seed(TypeDescriptor(*input_type)),
+                        (plan, structure) => map.next_value_seed(PlanSeed(structure, plan)),
+                    };
+                    values[index] = Some(value.map_err(error_types::at_key(key))?);
+                    if let Some(record_errors) = record_errors {
+                        error_types::prefix_record_errors(record_errors, || PathSegment::Key(key.clone()));
+                    }
+                }
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if values.iter().any(Option::is_none) {
+            let not_seen_keys: Vec<&String> = fields
+                .keys()
+                .zip(&values)
+                .filter(|(_, value)| value.is_none())
+                .map(|(k, _)| k)
+                .collect();
+            return Err(error_types::error(
+                ErrorKind::MissingKey,
+                Some(format!("{not_seen_keys:?}")),
+                None,
+                format!("Key(s) not found: {not_seen_keys:?}"),
+            ));
+        }
+        // output keys in the order they were given in the structure
+        Ok(Fields { keys: keys.clone(), values: values.into_iter().flatten().collect() })
+    }
+}
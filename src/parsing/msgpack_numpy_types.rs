@@ -0,0 +1,299 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use bytemuck::Pod;
+use half::f16;
+use num_traits::cast::FromPrimitive;
+use serde::de;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::errors::ErrorKind;
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::python_types::PythonType;
+
+/// An array encoded by msgpack-numpy: `{"nd": True, "type": "<f8", "kind": "", "shape": [2, 3], "data": b"..."}`
+/// with the values in C order, or a numpy scalar with `"nd": False` and no shape
+pub struct EncodedArray<'de> {
+    dtype: Dtype,
+    /// `None` for a scalar
+    pub shape: Option<Vec<usize>>,
+    data: Cow<'de, [u8]>,
+}
+
+/// The element type of an array of the structure, with the kind of its numpy dtype: `i`, `u` or `f`
+pub trait NativeType: Pod + FromPrimitive {
+    const KIND: u8;
+}
+
+macro_rules! native_types {
+    ($kind:literal: $($type:ty),*) => {
+        $(impl NativeType for $type {
+            const KIND: u8 = $kind;
+        })*
+    };
+}
+
+native_types!(b'i': i8, i16, i32, i64);
+native_types!(b'u': u8, u16, u32, u64);
+native_types!(b'f': f16, f32, f64);
+
+/// A numpy dtype given by its `str`, e.g. `<i4`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Dtype {
+    kind: u8,
+    size: usize,
+    big_endian: bool,
+}
+
+/// A value read from the data of an encoded array, before it is cast to the dtype of the structure
+#[derive(Clone, Copy, Debug)]
+enum Scalar {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Int(value) => write!(f, "{}", value),
+            Scalar::UInt(value) => write!(f, "{}", value),
+            Scalar::Float(value) => write!(f, "{}", value),
+            Scalar::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Dtype {
+    fn parse<E: de::Error>(descr: &str) -> Result<Dtype, E> {
+        let unsupported = || {
+            error_types::error(
+                ErrorKind::Cast,
+                None,
+                Some(descr.to_string()),
+                format!("Unsupported msgpack-numpy dtype {:?}", descr),
+            )
+        };
+        let mut chars = descr.chars();
+        let big_endian = match chars.next() {
+            Some('<' | '|' | '=') => false,
+            Some('>') => true,
+            _ => return Err(unsupported()),
+        };
+        let kind = chars.next().ok_or_else(unsupported)? as u8;
+        let size = chars.as_str().parse().map_err(|_| unsupported())?;
+        match (kind, size) {
+            (b'i' | b'u', 1 | 2 | 4 | 8) | (b'f', 2 | 4 | 8) | (b'b', 1) => Ok(Dtype { kind, size, big_endian }),
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn read(&self, bytes: &[u8]) -> Scalar {
+        let mut buffer = [0u8; 8];
+        buffer[..self.size].copy_from_slice(bytes);
+        if self.big_endian {
+            buffer[..self.size].reverse();
+        }
+        let raw = u64::from_le_bytes(buffer);
+        let unused = 64 - 8 * self.size as u32;
+        match (self.kind, self.size) {
+            (b'i', _) => Scalar::Int(((raw << unused) as i64) >> unused),
+            (b'u', _) => Scalar::UInt(raw),
+            (b'f', 2) => Scalar::Float(f16::from_bits(raw as u16).to_f64()),
+            (b'f', 4) => Scalar::Float(f32::from_bits(raw as u32) as f64),
+            (b'f', _) => Scalar::Float(f64::from_bits(raw)),
+            _ => Scalar::Bool(raw != 0),
+        }
+    }
+}
+
+impl fmt::Display for Dtype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            b'b' => write!(f, "bool"),
+            kind => write!(f, "{}{}", kind as char, self.size * 8),
+        }
+    }
+}
+
+impl<'de> EncodedArray<'de> {
+    /// Reads the encoded array from its map, whose keys are `bytes` or `str`
+    pub fn from_map<A: MapAccess<'de>>(mut map: A) -> Result<EncodedArray<'de>, A::Error> {
+        let mut nd = None;
+        let mut descr = None;
+        let mut shape = None;
+        let mut data = None;
+        while let Some(key) = map.next_key::<Key>()? {
+            match key.0.as_slice() {
+                b"nd" => nd = Some(map.next_value::<bool>().map_err(error_types::at_key("nd"))?),
+                b"type" => {
+                    let PythonType(value) = limit_types::buffering(|| map.next_value()).map_err(error_types::at_key("type"))?;
+                    descr = Some(value);
+                }
+                b"shape" => shape = Some(map.next_value::<Vec<usize>>().map_err(error_types::at_key("shape"))?),
+                b"data" => data = Some(map.next_value::<Data>().map_err(error_types::at_key("data"))?.0),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        let (descr, data) = match (descr, data) {
+            (Some(descr), Some(data)) => (descr, data),
+            _ => {
+                return Err(error_types::error(
+                    ErrorKind::Structure,
+                    Some("an array or a msgpack-numpy array".to_string()),
+                    Some("a map".to_string()),
+                    "Expected an array, found a map which isn't a msgpack-numpy array (with type and data)",
+                ))
+            }
+        };
+        let dtype = match &descr {
+            Value::String(descr) => Dtype::parse(descr)?,
+            descr => {
+                return Err(error_types::error(
+                    ErrorKind::Cast,
+                    None,
+                    Some(descr.to_string()),
+                    format!("Unsupported msgpack-numpy dtype {}, structured arrays can't be read", descr),
+                ))
+            }
+        };
+        let shape = match nd.unwrap_or(shape.is_some()) {
+            true => Some(shape.unwrap_or_default()),
+            false => None,
+        };
+
+        let len = data.len() / dtype.size;
+        let expected = shape.as_ref().map_or(1, |shape| shape.iter().product());
+        if data.len() % dtype.size != 0 || len != expected {
+            return Err(error_types::error(
+                ErrorKind::Shape,
+                Some(format!("{} elements", expected)),
+                Some(format!("{} bytes", data.len())),
+                format!(
+                    "msgpack-numpy array of shape {:?} and dtype {} has {} bytes of data",
+                    shape.as_deref().unwrap_or_default(),
+                    dtype,
+                    data.len()
+                ),
+            ));
+        }
+        Ok(EncodedArray { dtype, shape, data })
+    }
+
+    /// Number of values of the array
+    pub fn len(&self) -> usize {
+        self.data.len() / self.dtype.size
+    }
+
+    /// The values cast to `T`, failing on the first value which doesn't fit or is a float with a fractional part
+    /// cast to an integer. Data of the dtype of `T` in native byte order is copied as is
+    pub fn values<T: NativeType, E: de::Error>(&self) -> Result<Vec<T>, E> {
+        let native_order = self.dtype.size == 1 || self.dtype.big_endian == cfg!(target_endian = "big");
+        if self.dtype.kind == T::KIND && self.dtype.size == std::mem::size_of::<T>() && native_order {
+            // copied as bytes, as the data may not be aligned for `T`
+            let mut values = vec![T::zeroed(); self.len()];
+            bytemuck::cast_slice_mut::<T, u8>(&mut values).copy_from_slice(&self.data);
+            return Ok(values);
+        }
+        let mut values = Vec::with_capacity(self.len());
+        for bytes in self.data.chunks_exact(self.dtype.size) {
+            let scalar = self.dtype.read(bytes);
+            let value = match scalar {
+                Scalar::Int(value) => T::from_i64(value),
+                Scalar::UInt(value) => T::from_u64(value),
+                Scalar::Float(value) if T::KIND != b'f' && value.fract() != 0.0 => None,
+                Scalar::Float(value) => T::from_f64(value),
+                Scalar::Bool(value) => T::from_u8(value as u8),
+            };
+            match value {
+                Some(value) => values.push(value),
+                None => {
+                    return Err(error_types::error(
+                        ErrorKind::Cast,
+                        Some(std::any::type_name::<T>().to_string()),
+                        Some(scalar.to_string()),
+                        format!("Could not cast {} ({}) into: {:?}", scalar, self.dtype, std::any::type_name::<T>()),
+                    ))
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// The values of a bool array, the other dtypes aren't cast to bool
+    pub fn bools<E: de::Error>(&self) -> Result<Vec<bool>, E> {
+        if self.dtype.kind != b'b' {
+            return Err(error_types::error(
+                ErrorKind::Cast,
+                Some("bool".to_string()),
+                Some(self.dtype.to_string()),
+                format!("Could not cast {} array into: \"bool\"", self.dtype),
+            ));
+        }
+        Ok(self.data.iter().map(|&byte| byte != 0).collect())
+    }
+}
+
+/// A key of the map, `bytes` when packed by msgpack-numpy with `use_bin_type=True`
+struct Key(Vec<u8>);
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a key of a msgpack-numpy array")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Key, E> {
+                Ok(Key(value.as_bytes().to_vec()))
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Key, E> {
+                Ok(Key(value.to_vec()))
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+/// The data of the array, borrowed from the document when it is in memory
+struct Data<'de>(Cow<'de, [u8]>);
+
+impl<'de> Deserialize<'de> for Data<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DataVisitor;
+
+        impl<'de> Visitor<'de> for DataVisitor {
+            type Value = Data<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("the bytes of a msgpack-numpy array")
+            }
+
+            fn visit_borrowed_bytes<E: de::Error>(self, value: &'de [u8]) -> Result<Data<'de>, E> {
+                Ok(Data(Cow::Borrowed(value)))
+            }
+
+            fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Data<'de>, E> {
+                Ok(Data(Cow::Owned(value.to_vec())))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Data<'de>, E> {
+                Ok(Data(Cow::Owned(value)))
+            }
+        }
+
+        deserializer.deserialize_bytes(DataVisitor)
+    }
+}
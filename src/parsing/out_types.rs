@@ -0,0 +1,154 @@
+use half::f16;
+use numpy::{Element, PyArrayDyn};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de::{DeserializeSeed, Deserializer};
+
+use crate::parsing::array_types::{index_out, Array, BoolArray};
+use crate::parsing::plan_types::{self, Plan};
+use crate::parsing::{InputTypes, OutputTypes, Structure};
+
+/// A preallocated numpy array which an array of the structure is parsed straight into
+pub struct Target {
+    dtype: InputTypes,
+    /// The values of the C-contiguous array, of the structure's dtype
+    data: *mut u8,
+    len: usize,
+    shape: Vec<usize>,
+}
+
+/// Any value kept alive for its `Drop`, here the borrows of the out arrays
+trait Hold {}
+
+impl<T> Hold for T {}
+
+/// Keeps the out arrays borrowed while the next document on this thread is parsed into them. Dropping it stops
+/// parsing into them
+pub struct OutArrays<'py> {
+    _borrows: Vec<Box<dyn Hold + 'py>>,
+}
+
+impl<'py> Drop for OutArrays<'py> {
+    fn drop(&mut self) {
+        plan_types::set(None);
+    }
+}
+
+/// Parses the arrays of `structure` which have a counterpart in `out` (a dictionary mirroring the structure or a
+/// single array, at `index` if given) straight into the out arrays. Arrays whose counterpart has another dtype or
+/// isn't C-contiguous are parsed as usual and copied by `OutputTypes::into_py_out`, which raises the errors
+pub fn start<'py>(structure: &Structure, out: &'py PyAny, index: Option<usize>) -> PyResult<OutArrays<'py>> {
+    let mut borrows = Vec::new();
+    plan_types::set(collect(structure, out, index, &mut borrows)?);
+    Ok(OutArrays { _borrows: borrows })
+}
+
+fn collect<'py>(
+    structure: &Structure,
+    out: &'py PyAny,
+    index: Option<usize>,
+    borrows: &mut Vec<Box<dyn Hold + 'py>>,
+) -> PyResult<Option<Plan>> {
+    let map = match structure {
+        Structure::Type(dtype) => {
+            let out = match index_out(out, index) {
+                Ok(out) => out,
+                Err(_) => return Ok(None),
+            };
+            let target = match dtype {
+                InputTypes::int8 => target::<i8>(structure, out, borrows)?,
+                InputTypes::int16 => target::<i16>(structure, out, borrows)?,
+                InputTypes::int32 => target::<i32>(structure, out, borrows)?,
+                InputTypes::int64 => target::<i64>(structure, out, borrows)?,
+
+                InputTypes::uint8 => target::<u8>(structure, out, borrows)?,
+                InputTypes::uint16 => target::<u16>(structure, out, borrows)?,
+                InputTypes::uint32 => target::<u32>(structure, out, borrows)?,
+                InputTypes::uint64 => target::<u64>(structure, out, borrows)?,
+
+                InputTypes::float16 => target::<f16>(structure, out, borrows)?,
+                InputTypes::float32 => target::<f32>(structure, out, borrows)?,
+                InputTypes::float64 => target::<f64>(structure, out, borrows)?,
+
+                InputTypes::bool_ => target::<bool>(structure, out, borrows)?,
+                _ => None,
+            };
+            return Ok(target.map(Plan::Out));
+        }
+        Structure::Map(map) => map,
+        Structure::Class(class) => &class.fields,
+        _ => return Ok(None),
+    };
+    let out = match out.downcast::<PyDict>() {
+        Ok(out) => out,
+        Err(_) => return Ok(None),
+    };
+    let mut fields = Vec::with_capacity(map.fields.len());
+    for (key, field) in &map.fields {
+        fields.push(match out.get_item(key.as_str()) {
+            Some(field_out) => collect(field, field_out, index, borrows)?,
+            None => None,
+        });
+    }
+    Ok(fields.iter().any(Option::is_some).then_some(Plan::Fields(fields)))
+}
+
+fn target<'py, T: Element + 'py>(structure: &Structure, out: &'py PyAny, borrows: &mut Vec<Box<dyn Hold + 'py>>) -> PyResult<Option<Target>> {
+    let array = match out.downcast::<PyArrayDyn<T>>() {
+        Ok(array) if array.is_c_contiguous() => array,
+        _ => return Ok(None),
+    };
+    let dtype = match structure {
+        Structure::Type(dtype) => *dtype,
+        _ => return Ok(None),
+    };
+    // held until the document is parsed, so no one else reads or writes the array meanwhile
+    borrows.push(Box::new(array.try_readwrite()?));
+    Ok(Some(Target {
+        dtype,
+        data: array.data() as *mut u8,
+        len: array.len(),
+        shape: array.shape().to_vec(),
+    }))
+}
+
+impl Target {
+    /// # Safety
+    /// `T` must be the element type of the array, which `OutArrays` keeps borrowed and alive
+    unsafe fn values<'o, T>(&self) -> &'o mut [T] {
+        std::slice::from_raw_parts_mut(self.data as *mut T, self.len)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Target {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let shape = &self.shape;
+        // Safety: `target` checked that the array has the element type of the dtype
+        unsafe {
+            match self.dtype {
+                InputTypes::int8 => Array::<i8>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::int16 => Array::<i16>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::int32 => Array::<i32>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::int64 => Array::<i64>::deserialize_into(deserializer, self.values(), shape)?,
+
+                InputTypes::uint8 => Array::<u8>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::uint16 => Array::<u16>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::uint32 => Array::<u32>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::uint64 => Array::<u64>::deserialize_into(deserializer, self.values(), shape)?,
+
+                InputTypes::float16 => Array::<f16>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::float32 => Array::<f32>::deserialize_into(deserializer, self.values(), shape)?,
+                InputTypes::float64 => Array::<f64>::deserialize_into(deserializer, self.values(), shape)?,
+
+                InputTypes::bool_ => BoolArray::deserialize_into(deserializer, self.values(), shape)?,
+                _ => unreachable!("targets are only made for numpy dtypes"),
+            }
+        }
+        Ok(OutputTypes::Written)
+    }
+}
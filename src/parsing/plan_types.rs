@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+
+use crate::parsing::map_types::MapStructure;
+use crate::parsing::out_types::Target;
+
+/// What parsing the next document on this thread takes beyond its structure: the out arrays and streamed records,
+/// resolved once before the document is parsed and handed down next to the parts of the structure they belong to
+pub enum Plan {
+    /// An array parsed straight into its out array
+    Out(Target),
+    /// Transposed records handed to the stream's sink every `chunk_size` records
+    Stream(usize),
+    /// A map or class, with the plans of its fields by index
+    Fields(Vec<Option<Plan>>),
+}
+
+thread_local! {
+    static PLAN: RefCell<Option<Plan>> = const { RefCell::new(None) };
+}
+
+/// Parses the next document on this thread with `plan`, or as usual when it's `None`
+pub fn set(plan: Option<Plan>) {
+    PLAN.with(|cell| *cell.borrow_mut() = plan);
+}
+
+/// The plan of the document about to be parsed, which is taken once by the document
+pub fn take() -> Option<Plan> {
+    PLAN.with(|cell| cell.borrow_mut().take())
+}
+
+impl Plan {
+    /// The plan of a map whose `field` is parsed with `plan`
+    pub fn field(map: &MapStructure, field: &str, plan: Plan) -> Option<Plan> {
+        let index = map.fields.get_index_of(field)?;
+        let mut fields: Vec<Option<Plan>> = map.fields.iter().map(|_| None).collect();
+        fields[index] = Some(plan);
+        Some(Plan::Fields(fields))
+    }
+}
+
+/// The plans of the fields of a map or class, empty when none of its fields has one
+pub fn fields(plan: Option<Plan>) -> Vec<Option<Plan>> {
+    match plan {
+        Some(Plan::Fields(fields)) => fields,
+        _ => Vec::new(),
+    }
+}
+
+/// Number of records after which transposed records are flushed, `None` when they aren't streamed
+pub fn chunk_size(plan: &Option<Plan>) -> Option<usize> {
+    match plan {
+        Some(Plan::Stream(chunk_size)) => Some(*chunk_size),
+        _ => None,
+    }
+}
@@ -1,14 +1,103 @@
-use std::collections::HashMap;
+use std::fmt;
 
-use serde;
-use serde::Deserialize;
-use serde_json::Value;
+use indexmap::IndexMap;
+use serde::de;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
 
 use pyo3::prelude::*;
 
-#[derive(Debug, PartialEq, Deserialize)]
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+
+#[derive(Debug, PartialEq)]
 pub struct PythonType(pub Value);
 
+impl<'de> Deserialize<'de> for PythonType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor).map(PythonType)
+    }
+}
+
+/// Builds a `Value` like its own `Deserialize` does, counting its strings and elements towards the limits as they are
+/// added so that a large value fails before it is built whole
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("any valid JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(value).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut values = Vec::new();
+        while let Some(PythonType(value)) = seq.next_element().map_err(error_types::at_index(values.len()))? {
+            values.push(value);
+            limit_types::check_elements(values.len())?;
+            limit_types::reserve_output_bytes(std::mem::size_of::<Value>())?;
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut entries = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let PythonType(value) = map.next_value().map_err(error_types::at_key(&key))?;
+            limit_types::reserve_output_bytes(key.len() + std::mem::size_of::<(String, Value)>())?;
+            entries.insert(key, value);
+            limit_types::check_elements(entries.len())?;
+        }
+        Ok(Value::Object(entries))
+    }
+}
+
 impl IntoPy<PyObject> for PythonType {
     fn into_py(self, py: Python) -> PyObject {
         match self.0 {
@@ -34,7 +123,7 @@ impl IntoPy<PyObject> for PythonType {
             Value::Object(map) => map
                 .into_iter()
                 .map(|(k, v)| (k, PythonType(v).into_py(py)))
-                .collect::<HashMap<String, PyObject>>()
+                .collect::<IndexMap<String, PyObject>>()
                 .into_py(py),
         }
     }
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+use itertools::Either;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::parsing::{ArrayFormat, OutputOptions, OutputTypes};
+
+/// How transposed records (`[[Type, ...]]` and `[{key: Type, ...}]`) are returned
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RecordFormat {
+    #[default]
+    Dict,
+    Pandas,
+    Arrow,
+}
+
+impl FromStr for RecordFormat {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dict" => Ok(RecordFormat::Dict),
+            "pandas" => Ok(RecordFormat::Pandas),
+            "arrow" => Ok(RecordFormat::Arrow),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised record format {}. Expected one of: \"dict\", \"pandas\", \"arrow\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for RecordFormat {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        RecordFormat::from_str(object.extract()?)
+    }
+}
+
+/// Columns built by `TransposeSeq` / `TransposeMap`
+#[derive(Debug, PartialEq)]
+pub enum Records {
+    Seq(Vec<OutputTypes>),
+    Map(IndexMap<String, OutputTypes>),
+}
+
+impl Records {
+    pub fn columns(&self) -> impl Iterator<Item = &OutputTypes> {
+        match self {
+            Records::Seq(columns) => Either::Left(columns.iter()),
+            Records::Map(columns) => Either::Right(columns.values()),
+        }
+    }
+
+    pub fn columns_mut(&mut self) -> impl Iterator<Item = &mut OutputTypes> {
+        match self {
+            Records::Seq(columns) => Either::Left(columns.iter_mut()),
+            Records::Map(columns) => Either::Right(columns.values_mut()),
+        }
+    }
+
+    pub fn into_py_with(self, py: Python, options: &OutputOptions) -> PyResult<PyObject> {
+        // dataframes are always built from numpy columns
+        let column_options = OutputOptions { arrays: ArrayFormat::Numpy, ..options.clone() };
+        match options.records {
+            RecordFormat::Dict => match self {
+                Records::Seq(columns) => OutputTypes::List(columns).into_py_with(py, options),
+                Records::Map(columns) => OutputTypes::Map(columns.into_iter().collect()).into_py_with(py, options),
+            },
+            RecordFormat::Pandas => {
+                let pandas = py.import("pandas")?;
+                let columns = PyDict::new(py);
+                for (name, column) in self.into_columns(py) {
+                    let column = match column {
+                        OutputTypes::Categorical(values) => {
+                            pandas.call_method1("Categorical", (values.into_py(py),))?.into()
+                        }
+                        column => column_rows(py, column.into_py_with(py, &column_options)?)?,
+                    };
+                    columns.set_item(name, column)?;
+                }
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("copy", false)?;
+                Ok(pandas.call_method("DataFrame", (columns,), Some(kwargs))?.into())
+            }
+            RecordFormat::Arrow => {
+                let pyarrow = py.import("pyarrow")?;
+                let columns = PyDict::new(py);
+                for (name, column) in self.into_columns(py) {
+                    let column = match column {
+                        OutputTypes::Categorical(values) => pyarrow
+                            .call_method1("array", (values.into_py(py),))?
+                            .call_method0("dictionary_encode")?
+                            .into(),
+                        column => column_rows(py, column.into_py_with(py, &column_options)?)?,
+                    };
+                    columns.set_item(name.as_ref(py).str()?, column)?;
+                }
+                Ok(pyarrow.call_method1("table", (columns,))?.into())
+            }
+        }
+    }
+
+    /// Column names paired with columns. Sequence records are named by their position
+    fn into_columns(self, py: Python) -> Vec<(PyObject, OutputTypes)> {
+        match self {
+            Records::Seq(columns) => columns
+                .into_iter()
+                .enumerate()
+                .map(|(i, column)| (i.into_py(py), column))
+                .collect(),
+            Records::Map(columns) => columns
+                .into_iter()
+                .map(|(name, column)| (name.into_py(py), column))
+                .collect(),
+        }
+    }
+}
+
+/// Dataframe columns are 1D, so a column of N-D values (e.g. embeddings) becomes a list of its rows
+fn column_rows(py: Python, column: PyObject) -> PyResult<PyObject> {
+    let ndim: usize = column.getattr(py, "ndim").and_then(|ndim| ndim.extract(py)).unwrap_or(1);
+    match ndim {
+        0 | 1 => Ok(column),
+        _ => Ok(py.import("builtins")?.getattr("list")?.call1((column,))?.into()),
+    }
+}
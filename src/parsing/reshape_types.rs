@@ -0,0 +1,187 @@
+use std::fmt;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+use crate::errors::ErrorKind;
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::union_types::{Content, ContentDeserializer};
+use crate::parsing::{InputTypes, OutputTypes, TypeDescriptor};
+
+/// `{"reshape": "float32", "data": "data", "shape": "shape", "dtype": "datatype"}`: a map holding a flat array under
+/// `data` and its shape under `shape`, in any order. The optional `dtype` key names a field of the map whose dtype
+/// string (e.g. "int64") overrides the dtype given by `reshape`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "ReshapeFields")]
+pub struct Reshape {
+    pub dtype: InputTypes,
+    pub data_key: String,
+    pub shape_key: String,
+    pub dtype_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReshapeFields {
+    reshape: InputTypes,
+    data: String,
+    shape: String,
+    #[serde(default)]
+    dtype: Option<String>,
+}
+
+impl TryFrom<ReshapeFields> for Reshape {
+    type Error = String;
+
+    fn try_from(fields: ReshapeFields) -> Result<Self, Self::Error> {
+        if !fields.reshape.is_array() {
+            return Err(format!("reshaped arrays can't have dtype {}, expected a numpy dtype", fields.reshape));
+        }
+        Ok(Reshape { dtype: fields.reshape, data_key: fields.data, shape_key: fields.shape, dtype_key: fields.dtype })
+    }
+}
+
+impl<'source> FromPyObject<'source> for Reshape {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let dict = object.downcast::<PyDict>()?;
+        let known = |key: &PyAny| matches!(key.extract(), Ok("reshape" | "data" | "shape" | "dtype"));
+        match (dict.get_item("reshape"), dict.get_item("data"), dict.get_item("shape")) {
+            (Some(reshape), Some(data), Some(shape)) if dict.keys().iter().all(known) => {
+                let fields = ReshapeFields {
+                    reshape: reshape.extract()?,
+                    data: data.extract()?,
+                    shape: shape.extract()?,
+                    dtype: dict.get_item("dtype").map(PyAny::extract).transpose()?,
+                };
+                Reshape::try_from(fields).map_err(PyValueError::new_err)
+            }
+            _ => Err(PyValueError::new_err(format!("cannot parse {} as reshape structure", object))),
+        }
+    }
+}
+
+/// The dtype named by a dtype field of the document
+fn parse_dtype<E: de::Error>(name: &str) -> Result<InputTypes, E> {
+    let dtype = InputTypes::deserialize(de::value::StrDeserializer::<de::value::Error>::new(name));
+    match dtype {
+        Ok(dtype) if dtype.is_array() => Ok(dtype),
+        _ => Err(error_types::error(
+            ErrorKind::Cast,
+            Some("a numpy dtype".to_string()),
+            Some(name.to_string()),
+            format!("Unrecognised dtype {:?}", name),
+        )),
+    }
+}
+
+pub struct ReshapeSeed<'s>(pub &'s Reshape);
+
+impl<'de, 's> DeserializeSeed<'de> for ReshapeSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(ReshapeVisitor(self.0))
+    }
+}
+
+/// The data of the map, parsed once its dtype is known and buffered until then
+enum Data {
+    Parsed(OutputTypes),
+    Buffered(Content),
+}
+
+struct ReshapeVisitor<'s>(&'s Reshape);
+
+impl<'de, 's> Visitor<'de> for ReshapeVisitor<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "map with keys {:?} and {:?}", self.0.data_key, self.0.shape_key)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let structure = self.0;
+        // without a dtype field the dtype is known up front and the data is parsed straight into its array
+        let mut dtype = match structure.dtype_key {
+            Some(_) => None,
+            None => Some(structure.dtype),
+        };
+        let mut data = None;
+        let mut shape = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == structure.data_key {
+                let value = match dtype {
+                    Some(dtype) => map.next_value_seed(TypeDescriptor(dtype)).map(Data::Parsed),
+                    None => limit_types::buffering(|| map.next_value::<Content>()).map(Data::Buffered),
+                };
+                data = Some(value.map_err(error_types::at_key(&key))?);
+            } else if key == structure.shape_key {
+                shape = Some(map.next_value::<Vec<usize>>().map_err(error_types::at_key(&key))?);
+            } else if structure.dtype_key.as_ref() == Some(&key) {
+                let name = map.next_value::<String>().map_err(error_types::at_key(&key))?;
+                dtype = Some(parse_dtype(&name).map_err(error_types::at_key(&key))?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        let (data, shape) = match (data, shape) {
+            (Some(data), Some(shape)) => (data, shape),
+            (data, shape) => {
+                let not_seen_keys: Vec<&String> = [(&structure.data_key, data.is_none()), (&structure.shape_key, shape.is_none())]
+                    .into_iter()
+                    .filter(|(_, missing)| *missing)
+                    .map(|(key, _)| key)
+                    .collect();
+                return Err(error_types::error(
+                    ErrorKind::MissingKey,
+                    Some(format!("{not_seen_keys:?}")),
+                    None,
+                    format!("Key(s) not found: {not_seen_keys:?}"),
+                ));
+            }
+        };
+        let data = match data {
+            Data::Parsed(output) => output,
+            // the document has no dtype field, so the data takes the default dtype
+            Data::Buffered(value) => TypeDescriptor(dtype.unwrap_or(structure.dtype))
+                .deserialize(ContentDeserializer(&value))
+                .map_err(|err| de::Error::custom(err.to_string()))
+                .map_err(error_types::at_key(&structure.data_key))?,
+        };
+        reshape(data, shape).map_err(error_types::at_key(&structure.data_key))
+    }
+}
+
+pub fn reshape<E: de::Error>(output: OutputTypes, shape: Vec<usize>) -> Result<OutputTypes, E> {
+    let output = match output {
+        OutputTypes::I8(array) => OutputTypes::I8(array.reshape(shape)?),
+        OutputTypes::I16(array) => OutputTypes::I16(array.reshape(shape)?),
+        OutputTypes::I32(array) => OutputTypes::I32(array.reshape(shape)?),
+        OutputTypes::I64(array) => OutputTypes::I64(array.reshape(shape)?),
+
+        OutputTypes::U8(array) => OutputTypes::U8(array.reshape(shape)?),
+        OutputTypes::U16(array) => OutputTypes::U16(array.reshape(shape)?),
+        OutputTypes::U32(array) => OutputTypes::U32(array.reshape(shape)?),
+        OutputTypes::U64(array) => OutputTypes::U64(array.reshape(shape)?),
+
+        OutputTypes::F16(array) => OutputTypes::F16(array.reshape(shape)?),
+        OutputTypes::F32(array) => OutputTypes::F32(array.reshape(shape)?),
+        OutputTypes::F64(array) => OutputTypes::F64(array.reshape(shape)?),
+
+        OutputTypes::Bool(array) => OutputTypes::Bool(array.reshape(shape)?),
+        output => output,
+    };
+    Ok(output)
+}
@@ -0,0 +1,67 @@
+use std::cell::RefCell;
+
+use serde::de;
+
+use crate::parsing::error_types::{self, PathSegment, RecordError};
+use crate::parsing::plan_types::{self, Plan};
+use crate::parsing::{OutputTypes, Structure};
+
+/// Records of the streamed structure, handed over every `chunk_size` records
+pub struct Chunk {
+    pub records: OutputTypes,
+    /// Records skipped or filled in this chunk, when `on_error` isn't "raise"
+    pub errors: Vec<RecordError>,
+}
+
+/// Receives the chunks, returning `false` once it doesn't want any more
+pub type Sink = Box<dyn FnMut(Chunk) -> bool>;
+
+struct Stream {
+    /// The top level field holding the streamed records, which the paths of the record errors start with
+    field: Option<String>,
+    sink: Sink,
+}
+
+thread_local! {
+    static STREAM: RefCell<Option<Stream>> = const { RefCell::new(None) };
+}
+
+/// Streams the transposed records of the next document on this thread, parsed with `structure`, or those of its top
+/// level `field` to `sink` every `chunk_size` records
+pub fn start(structure: &Structure, field: Option<&str>, chunk_size: usize, sink: Sink) {
+    let plan = match (structure, field) {
+        (Structure::Map(map), Some(field)) => Plan::field(map, field, Plan::Stream(chunk_size)),
+        (_, Some(_)) => None,
+        (_, None) => Some(Plan::Stream(chunk_size)),
+    };
+    plan_types::set(plan);
+    let field = field.map(str::to_string);
+    STREAM.with(|stream| *stream.borrow_mut() = Some(Stream { field, sink }));
+}
+
+/// Stops streaming, so later documents on this thread are parsed whole
+pub fn stop() {
+    STREAM.with(|stream| stream.borrow_mut().take());
+}
+
+/// Hands the records parsed so far to the sink, failing when it was dropped
+pub fn flush<E: de::Error>(records: OutputTypes) -> Result<(), E> {
+    let mut errors = error_types::take_record_errors();
+    let sent = STREAM.with(|stream| match &mut *stream.borrow_mut() {
+        Some(stream) => {
+            // the chunk is handed over before the document around the records is parsed, which would add the field
+            if let Some(field) = &stream.field {
+                for error in &mut errors {
+                    error.error.path.0.insert(0, PathSegment::Key(field.clone()));
+                }
+            }
+            (stream.sink)(Chunk { records, errors })
+        }
+        None => false,
+    });
+    if sent {
+        Ok(())
+    } else {
+        Err(de::Error::custom("Iteration over the records was stopped"))
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::errors::ErrorKind;
+use crate::parsing::bytes_types::{Bytes, BytesSeed};
+use crate::parsing::error_types;
+use crate::parsing::transpose_types::stack_column;
+use crate::parsing::{InputTypes, OutputTypes, TypeDescriptor};
+
+/// `{"tf_serving": {"a": "float32", "b": "str"}}`: a TensorFlow Serving REST request (or response) with named inputs,
+/// or `{"tf_serving": "float32"}` with a single unnamed one. In the row format, `{"instances": [...]}`, each instance
+/// holds a value of every input and the values are stacked into arrays of shape `[N, ...]`. In the columnar format,
+/// `{"inputs": ...}`, every input is given as a whole. `str` inputs become arrays of `bytes`, whose values may be
+/// base64 encoded `{"b64": ...}` maps
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "TfServingFields")]
+pub struct TfServing {
+    pub inputs: TfInputs,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TfInputs {
+    Single(InputTypes),
+    Named(IndexMap<String, InputTypes>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TfServingFields {
+    tf_serving: TfInputs,
+}
+
+impl TryFrom<TfServingFields> for TfServing {
+    type Error = String;
+
+    fn try_from(fields: TfServingFields) -> Result<Self, Self::Error> {
+        let dtypes: Vec<&InputTypes> = match &fields.tf_serving {
+            TfInputs::Single(dtype) => vec![dtype],
+            TfInputs::Named(inputs) => inputs.values().collect(),
+        };
+        match dtypes.into_iter().find(|dtype| !dtype.is_array() && !matches!(dtype, InputTypes::str)) {
+            Some(dtype) => Err(format!("TF Serving inputs can't have dtype {}, expected a numpy dtype or str", dtype)),
+            None => Ok(TfServing { inputs: fields.tf_serving }),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for TfServing {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        let dict = object.downcast::<PyDict>()?;
+        match (dict.len(), dict.get_item("tf_serving")) {
+            (1, Some(inputs)) => {
+                let inputs = match inputs.extract() {
+                    Ok(dtype) => TfInputs::Single(dtype),
+                    Err(_) => TfInputs::Named(inputs.extract()?),
+                };
+                TfServing::try_from(TfServingFields { tf_serving: inputs }).map_err(PyValueError::new_err)
+            }
+            _ => Err(PyValueError::new_err(format!("cannot parse {} as tf_serving structure", object))),
+        }
+    }
+}
+
+/// An empty input, onto which the values of the instances are stacked
+fn new_input(dtype: InputTypes) -> OutputTypes {
+    match dtype {
+        InputTypes::str => OutputTypes::Bytes(Bytes::new()),
+        dtype => dtype.get_transpose_output_type(),
+    }
+}
+
+/// The value of an input: a single instance in the row format, or the whole input in the columnar format
+struct ValueSeed(InputTypes);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match self.0 {
+            InputTypes::str => BytesSeed { b64: true }.deserialize(deserializer).map(OutputTypes::Bytes),
+            dtype => TypeDescriptor(dtype).deserialize(deserializer),
+        }
+    }
+}
+
+pub struct TfServingSeed<'s>(pub &'s TfServing);
+
+impl<'de, 's> DeserializeSeed<'de> for TfServingSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(TfServingVisitor(&self.0.inputs))
+    }
+}
+
+struct TfServingVisitor<'s>(&'s TfInputs);
+
+impl<'de, 's> Visitor<'de> for TfServingVisitor<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("TF Serving request with \"instances\" or \"inputs\"")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut output = None;
+        while let Some(key) = map.next_key::<String>()? {
+            let value = match key.as_str() {
+                // the row format of requests and responses
+                "instances" | "predictions" => map.next_value_seed(RowsSeed(self.0)),
+                // the columnar format of requests and responses
+                "inputs" | "outputs" => map.next_value_seed(ColumnsSeed(self.0)),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                    continue;
+                }
+            };
+            output = Some(value.map_err(error_types::at_key(&key))?);
+        }
+        output.ok_or_else(|| {
+            error_types::error(
+                ErrorKind::MissingKey,
+                Some("[\"instances\"] or [\"inputs\"]".to_string()),
+                None,
+                "Key(s) not found: [\"instances\"] or [\"inputs\"]",
+            )
+        })
+    }
+}
+
+fn missing_inputs_error<'i, E: de::Error>(not_seen_keys: impl Iterator<Item = &'i String>) -> E {
+    let not_seen_keys: Vec<&String> = not_seen_keys.collect();
+    error_types::error(
+        ErrorKind::MissingKey,
+        Some(format!("{not_seen_keys:?}")),
+        None,
+        format!("Key(s) not found: {not_seen_keys:?}"),
+    )
+}
+
+/// `[instance, ...]`, stacking the values of each input across the instances
+struct RowsSeed<'s>(&'s TfInputs);
+
+impl<'de, 's> DeserializeSeed<'de> for RowsSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's> Visitor<'de> for RowsSeed<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("list of instances")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut row = 0;
+        match self.0 {
+            TfInputs::Single(dtype) => {
+                let mut input = new_input(*dtype);
+                while let Some(value) = seq.next_element_seed(ValueSeed(*dtype)).map_err(error_types::at_index(row))? {
+                    stack_column(&mut input, value).map_err(error_types::at_index(row))?;
+                    row += 1;
+                }
+                Ok(input)
+            }
+            TfInputs::Named(dtypes) => {
+                let mut inputs: IndexMap<String, OutputTypes> =
+                    dtypes.iter().map(|(name, dtype)| (name.clone(), new_input(*dtype))).collect();
+                while seq.next_element_seed(InstanceSeed(dtypes, &mut inputs)).map_err(error_types::at_index(row))?.is_some() {
+                    row += 1;
+                }
+                Ok(OutputTypes::Map(inputs.into_iter().collect()))
+            }
+        }
+    }
+}
+
+/// `{"a": value, "b": value}`, stacking each value onto its input
+struct InstanceSeed<'s, 'i>(&'s IndexMap<String, InputTypes>, &'i mut IndexMap<String, OutputTypes>);
+
+impl<'de, 's, 'i> DeserializeSeed<'de> for InstanceSeed<'s, 'i> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 's, 'i> Visitor<'de> for InstanceSeed<'s, 'i> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "instance with the inputs {:?}", self.0.keys().collect::<Vec<_>>())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut seen = vec![false; self.0.len()];
+        while let Some(key) = map.next_key::<String>()? {
+            match self.0.get_full(&key) {
+                Some((index, _, dtype)) if !seen[index] => {
+                    let value = map.next_value_seed(ValueSeed(*dtype)).map_err(error_types::at_key(&key))?;
+                    stack_column(&mut self.1[index], value).map_err(error_types::at_key(&key))?;
+                    seen[index] = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if seen.iter().any(|seen| !seen) {
+            return Err(missing_inputs_error(self.0.keys().zip(seen).filter(|(_, seen)| !seen).map(|(key, _)| key)));
+        }
+        Ok(())
+    }
+}
+
+/// `{"a": value, "b": value}` holding whole inputs, or the value of a single input
+struct ColumnsSeed<'s>(&'s TfInputs);
+
+impl<'de, 's> DeserializeSeed<'de> for ColumnsSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match self.0 {
+            TfInputs::Single(dtype) => ValueSeed(*dtype).deserialize(deserializer),
+            TfInputs::Named(_) => deserializer.deserialize_map(self),
+        }
+    }
+}
+
+impl<'de, 's> Visitor<'de> for ColumnsSeed<'s> {
+    type Value = OutputTypes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("map of named inputs")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let dtypes = match self.0 {
+            TfInputs::Named(dtypes) => dtypes,
+            TfInputs::Single(_) => return Err(de::Error::invalid_type(de::Unexpected::Map, &self)),
+        };
+        let mut inputs: Vec<Option<OutputTypes>> = dtypes.iter().map(|_| None).collect();
+        while let Some(key) = map.next_key::<String>()? {
+            match dtypes.get_full(&key) {
+                Some((index, _, dtype)) => {
+                    inputs[index] = Some(map.next_value_seed(ValueSeed(*dtype)).map_err(error_types::at_key(&key))?);
+                }
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if inputs.iter().any(Option::is_none) {
+            return Err(missing_inputs_error(dtypes.keys().zip(&inputs).filter(|(_, input)| input.is_none()).map(|(key, _)| key)));
+        }
+        Ok(OutputTypes::Map(dtypes.keys().cloned().zip(inputs.into_iter().flatten()).collect()))
+    }
+}
@@ -1,12 +1,69 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::cell::Cell;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
 
+use indexmap::IndexMap;
 use itertools::Itertools;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
 use serde::de;
 use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 
-use crate::parsing::OutputTypes;
+use serde_json::Value;
+
+use crate::errors::ErrorKind;
+use crate::parsing::array_types::ColumnSeed;
+use crate::parsing::error_types::{self, OnError};
+use crate::parsing::limit_types;
+use crate::parsing::map_types::KeySeed;
+use crate::parsing::python_types::PythonType;
+use crate::parsing::record_types::Records;
+use crate::parsing::stream_types;
+use crate::parsing::union_types::{Content, ContentDeserializer};
+use crate::parsing::{InputTypes, OutputTypes};
+
+/// What happens to the sequences of a list of sequences of records (`[[[Type, ...]]]`) which have different lengths
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Ragged {
+    /// Raise an error for a sequence whose length differs from the first one
+    #[default]
+    Raise,
+    /// Pad the shorter sequences with missing values (NaN, 0, False or None) up to the longest one
+    Pad,
+}
+
+impl FromStr for Ragged {
+    type Err = PyErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raise" => Ok(Ragged::Raise),
+            "pad" => Ok(Ragged::Pad),
+            _ => Err(PyValueError::new_err(format!(
+                "unrecognised ragged {}. Expected one of: \"raise\", \"pad\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for Ragged {
+    fn extract(object: &'source PyAny) -> Result<Self, PyErr> {
+        Ragged::from_str(object.extract()?)
+    }
+}
+
+thread_local! {
+    static RAGGED: Cell<Ragged> = const { Cell::new(Ragged::Raise) };
+}
+
+/// Sets how sequences of different lengths are handled, call before parsing a document
+pub fn start(ragged: Ragged) {
+    RAGGED.with(|cell| cell.set(ragged));
+}
+
+pub fn ragged() -> Ragged {
+    RAGGED.with(Cell::get)
+}
 
 pub struct TransposeSeq<'s>(pub &'s mut Vec<OutputTypes>);
 
@@ -39,49 +96,47 @@ impl<'de, 's> Visitor<'de> for TransposeSeqVisitor<'s> {
         S: SeqAccess<'de>,
     {
         let out: &mut Vec<OutputTypes> = self.0 .0;
-        for output_type in out.iter_mut() {
-            let success = match output_type {
-                OutputTypes::I8(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::I16(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::I32(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::I64(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-
-                OutputTypes::U8(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::U16(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::U32(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::U64(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
+        for (column, output_type) in out.iter_mut().enumerate() {
+            let pushed = match output_type {
+                OutputTypes::I8(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::I16(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::I32(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::I64(arr) => seq.next_element_seed(ColumnSeed(arr)),
 
-                OutputTypes::F32(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
-                OutputTypes::F64(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
+                OutputTypes::U8(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::U16(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::U32(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::U64(arr) => seq.next_element_seed(ColumnSeed(arr)),
 
-                OutputTypes::Bool(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
+                OutputTypes::F16(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::F32(arr) => seq.next_element_seed(ColumnSeed(arr)),
+                OutputTypes::F64(arr) => seq.next_element_seed(ColumnSeed(arr)),
 
-                OutputTypes::PyList(arr) => seq.next_element()?.map(|new_arr| arr.push(new_arr)),
+                OutputTypes::Bool(arr) => seq.next_element_seed(ColumnSeed(arr)),
 
-                _ => panic!(
-                    "other variants shoudn't be able to occur because of logic in StructureVisitor"
-                ),
-            };
-            match success {
-                Some(()) => {}
-                None => {
-                    drop(out);
-                    return Err(de::Error::custom(format!(
-                        "Too many columns specified: {} ({})",
-                        DisplayVecOutputTypes(self.0 .0),
-                        self.0 .0.len(),
-                    )));
+                OutputTypes::PyList(arr) | OutputTypes::Categorical(arr) => {
+                    seq.next_element().map(|value| value.map(|value| arr.push(value)))
                 }
+
+                other => Err(column_error(other)),
             };
+            if pushed.map_err(error_types::at_index(column))?.is_none() {
+                return Err(de::Error::custom(format!(
+                    "Too many columns specified: {} ({})",
+                    DisplayVecOutputTypes(self.0 .0),
+                    self.0 .0.len(),
+                )));
+            }
         }
-        while let Some(_) = seq.next_element::<IgnoredAny>()? {
+        while seq.next_element::<IgnoredAny>()?.is_some() {
             // empty any remaining items from the list with unspecified types
         }
         Ok(self.0)
     }
 }
 
-pub struct TransposeMap<'s>(pub &'s mut HashMap<String, OutputTypes>);
+/// Columns to push each row's values onto, plus a scratch buffer of which columns the current row has filled
+pub struct TransposeMap<'s>(pub &'s mut IndexMap<String, OutputTypes>, pub &'s mut Vec<bool>);
 
 impl<'de, 's> DeserializeSeed<'de> for TransposeMap<'s> {
     type Value = TransposeMap<'s>;
@@ -103,7 +158,7 @@ impl<'de, 's> Visitor<'de> for TransposeMapVisitor<'s> {
         write!(
             formatter,
             "map with elements: {}",
-            DisplayHashMapOutputTypes(self.0 .0)
+            DisplayIndexMapOutputTypes(self.0 .0)
         )
     }
 
@@ -111,52 +166,542 @@ impl<'de, 's> Visitor<'de> for TransposeMapVisitor<'s> {
     where
         A: MapAccess<'de>,
     {
-        let out: &mut HashMap<String, OutputTypes> = self.0 .0;
-        let n_keys = out.len();
-        let mut seen_keys = HashSet::with_capacity(n_keys);
-        while let Some(key) = map.next_key::<String>()? {
-            if let Some(output_type) = out.get_mut(&key) {
-                seen_keys.insert(key);
+        let TransposeMap(out, seen) = self.0;
+        seen.clear();
+        seen.resize(out.len(), false);
+        while let Some(index) = map.next_key_seed(KeySeed(out))? {
+            if let Some((index, (key, output_type))) = index.and_then(|index| Some((index, out.get_index_mut(index)?))) {
+                seen[index] = true;
+                let at_key = error_types::at_key(key);
                 match output_type {
-                    OutputTypes::I8(arr) => arr.push(map.next_value()?),
-                    OutputTypes::I16(arr) => arr.push(map.next_value()?),
-                    OutputTypes::I32(arr) => arr.push(map.next_value()?),
-                    OutputTypes::I64(arr) => arr.push(map.next_value()?),
+                    OutputTypes::I8(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::I16(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::I32(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::I64(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
 
-                    OutputTypes::U8(arr) => arr.push(map.next_value()?),
-                    OutputTypes::U16(arr) => arr.push(map.next_value()?),
-                    OutputTypes::U32(arr) => arr.push(map.next_value()?),
-                    OutputTypes::U64(arr) => arr.push(map.next_value()?),
+                    OutputTypes::U8(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::U16(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::U32(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::U64(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
 
-                    OutputTypes::F32(arr) => arr.push(map.next_value()?),
-                    OutputTypes::F64(arr) => arr.push(map.next_value()?),
+                    OutputTypes::F16(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::F32(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
+                    OutputTypes::F64(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
 
-                    OutputTypes::Bool(arr) => arr.push(map.next_value()?),
+                    OutputTypes::Bool(arr) => map.next_value_seed(ColumnSeed(arr)).map_err(at_key)?,
 
-                    OutputTypes::PyList(arr) => arr.push(map.next_value()?),
-                    _ => panic!(
-                        "other variants shoudn't be able to occur because of logic in StructureVisitor"
-                    ),
+                    OutputTypes::PyList(arr) | OutputTypes::Categorical(arr) => {
+                        arr.push(map.next_value().map_err(at_key)?)
+                    }
+                    other => return Err(column_error(other)),
                 }
             } else {
                 // if the `out` map doesn't contain a key in the map (i.e. it wasn't included in the structure) we ignore it
                 map.next_value::<IgnoredAny>()?;
             }
         }
-        if n_keys > seen_keys.len() {
+        if seen.contains(&false) {
             let not_seen_keys = out
-                .iter()
-                .filter(|(k, _)| !seen_keys.contains(k.clone()))
-                .map(|(k, _)| k.clone())
+                .keys()
+                .zip(seen.iter())
+                .filter(|(_, seen)| !**seen)
+                .map(|(k, _)| k)
                 .collect_vec();
-            return Err(de::Error::custom(format!(
-                "Key(s) not found: {not_seen_keys:?}"
-            )));
+            return Err(error_types::error(
+                ErrorKind::MissingKey,
+                Some(format!("{not_seen_keys:?}")),
+                None,
+                format!("Key(s) not found: {not_seen_keys:?}"),
+            ));
         }
-        Ok(self.0)
+        Ok(TransposeMap(out, seen))
+    }
+}
+
+/// A sequence of records `[[value, ...], ...]`, transposed into new columns of at most `capacity` records. Gives the
+/// columns and the number of records
+struct SequenceSeed<'t>(&'t [InputTypes], usize);
+
+impl<'de, 't> DeserializeSeed<'de> for SequenceSeed<'t> {
+    type Value = (Vec<OutputTypes>, usize);
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 't> Visitor<'de> for SequenceSeed<'t> {
+    type Value = (Vec<OutputTypes>, usize);
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("sequence of records")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut columns = new_columns(self.0);
+        let mut rows = 0;
+        while seq.next_element_seed(TransposeSeq(&mut columns)).map_err(error_types::at_index(rows))?.is_some() {
+            rows += 1;
+            if rows > self.1 {
+                return Err(error_types::at_index(rows - 1)(limit_types::capacity_error(self.1)));
+            }
+        }
+        Ok((columns, rows))
     }
 }
 
+fn new_columns(types: &[InputTypes]) -> Vec<OutputTypes> {
+    types.iter().map(InputTypes::get_transpose_output_type).collect()
+}
+
+/// The columns of a transposed list, which `transpose_records` parses the records onto one at a time
+pub trait Columns {
+    /// Parses the next record of `seq` onto the columns, `None` at the end of `seq`
+    fn next_record<'de, S: SeqAccess<'de>>(&mut self, seq: &mut S) -> Result<Option<()>, S::Error>;
+
+    /// Parses a record that was buffered so that parsing can carry on with the next one if it fails
+    fn buffered_record(&mut self, record: &Content) -> Result<(), de::value::Error>;
+
+    /// Undoes what a failed record parsed onto the columns, which held `rows` records before it. With
+    /// `OnError::Fill` the record is then replaced by missing values
+    fn recover<E: de::Error>(&mut self, rows: usize, on_error: OnError) -> Result<(), E>;
+
+    /// Fails once `rows` records go over the limits
+    fn check_rows<E: de::Error>(&self, rows: usize) -> Result<(), E>;
+
+    /// Takes the `rows` records parsed so far, leaving empty columns
+    fn take<E: de::Error>(&mut self, rows: usize) -> Result<Records, E>;
+
+    /// Takes the `rows` records left once the list ended, counting them towards `max_output_bytes`
+    fn finish<E: de::Error>(self, rows: usize) -> Result<Records, E>;
+}
+
+/// Parses the records of a transposed list onto `columns`. Failing records are skipped or filled following
+/// `on_error`, unless they go over the limits, and the records are flushed every `chunk_size` records when they are
+/// streamed
+pub fn transpose_records<'de, S, C>(mut seq: S, mut columns: C, chunk_size: Option<usize>) -> Result<Records, S::Error>
+where
+    S: SeqAccess<'de>,
+    C: Columns,
+{
+    let on_error = error_types::on_error();
+    // the limits apply to each streamed chunk, so the bytes of the python values of a chunk are forgotten with it
+    let chunk_bytes = limit_types::output_bytes();
+    let mut rows = 0;
+    for row in 0.. {
+        if on_error == OnError::Raise {
+            if columns.next_record(&mut seq).map_err(error_types::at_index(row))?.is_none() {
+                break;
+            }
+        } else {
+            // the record is buffered so that parsing can carry on with the next one if it fails
+            let Some(record) = limit_types::buffering(|| seq.next_element::<Content>()).map_err(error_types::at_index(row))?
+            else {
+                break;
+            };
+            if let Err(err) = columns.buffered_record(&record) {
+                if error_types::is_limit_error() {
+                    return Err(error_types::at_index(row)(de::Error::custom(err)));
+                }
+                error_types::record_error(row, err.to_string());
+                columns.recover(rows, on_error)?;
+                if on_error == OnError::Skip {
+                    continue;
+                }
+            }
+        }
+        rows += 1;
+        columns.check_rows(rows).map_err(error_types::at_index(row))?;
+        if chunk_size == Some(rows) {
+            let chunk = columns.take(rows)?;
+            stream_types::flush(OutputTypes::Records(chunk)).map_err(error_types::at_index(row))?;
+            limit_types::reset_output_bytes(chunk_bytes);
+            rows = 0;
+        }
+    }
+    if chunk_size.is_some() && (rows > 0 || error_types::has_record_errors()) {
+        // the streamed records are all flushed, leaving the returned columns empty
+        stream_types::flush(OutputTypes::Records(columns.take(rows)?))?;
+        limit_types::reset_output_bytes(chunk_bytes);
+        rows = 0;
+    }
+    columns.finish(rows)
+}
+
+/// The types of the columns of `[[Type, ...]]` or `[{key: Type, ...}]` records
+#[derive(Clone, Copy)]
+pub enum RecordTypes<'t> {
+    Seq(&'t [InputTypes]),
+    Map(&'t IndexMap<String, InputTypes>),
+}
+
+impl<'t> RecordTypes<'t> {
+    fn new_columns(self) -> Records {
+        match self {
+            RecordTypes::Seq(types) => Records::Seq(new_columns(types)),
+            RecordTypes::Map(types) => Records::Map(
+                types
+                    .iter()
+                    .map(|(key, input_type)| (key.clone(), input_type.get_transpose_output_type()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The columns of `[[Type, ...]]` or `[{key: Type, ...}]` records, one value of each record per column
+pub struct RecordColumns<'t> {
+    types: RecordTypes<'t>,
+    out: Records,
+    /// Which columns the current record of a map has filled, see `TransposeMap`
+    seen: Vec<bool>,
+    /// Records filled before any record parsed. They are added once one does, as it gives the shape of the values
+    pending: usize,
+    record_size: usize,
+}
+
+impl<'t> RecordColumns<'t> {
+    pub fn new(types: RecordTypes<'t>) -> RecordColumns<'t> {
+        let out = types.new_columns();
+        let record_size = record_size(out.columns());
+        RecordColumns { types, out, seen: Vec::new(), pending: 0, record_size }
+    }
+
+    /// Adds the pending filled records before the records parsed so far
+    fn fill_pending(&mut self) {
+        if self.pending > 0 {
+            for column in self.out.columns_mut() {
+                insert_missing(column, self.pending);
+            }
+            self.pending = 0;
+        }
+    }
+}
+
+impl<'t> Columns for RecordColumns<'t> {
+    fn next_record<'de, S: SeqAccess<'de>>(&mut self, seq: &mut S) -> Result<Option<()>, S::Error> {
+        match &mut self.out {
+            Records::Seq(out) => Ok(seq.next_element_seed(TransposeSeq(out))?.map(drop)),
+            Records::Map(out) => Ok(seq.next_element_seed(TransposeMap(out, &mut self.seen))?.map(drop)),
+        }
+    }
+
+    fn buffered_record(&mut self, record: &Content) -> Result<(), de::value::Error> {
+        match &mut self.out {
+            Records::Seq(out) => TransposeSeq(out).deserialize(ContentDeserializer(record)).map(drop)?,
+            Records::Map(out) => TransposeMap(out, &mut self.seen).deserialize(ContentDeserializer(record)).map(drop)?,
+        };
+        self.fill_pending();
+        Ok(())
+    }
+
+    fn recover<E: de::Error>(&mut self, rows: usize, on_error: OnError) -> Result<(), E> {
+        let parsed = rows - self.pending;
+        if on_error == OnError::Fill && parsed == 0 {
+            // a missing value pushed now would be a scalar, whatever the shape of the values of the column
+            recover_record(self.out.columns_mut(), 0, OnError::Skip)?;
+            self.pending += 1;
+            return Ok(());
+        }
+        recover_record(self.out.columns_mut(), parsed, on_error)
+    }
+
+    fn check_rows<E: de::Error>(&self, rows: usize) -> Result<(), E> {
+        // the python values of the records are counted as they are parsed, leaving less room for the columns
+        let capacity = limit_types::capacity(self.record_size);
+        if rows > capacity {
+            return Err(limit_types::capacity_error(capacity));
+        }
+        Ok(())
+    }
+
+    fn take<E: de::Error>(&mut self, _rows: usize) -> Result<Records, E> {
+        self.fill_pending();
+        Ok(std::mem::replace(&mut self.out, self.types.new_columns()))
+    }
+
+    fn finish<E: de::Error>(mut self, rows: usize) -> Result<Records, E> {
+        limit_types::add_output_bytes(rows * self.record_size);
+        self.fill_pending();
+        Ok(self.out)
+    }
+}
+
+/// The columns of a list of sequences of records (`[[[Type, ...]]]`), one sequence of each column per item. Sequences
+/// of different lengths raise an error, or with `Ragged::Pad` the shorter ones are padded with missing values
+pub struct SequenceColumns<'t> {
+    types: &'t [InputTypes],
+    ragged: Ragged,
+    out: Vec<OutputTypes>,
+    /// The sequences whose padded length isn't known yet: all of them with `Ragged::Pad`, and with `Ragged::Raise`
+    /// the filled ones (`None`) before the first parsed sequence sets the length
+    pending: Vec<Option<(Vec<OutputTypes>, usize)>>,
+    /// Number of records of each sequence, the first one's or with `Ragged::Pad` the longest one's
+    length: Option<usize>,
+    record_size: usize,
+    capacity: usize,
+}
+
+impl<'t> SequenceColumns<'t> {
+    pub fn new(types: &'t [InputTypes]) -> SequenceColumns<'t> {
+        let record_size = record_size(new_columns(types).iter());
+        let capacity = limit_types::capacity(record_size);
+        SequenceColumns { types, ragged: ragged(), out: Self::new_out(types), pending: Vec::new(), length: None, record_size, capacity }
+    }
+
+    /// Lists can't be categories, so `category` columns are kept as lists of lists
+    fn new_out(types: &[InputTypes]) -> Vec<OutputTypes> {
+        new_columns(types)
+            .into_iter()
+            .map(|column| match column {
+                OutputTypes::Categorical(values) => OutputTypes::PyList(values),
+                column => column,
+            })
+            .collect()
+    }
+
+    fn push<E: de::Error>(&mut self, (columns, len): (Vec<OutputTypes>, usize)) -> Result<(), E> {
+        match (self.ragged, self.length) {
+            (Ragged::Raise, Some(length)) if len != length => Err(ragged_error(len, length)),
+            (Ragged::Raise, _) => {
+                self.length = Some(len);
+                // the filled sequences before the first one take its length
+                for sequence in std::mem::take(&mut self.pending) {
+                    stack_sequence(&mut self.out, self.types, sequence, len)?;
+                }
+                stack_sequence(&mut self.out, self.types, Some((columns, len)), len)
+            }
+            (Ragged::Pad, length) => {
+                self.length = Some(length.map_or(len, |length| length.max(len)));
+                self.pending.push(Some((columns, len)));
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'t> Columns for SequenceColumns<'t> {
+    fn next_record<'de, S: SeqAccess<'de>>(&mut self, seq: &mut S) -> Result<Option<()>, S::Error> {
+        match seq.next_element_seed(SequenceSeed(self.types, self.capacity))? {
+            Some(sequence) => self.push(sequence).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn buffered_record(&mut self, record: &Content) -> Result<(), de::value::Error> {
+        let sequence = SequenceSeed(self.types, self.capacity).deserialize(ContentDeserializer(record))?;
+        self.push(sequence)
+    }
+
+    fn recover<E: de::Error>(&mut self, _rows: usize, on_error: OnError) -> Result<(), E> {
+        match (on_error, self.ragged, self.length) {
+            (OnError::Fill, Ragged::Raise, Some(length)) => stack_sequence(&mut self.out, self.types, None, length),
+            (OnError::Fill, ..) => {
+                self.pending.push(None);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_rows<E: de::Error>(&self, rows: usize) -> Result<(), E> {
+        padded_size(rows, self.length.unwrap_or(0), limit_types::capacity(self.record_size)).map(drop)
+    }
+
+    fn take<E: de::Error>(&mut self, _rows: usize) -> Result<Records, E> {
+        let length = self.length.take().unwrap_or(0);
+        for sequence in std::mem::take(&mut self.pending) {
+            stack_sequence(&mut self.out, self.types, sequence, length)?;
+        }
+        Ok(Records::Seq(std::mem::replace(&mut self.out, Self::new_out(self.types))))
+    }
+
+    fn finish<E: de::Error>(mut self, rows: usize) -> Result<Records, E> {
+        let padded = padded_size(rows, self.length.unwrap_or(0), limit_types::capacity(self.record_size))?;
+        limit_types::add_output_bytes(padded * self.record_size);
+        self.take(rows)
+    }
+}
+
+/// Number of records of `count` sequences padded to `length` records, checked against the `capacity` before padding
+fn padded_size<E: de::Error>(count: usize, length: usize, capacity: usize) -> Result<usize, E> {
+    count
+        .checked_mul(length)
+        .filter(|padded| *padded <= capacity)
+        .ok_or_else(|| limit_types::capacity_error(capacity))
+}
+
+/// Pads a sequence (or a filled one, `None`) with missing values up to `length` records and stacks it onto `out`
+fn stack_sequence<E: de::Error>(
+    out: &mut [OutputTypes],
+    types: &[InputTypes],
+    sequence: Option<(Vec<OutputTypes>, usize)>,
+    length: usize,
+) -> Result<(), E> {
+    let (mut columns, len) = sequence.unwrap_or_else(|| (new_columns(types), 0));
+    for _ in len..length {
+        for column in columns.iter_mut() {
+            push_missing(column)?;
+        }
+    }
+    for (column, item) in out.iter_mut().zip(columns) {
+        stack_column(column, item)?;
+    }
+    Ok(())
+}
+
+fn ragged_error<E: de::Error>(len: usize, length: usize) -> E {
+    error_types::error(
+        ErrorKind::Shape,
+        Some(format!("{} records", length)),
+        Some(format!("{} records", len)),
+        format!(
+            "Sequence of {} records doesn't match the {} records of the first sequence. Pass ragged=\"pad\" to pad the shorter sequences",
+            len, length
+        ),
+    )
+}
+
+/// Appends a column (or a single value) as the next item of `column` along its first axis, see `array_types::stack`.
+/// A list of python values is appended to a list column as one list
+pub fn stack_column<E: de::Error>(column: &mut OutputTypes, item: OutputTypes) -> Result<(), E> {
+    match (column, item) {
+        (OutputTypes::I8(arr), OutputTypes::I8(item)) => arr.stack(item),
+        (OutputTypes::I16(arr), OutputTypes::I16(item)) => arr.stack(item),
+        (OutputTypes::I32(arr), OutputTypes::I32(item)) => arr.stack(item),
+        (OutputTypes::I64(arr), OutputTypes::I64(item)) => arr.stack(item),
+
+        (OutputTypes::U8(arr), OutputTypes::U8(item)) => arr.stack(item),
+        (OutputTypes::U16(arr), OutputTypes::U16(item)) => arr.stack(item),
+        (OutputTypes::U32(arr), OutputTypes::U32(item)) => arr.stack(item),
+        (OutputTypes::U64(arr), OutputTypes::U64(item)) => arr.stack(item),
+
+        (OutputTypes::F16(arr), OutputTypes::F16(item)) => arr.stack(item),
+        (OutputTypes::F32(arr), OutputTypes::F32(item)) => arr.stack(item),
+        (OutputTypes::F64(arr), OutputTypes::F64(item)) => arr.stack(item),
+
+        (OutputTypes::Bool(arr), OutputTypes::Bool(item)) => arr.stack(item),
+        (OutputTypes::Bytes(arr), OutputTypes::Bytes(item)) => arr.stack(item),
+
+        (OutputTypes::PyList(arr), OutputTypes::PyList(item) | OutputTypes::Categorical(item)) => {
+            arr.push(PythonType(Value::Array(item.into_iter().map(|value| value.0).collect())));
+            Ok(())
+        }
+        (column, item) => Err(de::Error::custom(format!("Cannot stack {} onto {}", item, column))),
+    }
+}
+
+/// Number of bytes a record takes up in the transposed columns
+pub fn record_size<'c>(columns: impl Iterator<Item = &'c OutputTypes>) -> usize {
+    columns
+        .map(|column| match column {
+            OutputTypes::I8(_) | OutputTypes::U8(_) | OutputTypes::Bool(_) => 1,
+            OutputTypes::I16(_) | OutputTypes::U16(_) | OutputTypes::F16(_) => 2,
+            OutputTypes::I32(_) | OutputTypes::U32(_) | OutputTypes::F32(_) => 4,
+            OutputTypes::I64(_) | OutputTypes::U64(_) | OutputTypes::F64(_) => 8,
+            _ => std::mem::size_of::<PythonType>(),
+        })
+        .sum()
+}
+
+/// Undoes the values a record which failed part way pushed onto `columns`, leaving `rows` values in each column.
+/// With `OnError::Fill` the record is then replaced by missing values
+pub fn recover_record<'c, E: de::Error>(
+    columns: impl Iterator<Item = &'c mut OutputTypes>,
+    rows: usize,
+    on_error: OnError,
+) -> Result<(), E> {
+    for column in columns {
+        match column {
+            OutputTypes::I8(arr) => arr.truncate(rows),
+            OutputTypes::I16(arr) => arr.truncate(rows),
+            OutputTypes::I32(arr) => arr.truncate(rows),
+            OutputTypes::I64(arr) => arr.truncate(rows),
+
+            OutputTypes::U8(arr) => arr.truncate(rows),
+            OutputTypes::U16(arr) => arr.truncate(rows),
+            OutputTypes::U32(arr) => arr.truncate(rows),
+            OutputTypes::U64(arr) => arr.truncate(rows),
+
+            OutputTypes::F16(arr) => arr.truncate(rows),
+            OutputTypes::F32(arr) => arr.truncate(rows),
+            OutputTypes::F64(arr) => arr.truncate(rows),
+
+            OutputTypes::Bool(arr) => arr.truncate(rows),
+
+            OutputTypes::PyList(arr) | OutputTypes::Categorical(arr) => arr.truncate(rows),
+            _ => {}
+        }
+        if on_error == OnError::Fill {
+            push_missing(column)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends a missing value to `column`: NaN, 0, False or None
+fn push_missing<E: de::Error>(column: &mut OutputTypes) -> Result<(), E> {
+    match column {
+        OutputTypes::I8(arr) => arr.push_missing()?,
+        OutputTypes::I16(arr) => arr.push_missing()?,
+        OutputTypes::I32(arr) => arr.push_missing()?,
+        OutputTypes::I64(arr) => arr.push_missing()?,
+
+        OutputTypes::U8(arr) => arr.push_missing()?,
+        OutputTypes::U16(arr) => arr.push_missing()?,
+        OutputTypes::U32(arr) => arr.push_missing()?,
+        OutputTypes::U64(arr) => arr.push_missing()?,
+
+        OutputTypes::F16(arr) => arr.push_missing()?,
+        OutputTypes::F32(arr) => arr.push_missing()?,
+        OutputTypes::F64(arr) => arr.push_missing()?,
+
+        OutputTypes::Bool(arr) => arr.push_missing()?,
+
+        OutputTypes::PyList(arr) | OutputTypes::Categorical(arr) => arr.push(PythonType(Value::Null)),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Inserts `count` missing values before the values of `column`, see `push_missing`
+fn insert_missing(column: &mut OutputTypes, count: usize) {
+    match column {
+        OutputTypes::I8(arr) => arr.insert_missing(count),
+        OutputTypes::I16(arr) => arr.insert_missing(count),
+        OutputTypes::I32(arr) => arr.insert_missing(count),
+        OutputTypes::I64(arr) => arr.insert_missing(count),
+
+        OutputTypes::U8(arr) => arr.insert_missing(count),
+        OutputTypes::U16(arr) => arr.insert_missing(count),
+        OutputTypes::U32(arr) => arr.insert_missing(count),
+        OutputTypes::U64(arr) => arr.insert_missing(count),
+
+        OutputTypes::F16(arr) => arr.insert_missing(count),
+        OutputTypes::F32(arr) => arr.insert_missing(count),
+        OutputTypes::F64(arr) => arr.insert_missing(count),
+
+        OutputTypes::Bool(arr) => arr.insert_missing(count),
+
+        OutputTypes::PyList(arr) | OutputTypes::Categorical(arr) => {
+            arr.splice(0..0, std::iter::repeat_with(|| PythonType(Value::Null)).take(count));
+        }
+        _ => {}
+    }
+}
+
+/// The error for a column whose type can't be transposed, which the structure parsing should already rule out
+fn column_error<E: de::Error>(column: &OutputTypes) -> E {
+    de::Error::custom(format!("Cannot transpose a column of {}", column))
+}
+
 struct DisplayVecOutputTypes<'d>(&'d Vec<OutputTypes>);
 
 impl<'d> Display for DisplayVecOutputTypes<'d> {
@@ -171,15 +716,15 @@ impl<'d> Display for DisplayVecOutputTypes<'d> {
     }
 }
 
-struct DisplayHashMapOutputTypes<'d>(&'d HashMap<String, OutputTypes>);
+struct DisplayIndexMapOutputTypes<'d>(&'d IndexMap<String, OutputTypes>);
 
-impl<'d> Display for DisplayHashMapOutputTypes<'d> {
+impl<'d> Display for DisplayIndexMapOutputTypes<'d> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         write!(
             f,
             "{{{}}}",
             self.0.iter().fold(String::new(), |agg, (key, var)| agg
-                + format!("\"{}\": {}", key, var.to_string()).as_str()
+                + format!("\"{}\": {}", key, var).as_str()
                 + ", ")
         )
     }
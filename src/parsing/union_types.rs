@@ -0,0 +1,217 @@
+use std::fmt;
+
+use serde::de;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize};
+
+use crate::parsing::error_types;
+use crate::parsing::limit_types;
+use crate::parsing::{OutputTypes, Structure, StructureSeed};
+
+pub struct OneOfSeed<'s>(pub &'s [Structure]);
+
+impl<'de, 's> DeserializeSeed<'de> for OneOfSeed<'s> {
+    type Value = OutputTypes;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // buffer the value so that each alternative can be tried against it in turn
+        let buffered = limit_types::buffering(|| Content::deserialize(deserializer))?;
+        let mut errors = Vec::with_capacity(self.0.len());
+        let depth = error_types::depth();
+        let record_errors = error_types::record_errors_len();
+        for alternative in self.0 {
+            match StructureSeed(alternative).deserialize(ContentDeserializer(&buffered)) {
+                Ok(value) => return Ok(value),
+                // the value is too large for any alternative
+                Err(err) if error_types::is_limit_error() => return Err(de::Error::custom(err)),
+                Err(err) => errors.push(err.to_string()),
+            }
+            // the error is reported for the oneOf as a whole so drop the path into the alternative, and the records
+            // the alternative skipped or filled before it failed
+            error_types::truncate(depth);
+            error_types::truncate_record_errors(record_errors);
+        }
+        Err(de::Error::custom(format!(
+            "Value did not match any oneOf alternative: [{}]",
+            errors.join(", ")
+        )))
+    }
+}
+
+/// A value of any format buffered in memory. Unlike `serde_json::Value` it keeps binary data (msgpack `bin`) apart
+/// from strings and lists, so binary leaves and msgpack-numpy arrays can match an alternative or a skipped record
+#[derive(Debug)]
+pub enum Content {
+    Unit,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Seq(Vec<Content>),
+    /// The entries in the order of the document
+    Map(Vec<(Content, Content)>),
+}
+
+impl<'de> Deserialize<'de> for Content {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ContentVisitor)
+    }
+}
+
+struct ContentVisitor;
+
+impl<'de> Visitor<'de> for ContentVisitor {
+    type Value = Content;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Content, E> {
+        Ok(Content::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Content, E> {
+        Ok(Content::I64(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Content, E> {
+        Ok(Content::U64(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Content, E> {
+        Ok(Content::F64(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Content, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Content::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Content, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Content::String(value))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Content, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Content::Bytes(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Content, E> {
+        limit_types::reserve_output_bytes(value.len())?;
+        Ok(Content::Bytes(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Content, E> {
+        Ok(Content::Unit)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Content, E> {
+        Ok(Content::Unit)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Content, D::Error> {
+        Content::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Content, D::Error> {
+        Content::deserialize(deserializer)
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Content, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(value) = seq.next_element().map_err(error_types::at_index(values.len()))? {
+            values.push(value);
+            limit_types::check_elements(values.len())?;
+            limit_types::reserve_output_bytes(std::mem::size_of::<Content>())?;
+        }
+        Ok(Content::Seq(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Content, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let _depth = limit_types::enter()?;
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0).min(4096));
+        while let Some(key) = map.next_key()? {
+            let value = match &key {
+                Content::String(key) => map.next_value().map_err(error_types::at_key(key))?,
+                _ => map.next_value()?,
+            };
+            entries.push((key, value));
+            limit_types::check_elements(entries.len())?;
+            limit_types::reserve_output_bytes(std::mem::size_of::<(Content, Content)>())?;
+        }
+        Ok(Content::Map(entries))
+    }
+}
+
+/// Replays buffered content, lending out its strings and bytes
+#[derive(Clone, Copy)]
+pub struct ContentDeserializer<'c>(pub &'c Content);
+
+impl<'c> IntoDeserializer<'c, de::value::Error> for ContentDeserializer<'c> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'c> Deserializer<'c> for ContentDeserializer<'c> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Content::Unit => visitor.visit_unit(),
+            Content::Bool(value) => visitor.visit_bool(*value),
+            Content::I64(value) => visitor.visit_i64(*value),
+            Content::U64(value) => visitor.visit_u64(*value),
+            Content::F64(value) => visitor.visit_f64(*value),
+            Content::String(value) => visitor.visit_borrowed_str(value),
+            Content::Bytes(value) => visitor.visit_borrowed_bytes(value),
+            Content::Seq(values) => {
+                let mut seq = SeqDeserializer::new(values.iter().map(ContentDeserializer));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Content::Map(entries) => {
+                let mut map =
+                    MapDeserializer::new(entries.iter().map(|(key, value)| (ContentDeserializer(key), ContentDeserializer(value))));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Content::Unit => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'c>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        <W: Visitor<'c>>
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
@@ -6,6 +6,7 @@ from PIL import Image
 from skimage.color import rgb2lab
 from skimage.filters import gaussian
 
+import serde_numpy
 from serde_numpy import decode_jpeg, decode_png
 
 from .fixtures import img_array
@@ -82,4 +83,46 @@ def test_decode_png_grayscale(grayscale_png_bytes: bytes):
 
 
 def test_decode_png_rgba(rgba_png_bytes: bytes):
-    assert decode_png(rgba_png_bytes) is not None
\ No newline at end of file
+    assert decode_png(rgba_png_bytes) is not None
+
+def test_decode_png_out(png_bytes: bytes):
+    batch = np.zeros((2,) + img_array.shape, dtype=np.uint8)
+    assert decode_png(png_bytes, out=batch, index=1) is batch
+    assert np.array_equal(batch[1], decode_png(png_bytes))
+    assert np.all(batch[0] == 0)
+
+
+def test_decode_jpeg_out(jpeg_bytes: bytes):
+    out = np.zeros(img_array.shape, dtype=np.uint8)
+    decode_jpeg(jpeg_bytes, out=out)
+    assert np.array_equal(out, decode_jpeg(jpeg_bytes))
+
+
+def test_decode_out_wrong_shape(png_bytes: bytes):
+    with pytest.raises(serde_numpy.ShapeError):
+        decode_png(png_bytes, out=np.zeros((1, 1, 3), dtype=np.uint8))
+
+
+def test_decode_png_torch(png_bytes: bytes):
+    torch = pytest.importorskip("torch")
+    img = decode_png(png_bytes, arrays="torch")
+    assert isinstance(img, torch.Tensor)
+    assert np.array_equal(img.numpy(), decode_png(png_bytes))
+
+
+@pytest.mark.parametrize("limits", [{"max_width": 9}, {"max_height": 9}, {"max_pixels": 99}])
+def test_decode_limits(png_bytes: bytes, jpeg_bytes: bytes, limits: dict):
+    height, width, _ = img_array.shape
+    assert decode_png(png_bytes, limits={"max_width": width, "max_height": height, "max_pixels": width * height}) is not None
+    with pytest.raises(serde_numpy.LimitError):
+        decode_png(png_bytes, limits=limits)
+    with pytest.raises(serde_numpy.LimitError):
+        decode_jpeg(jpeg_bytes, limits=limits)
+
+
+@pytest.mark.parametrize("data", [b"", b"\x89PNG\r\n\x1a\n", b"\xff\xd8\xff", b"not an image"])
+def test_decode_malformed(data: bytes):
+    with pytest.raises(serde_numpy.ImageDecodeError):
+        decode_png(data)
+    with pytest.raises(serde_numpy.ImageDecodeError):
+        decode_jpeg(data)
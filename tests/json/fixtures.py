@@ -24,6 +24,8 @@ json_str = b"""{
         "stream3":[[-2.1727126743596266,0,1],
                    [-0.06389102863189458,1,2],
                    [1.3716941547285826,1,3]],
+        "union_bare":[1.5,2.5],
+        "union_wrapped":{"values":[1.5,2.5],"shape":[2]},
         "stream4":[{"x":-2.1727126743596266,"y":0,"z":1},
                    {"x":-0.06389102863189458,"y":1,"z":2},
                    {"x":1.3716941547285826,"y":1,"z":3}],
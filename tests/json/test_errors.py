@@ -1,11 +1,15 @@
+import gzip
+import io
 import pytest
 from typing import Any, Callable, Tuple, Type
 
+import msgpack
 import numpy as np
 
 from .fixtures import json_str, msgpack_bytes, wonky_json_str, wonky_msgpack_bytes
 from .utils import deserialize_json, deserialize_msgpack, assert_correct_types, assert_same_structure
 
+import serde_numpy
 from serde_numpy import NumpyDeserializer
 
 
@@ -54,6 +58,24 @@ def test_list_of_nested_structure():
     with pytest.raises(TypeError) as e:
         NumpyDeserializer.from_dict(structure)
     assert str(e.value).startswith("""structure unsupported. Currently sequences of nested structures are unsupported e.g. [{\"a\": {\"b\": Type}}])""")
+    assert isinstance(e.value, serde_numpy.StructureError)
+    assert isinstance(e.value.__cause__, TypeError)
+
+
+def test_unsupported_class():
+    class Plain:
+        pass
+
+    with pytest.raises(serde_numpy.StructureError) as e:
+        NumpyDeserializer.from_class(Plain)
+    assert "as class structure" in str(e.value.__cause__)
+
+
+@pytest.mark.parametrize("name", ["StructureError", "MissingKeyError", "CastError", "DocumentSyntaxError"])
+def test_deprecated_type_error_base(name: str):
+    # TypeError is only kept until the next minor release, ValueError is the base to catch
+    assert issubclass(getattr(serde_numpy, name), ValueError)
+    assert issubclass(getattr(serde_numpy, name), TypeError)
 
 
 @pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
@@ -109,3 +131,216 @@ def test_deserialize_lom_as_lol(bytes_func: Tuple[bytes, Callable]):
         structure = {"stream4": [[np.float64, np.uint8, np.uint8]]}
         deserialized = deserialize_func(input_bytes, structure)
     assert str(e.value).startswith("invalid type: map, expected sequence with elements: [np.float64, np.uint8, np.uint8, ]")
+
+
+def test_error_path():
+    structure = {"records": [{"a": np.int8, "b": str}], "x": {"bbox": np.uint8}}
+    json_str = b'{"x": {"bbox": [[1, 2], [3, 4]]}, "records": [{"a": 1, "b": "x"}, {"b": "y", "a": 300}]}'
+    with pytest.raises(TypeError) as e:
+        deserialize_json(json_str, structure)
+    assert "line 1 column" in str(e.value)
+    assert str(e.value).endswith("(path: $.records[1].a)")
+
+    with pytest.raises(TypeError) as e:
+        deserialize_json(b'{"records": [], "x": {"bbox": [[1, 2], [3, -4]]}}', structure)
+    assert str(e.value).endswith("(path: $.x.bbox[1][1])")
+
+
+def test_error_classes():
+    deserializer = NumpyDeserializer.from_dict({"a": np.uint8, "b": str, "c": [[np.int32]]})
+
+    with pytest.raises(serde_numpy.CastError) as e:
+        deserializer.deserialize_json(b'{"a": [1, 256], "b": "x", "c": []}')
+    assert isinstance(e.value, serde_numpy.SerdeNumpyError)
+    assert isinstance(e.value, TypeError)
+    assert (e.value.path, e.value.expected, e.value.found) == ("$.a[1]", "u8", "256")
+
+    with pytest.raises(serde_numpy.MissingKeyError) as e:
+        deserializer.deserialize_json(b'{"a": 1, "c": []}')
+    assert e.value.expected == '["b"]'
+
+    with pytest.raises(serde_numpy.DocumentSyntaxError) as e:
+        deserializer.deserialize_json(b'{"a": 1,\n "b": }')
+    assert (e.value.line, e.value.column) == (2, 7)
+
+    with pytest.raises(serde_numpy.StructureError):
+        deserializer.deserialize_json(b'{"a": 1, "b": "x", "c": {"d": 1}}')
+
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'{"a": [[1, 2], [3]], "b": "x", "c": []}')
+    assert isinstance(e.value, ValueError)
+
+
+@pytest.mark.parametrize("limits,message", [
+    ({"max_depth": 2}, "Document is nested too deeply"),
+    ({"max_array_elements": 3}, "Array has more than 3 elements"),
+    ({"max_output_bytes": 16}, "Output is larger than 16 bytes"),
+])
+def test_limits(limits: dict, message: str):
+    structure = {"arr": np.float64, "rows": [{"a": np.int8}]}
+    json_str = b'{"arr": [[1, 2], [3, 4]], "rows": [{"a": 1}, {"a": 2}]}'
+    NumpyDeserializer.from_dict(structure, limits={"max_depth": 3, "max_array_elements": 4, "max_output_bytes": 34}).deserialize_json(json_str)
+    with pytest.raises(serde_numpy.LimitError) as e:
+        NumpyDeserializer.from_dict(structure, limits=limits).deserialize_json(json_str)
+    assert str(e.value).startswith(message)
+    assert isinstance(e.value, ValueError)
+
+
+@pytest.mark.parametrize("structure,json_str", [
+    ({"s": str}, b'{"s": "%s"}' % (b"x" * 1000)),
+    ([{"s": str}], b'[{"s": "x"}, {"s": "%s"}]' % (b"x" * 1000)),
+    ({"a": "any"}, b'{"a": [%s]}' % b", ".join([b"1"] * 100)),
+    ([{"v": np.float32}], b'[{"v": [1]}, {"v": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]}]'),
+])
+def test_limits_of_python_values_and_skipped_records(structure, json_str: bytes):
+    deserializer = NumpyDeserializer.from_dict(structure, limits={"max_array_elements": 10, "max_output_bytes": 100}, on_error="skip")
+    with pytest.raises(serde_numpy.LimitError):
+        deserializer.deserialize_json(json_str)
+
+
+@pytest.mark.parametrize("structure,json_str", [
+    ([], b'[[1, 2]]'),
+    ({"rows": []}, b'{"rows": [[1, 2]]}'),
+])
+def test_empty_transposed_structure(structure, json_str: bytes):
+    with pytest.raises(serde_numpy.StructureError) as e:
+        NumpyDeserializer.from_dict(structure).deserialize_json(json_str)
+    assert str(e.value).startswith("Transposed structure is empty")
+
+
+def test_iter_json_errors():
+    deserializer = NumpyDeserializer.from_dict({"meta": str, "rows": [[np.uint8]]})
+    with pytest.raises(serde_numpy.StructureError):
+        deserializer.iter_json(b"{}", chunk_size=2)
+    with pytest.raises(ValueError):
+        deserializer.iter_json(b"{}", chunk_size=0, field="rows")
+    chunks = deserializer.iter_json(b'{"rows": [[1], [2], [300]]}', chunk_size=2, field="rows")
+    assert next(chunks)[0].tolist() == [1, 2]
+    with pytest.raises(serde_numpy.CastError) as e:
+        next(chunks)
+    assert e.value.path == "$.rows[2][0]"
+
+
+def test_read_files_errors(tmp_path):
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32})
+    with pytest.raises(OSError):
+        deserializer.read_json(str(tmp_path / "missing.json"))
+    (tmp_path / "empty.json").write_bytes(b"")
+    with pytest.raises(serde_numpy.DocumentSyntaxError):
+        deserializer.read_json(str(tmp_path / "empty.json"))
+
+
+def test_document_input_errors():
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32})
+    with pytest.raises(TypeError):
+        deserializer.deserialize_json(1)
+    with pytest.raises(ValueError):
+        deserializer.deserialize_json(np.frombuffer(b'{"a": 1}  ', dtype=np.uint8)[::2])
+    with pytest.raises(serde_numpy.DocumentSyntaxError):
+        deserializer.deserialize_json(io.StringIO('{"a": 1}'))
+    with pytest.raises(serde_numpy.DocumentSyntaxError) as e:
+        deserializer.deserialize_json(io.BytesIO(b'{"a": '))
+    assert e.value.line == 1
+
+
+def test_decompression_errors():
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32})
+    compressed = gzip.compress(b'{"a": [1, 2, 3, 4, 5, 6, 7, 8]}')
+    with pytest.raises(serde_numpy.DecompressionError) as e:
+        deserializer.deserialize_json(compressed[:-6])
+    assert isinstance(e.value, ValueError)
+    with pytest.raises(serde_numpy.DecompressionError):
+        deserializer.deserialize_json(b'{"a": [1]}', compression="zstd")
+    with pytest.raises(serde_numpy.DocumentSyntaxError):
+        deserializer.deserialize_json(compressed, compression="none")
+    with pytest.raises(ValueError):
+        deserializer.deserialize_json(compressed, compression="brotli")
+
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32}, limits={"max_decompressed_bytes": 16})
+    with pytest.raises(serde_numpy.LimitError) as e:
+        deserializer.deserialize_json(io.BytesIO(compressed))
+    assert str(e.value).startswith("Decompressed document is larger than 16 bytes")
+
+
+def test_msgpack_numpy_errors():
+    deserializer = NumpyDeserializer.from_dict({"a": np.int8})
+    encoded = {b"nd": True, b"type": "<f8", b"kind": b"", b"shape": [2], b"data": np.array([1.0, 1e6]).tobytes()}
+    with pytest.raises(serde_numpy.CastError) as e:
+        deserializer.deserialize_msgpack(msgpack.packb({"a": encoded}))
+    assert e.value.path == "$.a"
+    # floats are only cast to integers without a fractional part
+    with pytest.raises(serde_numpy.CastError):
+        deserializer.deserialize_msgpack(msgpack.packb({"a": {**encoded, b"data": np.array([1.0, 1.5]).tobytes()}}))
+
+    with pytest.raises(serde_numpy.ShapeError):
+        deserializer.deserialize_msgpack(msgpack.packb({"a": {**encoded, b"shape": [3]}}))
+    with pytest.raises(serde_numpy.CastError):
+        deserializer.deserialize_msgpack(msgpack.packb({"a": {**encoded, b"type": "<c16"}}))
+    with pytest.raises(serde_numpy.StructureError):
+        deserializer.deserialize_msgpack(msgpack.packb({"a": {"x": 1}}))
+
+
+def test_binary_array_errors():
+    deserializer = NumpyDeserializer.from_dict({"a": {"dtype": np.float32, "encoding": "base64", "shape": [3, -1]}})
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'{"a": "AAAAAAAAAAA="}')
+    assert e.value.path == "$.a"
+    with pytest.raises(serde_numpy.CastError):
+        deserializer.deserialize_json(b'{"a": "not base64!"}')
+    with pytest.raises(serde_numpy.StructureError):
+        NumpyDeserializer.from_dict({"a": {"dtype": np.float32, "encoding": "base32"}})
+
+
+def test_reshape_errors():
+    deserializer = NumpyDeserializer.from_dict({"t": {"reshape": np.float32, "data": "data", "shape": "shape", "dtype": "dtype"}})
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'{"t": {"data": [1, 2, 3], "shape": [2, 2]}}')
+    assert e.value.path == "$.t.data"
+    with pytest.raises(serde_numpy.MissingKeyError):
+        deserializer.deserialize_json(b'{"t": {"data": [1, 2, 3]}}')
+    with pytest.raises(serde_numpy.CastError) as e:
+        deserializer.deserialize_json(b'{"t": {"data": [1], "shape": [1], "dtype": "complex64"}}')
+    assert e.value.path == "$.t.dtype"
+
+
+def test_kserve_v2_errors():
+    deserializer = NumpyDeserializer.from_dict({"kserve_v2": "inputs"})
+    with pytest.raises(serde_numpy.MissingKeyError):
+        deserializer.deserialize_json(b'{"outputs": []}')
+    with pytest.raises(serde_numpy.CastError) as e:
+        deserializer.deserialize_json(b'{"inputs": [{"name": "x", "shape": [1], "datatype": "BF16", "data": [1]}]}')
+    assert e.value.path == "$.inputs[0].datatype"
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'{"inputs": [{"name": "x", "shape": [2, 2], "datatype": "FP32", "data": [1, 2, 3]}]}')
+    assert e.value.path == "$.inputs[0].data"
+    with pytest.raises(TypeError):
+        serde_numpy.encode_kserve_v2({"x": np.array([1 + 2j])})
+
+
+def test_tf_serving_errors():
+    deserializer = NumpyDeserializer.from_dict({"tf_serving": {"x": np.float32}})
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'{"instances": [{"x": [1, 2]}, {"x": [3]}]}')
+    assert e.value.path == "$.instances[1].x"
+    with pytest.raises(serde_numpy.MissingKeyError) as e:
+        deserializer.deserialize_json(b'{"instances": [{"x": [1, 2]}, {"y": [3, 4]}]}')
+    assert e.value.path == "$.instances[1]"
+    with pytest.raises(serde_numpy.MissingKeyError):
+        deserializer.deserialize_json(b'{"signature_name": "serving_default"}')
+
+
+def test_tensor_columns_errors():
+    deserializer = NumpyDeserializer.from_dict([{"id": np.int32, "bbox": np.float32}])
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b'[{"id": 1, "bbox": [0, 0, 10, 20]}, {"id": 2, "bbox": [5, 5, 15]}]')
+    assert e.value.path == "$[1].bbox"
+
+
+def test_sequences_errors():
+    deserializer = NumpyDeserializer.from_dict([[[np.float64, np.int32]]])
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserializer.deserialize_json(b"[[[0.5, 1], [1.5, 2]], [[2.5, 3]]]")
+    assert e.value.path == "$[1]"
+
+    with pytest.raises(ValueError):
+        NumpyDeserializer.from_dict([[[np.float64, np.int32]]], ragged="truncate")
@@ -1,11 +1,23 @@
 from itertools import product
+import base64
+import gc
+import gzip
+import io
+import json
+import sys
+import weakref
 import pytest
-from typing import Any, Callable, Tuple, Type
+from dataclasses import dataclass
+from typing import Any, Callable, List, NamedTuple, Tuple, Type
 
+import msgpack
 import numpy as np
 
+import serde_numpy
+from serde_numpy import NumpyDeserializer, encode_kserve_v2
+
 from .fixtures import json_str, msgpack_bytes
-from .utils import deserialize_json, deserialize_msgpack, assert_correct_types, assert_same_structure
+from .utils import deserialize_json, deserialize_msgpack, deserialize_class, assert_correct_types, assert_same_structure
 
 
 def test_parses():
@@ -188,5 +200,494 @@ def test_missing_key_tranpose(bytes_func: Tuple[bytes, Callable]):
 
 
 
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_one_of(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    one_of = {"oneOf": [np.float32, {"values": np.float32, "shape": np.int64}]}
+    structure = {"union_bare": one_of, "union_wrapped": one_of}
+    deserialized = deserialize_func(input_bytes, structure)
+    assert deserialized["union_bare"].dtype == np.float32
+    assert np.array_equal(deserialized["union_bare"], [1.5, 2.5])
+    assert deserialized["union_wrapped"]["values"].dtype == np.float32
+    assert np.array_equal(deserialized["union_wrapped"]["values"], [1.5, 2.5])
+    assert np.array_equal(deserialized["union_wrapped"]["shape"], [2])
+
+
+@pytest.mark.parametrize("input_bytes,deserialize_func", [(b"[[1, 2], [3, 4]]", deserialize_json),
+                                                          (msgpack.dumps([[1, 2], [3, 4]]), deserialize_msgpack)])
+def test_top_level_array(input_bytes: bytes, deserialize_func: Callable):
+    deserialized = deserialize_func(input_bytes, np.float32)
+    assert deserialized.dtype == np.float32
+    assert np.array_equal(deserialized, [[1, 2], [3, 4]])
+
+
+@pytest.mark.parametrize("input_bytes,deserialize_func", [(b"3", deserialize_json), (msgpack.dumps(3), deserialize_msgpack)])
+def test_top_level_scalar(input_bytes: bytes, deserialize_func: Callable):
+    assert deserialize_func(input_bytes, int) == 3
+    assert deserialize_func(input_bytes, "float32") == 3.0
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_key_order(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    structure = {"stream4": [{"z": np.uint8, "x": np.float64, "y": np.uint8}],
+                 "nest": {"stream1": np.int32, "is_nest": bool},
+                 "int": int}
+    deserialized = deserialize_func(input_bytes, structure)
+    assert list(deserialized) == ["stream4", "nest", "int"]
+    assert list(deserialized["stream4"]) == ["z", "x", "y"]
+    assert list(deserialized["nest"]) == ["stream1", "is_nest"]
+
+
+@dataclass
+class Nest:
+    is_nest: bool
+    stream1: np.int32
+
+
+@dataclass
+class Sample:
+    str: str
+    float_arr: np.float32
+    nest: Nest
+
+
+class SampleTuple(NamedTuple):
+    int: int
+    uint_arr: np.uint16
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_dataclass(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserialized = deserialize_class(input_bytes, Sample, deserialize_func)
+    assert isinstance(deserialized, Sample)
+    assert isinstance(deserialized.nest, Nest)
+    assert deserialized.str == "h"
+    assert deserialized.float_arr.dtype == np.float32
+    assert deserialized.nest.is_nest == True
+    assert np.array_equal(deserialized.nest.stream1, [[0, 28], [0, 9]])
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_namedtuple(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserialized = deserialize_class(input_bytes, SampleTuple, deserialize_func)
+    assert isinstance(deserialized, SampleTuple)
+    assert deserialized.int == 3
+    assert deserialized.uint_arr.dtype == np.uint16
+
+
+@pytest.mark.skipif(sys.version_info < (3, 8), reason="TypedDict requires python >= 3.8")
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_typeddict(bytes_func: Tuple[bytes, Callable]):
+    from typing import TypedDict
+
+    class SampleDict(TypedDict):
+        bool_arr: np.bool_
+
+    input_bytes, deserialize_func = bytes_func
+    deserialized = deserialize_class(input_bytes, SampleDict, deserialize_func)
+    assert list(deserialized) == ["bool_arr"]
+    assert deserialized["bool_arr"].dtype == np.bool_
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_attrs_class(bytes_func: Tuple[bytes, Callable]):
+    attr = pytest.importorskip("attr")
+
+    @attr.s(auto_attribs=True)
+    class AttrsSample:
+        float: float
+        int_arr: np.int64
+
+    input_bytes, deserialize_func = bytes_func
+    deserialized = deserialize_class(input_bytes, AttrsSample, deserialize_func)
+    assert isinstance(deserialized, AttrsSample)
+    assert deserialized.float == 0.34
+    assert deserialized.int_arr.dtype == np.int64
+
+
+@dataclass
+class Point:
+    x: float
+    y: np.int32
+
+
+@dataclass
+class Path:
+    name: str
+    points: List[Point]
+
+
+def test_list_of_dataclasses():
+    deserializer = NumpyDeserializer.from_class(Path)
+    deserialized = deserializer.deserialize_json(b'{"name": "p", "points": [{"x": 0.5, "y": 1}, {"x": 1.5, "y": [2, 3]}]}')
+    assert isinstance(deserialized, Path)
+    assert [type(point) for point in deserialized.points] == [Point, Point]
+    assert deserialized.points[1].x == 1.5
+    assert deserialized.points[1].y.tolist() == [2, 3]
+
+
+def test_classes_released_with_deserializer():
+    @dataclass
+    class Temporary:
+        a: int
+
+    deserializer = NumpyDeserializer.from_class(Temporary)
+    assert isinstance(deserializer.deserialize_json(b'{"a": 1}'), Temporary)
+    reference = weakref.ref(Temporary)
+    del deserializer, Temporary
+    gc.collect()
+    assert reference() is None
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_records_pandas(bytes_func: Tuple[bytes, Callable]):
+    pd = pytest.importorskip("pandas")
+    input_bytes, deserialize_func = bytes_func
+    structure = {"stream4": [{"x": np.float64, "y": "category", "z": np.uint8}],
+                 "stream2": [[np.float32, np.bool_, str]]}
+    deserializer = NumpyDeserializer.from_dict(structure, records="pandas")
+    deserialized = getattr(deserializer, deserialize_func.__name__)(input_bytes)
+    assert isinstance(deserialized["stream4"], pd.DataFrame)
+    assert list(deserialized["stream4"].columns) == ["x", "y", "z"]
+    assert deserialized["stream4"]["z"].dtype == np.uint8
+    assert isinstance(deserialized["stream4"]["y"].dtype, pd.CategoricalDtype)
+    assert list(deserialized["stream2"].columns) == [0, 1, 2]
+    assert list(deserialized["stream2"][2]) == ["a", "b", "c"]
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_records_arrow(bytes_func: Tuple[bytes, Callable]):
+    pa = pytest.importorskip("pyarrow")
+    input_bytes, deserialize_func = bytes_func
+    structure = {"stream4": [{"x": np.float64, "y": "category", "z": np.uint8}]}
+    deserializer = NumpyDeserializer.from_dict(structure, records="arrow")
+    deserialized = getattr(deserializer, deserialize_func.__name__)(input_bytes)
+    assert isinstance(deserialized["stream4"], pa.Table)
+    assert deserialized["stream4"].column_names == ["x", "y", "z"]
+    assert deserialized["stream4"].schema.field("z").type == pa.uint8()
+    assert pa.types.is_dictionary(deserialized["stream4"].schema.field("y").type)
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_out_buffers(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserializer = NumpyDeserializer.from_dict({"float_arr": np.float32, "int_arr": np.int64})
+    deserialize = getattr(deserializer, deserialize_func.__name__)
+
+    out = {"float_arr": np.zeros((2, 2), dtype=np.float32)}
+    deserialized = deserialize(input_bytes, out=out)
+    assert deserialized is out
+    assert np.allclose(out["float_arr"], [[1.254439975231648, -0.6893827594332794], [-0.2922560025562806, 0.5204819306523419]])
+    assert out["int_arr"].dtype == np.int64
+
+    batch = {"float_arr": np.zeros((3, 2, 2), dtype=np.float32), "int_arr": np.zeros((3, 2, 2), dtype=np.int64)}
+    deserialize(input_bytes, out=batch, index=1)
+    assert np.all(batch["float_arr"][0] == 0) and np.all(batch["float_arr"][2] == 0)
+    assert np.array_equal(batch["int_arr"][1], [[-100, -25], [-41, -62]])
+
 
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_out_buffers_not_contiguous(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserializer = NumpyDeserializer.from_dict({"float_arr": np.float32})
+    deserialize = getattr(deserializer, deserialize_func.__name__)
+    # a strided view is parsed as usual and then copied into
+    out = np.zeros((2, 4), dtype=np.float32)[:, ::2]
+    deserialize(input_bytes, out={"float_arr": out})
+    assert np.allclose(out, [[1.254439975231648, -0.6893827594332794], [-0.2922560025562806, 0.5204819306523419]])
 
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_out_buffers_wrong_shape(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserializer = NumpyDeserializer.from_dict({"float_arr": np.float32})
+    deserialize = getattr(deserializer, deserialize_func.__name__)
+    with pytest.raises(serde_numpy.ShapeError) as e:
+        deserialize(input_bytes, out={"float_arr": np.zeros((3, 2), dtype=np.float32)})
+    assert e.value.path == "$.float_arr"
+    with pytest.raises(serde_numpy.ShapeError):
+        deserialize(input_bytes, out={"float_arr": np.zeros((4,), dtype=np.float32)})
+    with pytest.raises(ValueError):
+        deserialize(input_bytes, out={"float_arr": np.zeros((2, 2), dtype=np.float64)})
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_arrays_dlpack(bytes_func: Tuple[bytes, Callable]):
+    input_bytes, deserialize_func = bytes_func
+    deserializer = NumpyDeserializer.from_dict({"float_arr": np.float32, "int": int}, arrays="dlpack")
+    deserialized = getattr(deserializer, deserialize_func.__name__)(input_bytes)
+    assert type(deserialized["float_arr"]).__name__ == "PyCapsule"
+    assert deserialized["int"] == 3
+
+
+@pytest.mark.parametrize("bytes_func", [(json_str, deserialize_json), (msgpack_bytes, deserialize_msgpack)])
+def test_arrays_torch(bytes_func: Tuple[bytes, Callable]):
+    torch = pytest.importorskip("torch")
+    input_bytes, deserialize_func = bytes_func
+    deserializer = NumpyDeserializer.from_dict({"float_arr": np.float32, "int_arr": np.int64, "bool_arr": np.bool_}, arrays="torch")
+    deserialized = getattr(deserializer, deserialize_func.__name__)(input_bytes)
+    assert deserialized["float_arr"].dtype == torch.float32
+    assert deserialized["bool_arr"].dtype == torch.bool
+    assert deserialized["int_arr"].tolist() == [[-100, -25], [-41, -62]]
+
+
+def test_jsonl():
+    deserializer = NumpyDeserializer.from_dict([{"a": np.uint8, "b": str}])
+    deserialized = deserializer.deserialize_jsonl(b'{"a": 1, "b": "x"}\n{"b": "y", "a": 2}\n\n')
+    assert np.array_equal(deserialized["a"], [1, 2])
+    assert deserialized["b"] == ["x", "y"]
+
+
+@pytest.mark.parametrize("on_error", ["skip", "fill"])
+def test_on_error(on_error: str):
+    deserializer = NumpyDeserializer.from_dict({"df": [{"a": np.uint8, "b": np.float32}]}, on_error=on_error)
+    json_str = b'{"df": [{"a": 1, "b": 1.5}, {"a": 300, "b": 2.5}, {"a": 3}, {"a": 4, "b": 4.5}]}'
+    deserialized, errors = deserializer.deserialize_json(json_str)
+    assert [(error["index"], error["path"]) for error in errors] == [(1, "$.df[1].a"), (2, "$.df[2]")]
+    assert errors[1]["message"] == 'Key(s) not found: ["b"]'
+    if on_error == "skip":
+        assert np.array_equal(deserialized["df"]["a"], [1, 4])
+        assert np.array_equal(deserialized["df"]["b"], [1.5, 4.5])
+    else:
+        assert np.array_equal(deserialized["df"]["a"], [1, 0, 0, 4])
+        assert np.array_equal(deserialized["df"]["b"], [1.5, np.nan, np.nan, 4.5], equal_nan=True)
+
+
+@pytest.mark.parametrize("as_file", [False, True])
+def test_iter_json(as_file: bool):
+    deserializer = NumpyDeserializer.from_dict({"meta": str, "rows": [{"a": np.uint8, "b": str}]})
+    json_str = b'{"meta": "x", "rows": [' + b", ".join(b'{"a": %d, "b": "%d"}' % (i, i) for i in range(7)) + b"]}"
+    chunks = list(deserializer.iter_json(io.BytesIO(json_str) if as_file else json_str, chunk_size=3, field="rows"))
+    assert [chunk["a"].tolist() for chunk in chunks] == [[0, 1, 2], [3, 4, 5], [6]]
+    assert chunks[2]["b"] == ["6"]
+
+
+def test_iter_json_on_error():
+    deserializer = NumpyDeserializer.from_dict([{"a": np.uint8}], on_error="skip")
+    chunks = list(deserializer.iter_json(b'[{"a": 1}, {"a": 300}, {"a": 3}, {"a": 4}]', chunk_size=2))
+    assert [chunk["a"].tolist() for chunk, _ in chunks] == [[1, 3], [4]]
+    assert [[error["index"] for error in errors] for _, errors in chunks] == [[1], []]
+
+    deserializer = NumpyDeserializer.from_dict({"rows": [{"a": np.uint8}]}, on_error="skip")
+    chunks = list(deserializer.iter_json(b'{"rows": [{"a": 1}, {"a": 300}, {"a": 3}]}', chunk_size=2, field="rows"))
+    assert [[error["path"] for error in errors] for _, errors in chunks] == [["$.rows[1].a"], []]
+
+
+def test_read_files(tmp_path):
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32, "b": [{"x": np.float64}]})
+    document = {"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]}
+    (tmp_path / "doc.json").write_bytes(b'{"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]}')
+    (tmp_path / "doc.msgpack").write_bytes(msgpack.packb(document))
+    for deserialized in [
+        deserializer.read_json(str(tmp_path / "doc.json")),
+        deserializer.read_msgpack(str(tmp_path / "doc.msgpack")),
+    ]:
+        assert deserialized["a"].tolist() == [1, 2]
+        assert deserialized["b"]["x"].tolist() == [1.5, 2.5]
+
+
+@pytest.mark.parametrize("to_document", [
+    bytes,
+    bytearray,
+    memoryview,
+    lambda doc: doc.decode(),
+    lambda doc: np.frombuffer(doc, dtype=np.uint8),
+    io.BytesIO,
+    lambda doc: gzip.GzipFile(fileobj=io.BytesIO(gzip.compress(doc))),
+])
+def test_document_inputs(to_document: Callable):
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32, "b": [{"x": np.float64}]})
+    json_str = b'{"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]}'
+    deserialized = deserializer.deserialize_json(to_document(json_str))
+    assert deserialized["a"].tolist() == [1, 2]
+    assert deserialized["b"]["x"].tolist() == [1.5, 2.5]
+
+    msgpack_bytes = msgpack.packb({"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]})
+    if not isinstance(to_document(json_str), str):
+        assert deserializer.deserialize_msgpack(to_document(msgpack_bytes))["a"].tolist() == [1, 2]
+
+
+# {"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]} compressed by the zstd and lz4 command line tools
+zstd_json = b'(\xb5/\xfd$,a\x01\x00{"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]}p\xa07x'
+lz4_json = b'\x04"M\x18d@\xa7*\x00\x00\x00\xf0\x01{"a": [1, 2], "b\r\x00\xc2{"x": 1.5}, \x0c\x00`2.5}]}\x00\x00\x00\x00\xd4\xad!n'
+
+
+@pytest.mark.parametrize("compressed,compression", [
+    (gzip.compress(b'{"a": [1, 2], "b": [{"x": 1.5}, {"x": 2.5}]}'), "gzip"),
+    (zstd_json, "zstd"),
+    (lz4_json, "lz4"),
+])
+def test_compressed_documents(tmp_path, compressed: bytes, compression: str):
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32, "b": [{"x": np.float64}]})
+    (tmp_path / "doc.json").write_bytes(compressed)
+    for deserialized in [
+        deserializer.deserialize_json(compressed),
+        deserializer.deserialize_json(compressed, compression=compression),
+        deserializer.deserialize_json(io.BytesIO(compressed)),
+        deserializer.read_json(str(tmp_path / "doc.json")),
+    ]:
+        assert deserialized["a"].tolist() == [1, 2]
+        assert deserialized["b"]["x"].tolist() == [1.5, 2.5]
+
+
+def test_compressed_msgpack_and_jsonl(tmp_path):
+    deserializer = NumpyDeserializer.from_dict({"a": np.int32})
+    compressed = gzip.compress(msgpack.packb({"a": [1, 2]}))
+    (tmp_path / "doc.msgpack.gz").write_bytes(compressed)
+    assert deserializer.deserialize_msgpack(compressed)["a"].tolist() == [1, 2]
+    assert deserializer.read_msgpack(str(tmp_path / "doc.msgpack.gz"))["a"].tolist() == [1, 2]
+
+    deserializer = NumpyDeserializer.from_dict([{"a": np.int32}])
+    deserialized = deserializer.deserialize_jsonl(gzip.compress(b'{"a": 1}\n{"a": 2}\n'), compression="gzip")
+    assert np.array_equal(deserialized["a"], [1, 2])
+
+
+def msgpack_numpy_encode(array: np.ndarray) -> dict:
+    """The encoding of msgpack-numpy, with bytes keys"""
+    if array.ndim == 0:
+        return {b"nd": False, b"type": array.dtype.str, b"kind": b"", b"data": array.tobytes()}
+    return {b"nd": True, b"type": array.dtype.str, b"kind": b"", b"shape": array.shape, b"data": array.tobytes()}
+
+
+def test_msgpack_numpy():
+    deserializer = NumpyDeserializer.from_dict({"a": np.float64, "b": np.int16, "c": np.bool_, "d": np.int64})
+    a = np.arange(12, dtype=np.float32).reshape(3, 4)
+    document = msgpack.packb({
+        "a": msgpack_numpy_encode(a),
+        "b": msgpack_numpy_encode(np.array([1, -2, 3], dtype=">i4")),
+        "c": msgpack_numpy_encode(np.array([True, False])),
+        "d": msgpack_numpy_encode(np.int32(7)),
+    }, use_bin_type=True)
+    deserialized = deserializer.deserialize_msgpack(document)
+    assert deserialized["a"].dtype == np.float64
+    assert np.array_equal(deserialized["a"], a)
+    assert deserialized["b"].tolist() == [1, -2, 3]
+    assert deserialized["c"].tolist() == [True, False]
+    assert deserialized["d"] == 7
+
+    deserializer = NumpyDeserializer.from_dict({"rows": np.uint8})
+    rows = [msgpack_numpy_encode(np.array([i, i + 1], dtype=np.uint8)) for i in range(3)]
+    deserialized = deserializer.deserialize_msgpack(msgpack.packb({"rows": rows}))
+    assert deserialized["rows"].tolist() == [[0, 1], [1, 2], [2, 3]]
+
+
+def test_binary_arrays():
+    embeddings = np.arange(8, dtype=np.float32).reshape(2, 4)
+    ids = np.array([1, -2, 300], dtype=">i2")
+    deserializer = NumpyDeserializer.from_dict({
+        "embeddings": {"dtype": np.float32, "encoding": "base64", "shape": [-1, 4]},
+        "ids": {"dtype": np.int16, "encoding": "hex", "byteorder": ">"},
+    })
+    document = b'{"embeddings": "%s", "ids": "%s"}' % (base64.b64encode(embeddings.tobytes()), ids.tobytes().hex().encode())
+    deserialized = deserializer.deserialize_json(document)
+    assert deserialized["embeddings"].dtype == np.float32
+    assert np.array_equal(deserialized["embeddings"], embeddings)
+    assert deserialized["ids"].tolist() == [1, -2, 300]
+
+    ids = np.array([1, 2**40, 2**64 - 1], dtype=np.uint64)
+    deserializer = NumpyDeserializer.from_dict({"ids": {"dtype": "uint64", "encoding": "base64"}})
+    deserialized = deserializer.deserialize_json(b'{"ids": "%s"}' % base64.b64encode(ids.tobytes()))
+    assert deserialized["ids"].dtype == np.uint64
+    assert deserialized["ids"].tolist() == ids.tolist()
+
+    deserializer = NumpyDeserializer.from_dict({"mask": {"dtype": np.bool_, "encoding": "bin"}})
+    deserialized = deserializer.deserialize_msgpack(msgpack.packb({"mask": np.array([True, False]).tobytes()}))
+    assert deserialized["mask"].tolist() == [True, False]
+
+
+@pytest.mark.parametrize("encode,method", [(lambda value: json.dumps(value).encode(), "deserialize_json"), (msgpack.packb, "deserialize_msgpack")])
+def test_reshape(encode: Callable, method: str):
+    deserializer = NumpyDeserializer.from_dict({"t": {"reshape": np.float32, "data": "data", "shape": "shape", "dtype": "dtype"}})
+    deserialize = getattr(deserializer, method)
+    for tensor in [
+        {"shape": [2, 3], "data": [1, 2, 3, 4, 5, 6]},
+        {"data": [1, 2, 3, 4, 5, 6], "shape": [2, 3]},
+    ]:
+        deserialized = deserialize(encode({"t": tensor}))
+        assert deserialized["t"].dtype == np.float32
+        assert deserialized["t"].tolist() == [[1, 2, 3], [4, 5, 6]]
+
+    for tensor in [
+        {"dtype": "int64", "data": [1, 2, 3, 4], "shape": [2, 2]},
+        {"data": [1, 2, 3, 4], "shape": [2, 2], "dtype": "int64"},
+    ]:
+        deserialized = deserialize(encode({"t": tensor}))
+        assert deserialized["t"].dtype == np.int64
+        assert deserialized["t"].tolist() == [[1, 2], [3, 4]]
+
+
+def test_kserve_v2():
+    request = {
+        "id": "42",
+        "inputs": [
+            {"name": "x", "shape": [2, 2], "datatype": "FP16", "data": [1, 2, 3, 4]},
+            {"name": "ids", "shape": [3], "datatype": "INT64", "parameters": {}, "data": [1, -2, 3]},
+            {"name": "text", "shape": [2], "datatype": "BYTES", "data": ["hello", "world"]},
+        ],
+    }
+    deserializer = NumpyDeserializer.from_dict({"kserve_v2": "inputs"})
+    deserialized = deserializer.deserialize_json(json.dumps(request))
+    assert list(deserialized) == ["x", "ids", "text"]
+    assert deserialized["x"].dtype == np.float16
+    assert deserialized["x"].tolist() == [[1, 2], [3, 4]]
+    assert deserialized["ids"].dtype == np.int64
+    assert deserialized["text"].dtype == object
+    assert deserialized["text"].tolist() == [b"hello", b"world"]
+
+    # encoding a response and decoding it gives back the same arrays
+    response = encode_kserve_v2(deserialized, model_name="model", id="42")
+    assert json.loads(response)["outputs"][0] == {"name": "x", "shape": [2, 2], "datatype": "FP16", "data": [1, 2, 3, 4]}
+    decoded = NumpyDeserializer.from_dict({"kserve_v2": "outputs"}).deserialize_json(response)
+    for name, array in deserialized.items():
+        assert decoded[name].dtype == array.dtype
+        assert np.array_equal(decoded[name], array)
+
+
+def test_tf_serving():
+    deserializer = NumpyDeserializer.from_dict({"tf_serving": {"x": np.float32, "image": str}})
+    rows = {"signature_name": "serving_default", "instances": [
+        {"x": [1, 2, 3], "image": {"b64": base64.b64encode(b"\x00\xff").decode()}},
+        {"x": [4, 5, 6], "image": {"b64": base64.b64encode(b"png").decode()}},
+    ]}
+    columns = {"inputs": {"x": [[1, 2, 3], [4, 5, 6]], "image": [{"b64": "AP8="}, "png"]}}
+    for request in [rows, columns]:
+        deserialized = deserializer.deserialize_json(json.dumps(request))
+        assert deserialized["x"].dtype == np.float32
+        assert deserialized["x"].tolist() == [[1, 2, 3], [4, 5, 6]]
+        assert deserialized["image"].tolist() == [b"\x00\xff", b"png"]
+
+    deserializer = NumpyDeserializer.from_dict({"tf_serving": np.int64})
+    deserialized = deserializer.deserialize_json(b'{"instances": [[1, 2], [3, 4], [5, 6]]}')
+    assert deserialized.shape == (3, 2)
+
+
+def test_tensor_columns():
+    deserializer = NumpyDeserializer.from_dict([{"id": np.int32, "embedding": np.float32}])
+    records = [{"id": i, "embedding": [i, i + 0.5, i + 1]} for i in range(4)]
+    deserialized = deserializer.deserialize_json(json.dumps(records))
+    assert deserialized["id"].tolist() == [0, 1, 2, 3]
+    assert deserialized["embedding"].shape == (4, 3)
+    assert deserialized["embedding"][2].tolist() == [2, 2.5, 3]
+
+    deserializer = NumpyDeserializer.from_dict([[np.int32, np.float32]], records="pandas")
+    df = deserializer.deserialize_json(b"[[1, [1, 2]], [2, [3, 4]]]")
+    assert df[1].tolist()[1].tolist() == [3, 4]
+
+
+def test_sequences():
+    deserializer = NumpyDeserializer.from_dict({"batch": [[[np.float32, np.int32, str]]]})
+    batch = [[[t + 0.5, t, f"{i}-{t}"] for t in range(3)] for i in range(2)]
+    deserialized = deserializer.deserialize_json(json.dumps({"batch": batch}))["batch"]
+    assert deserialized[0].shape == (2, 3)
+    assert deserialized[0].dtype == np.float32
+    assert deserialized[1].tolist() == [[0, 1, 2], [0, 1, 2]]
+    assert deserialized[2] == [["0-0", "0-1", "0-2"], ["1-0", "1-1", "1-2"]]
+
+    deserializer = NumpyDeserializer.from_dict([[[np.float64, np.int32]]], ragged="pad")
+    deserialized = deserializer.deserialize_json(b"[[[0.5, 1], [1.5, 2]], [[2.5, 3]]]")
+    assert deserialized[0].shape == (2, 2)
+    assert np.isnan(deserialized[0][1, 1])
+    assert deserialized[1].tolist() == [[1, 2], [3, 0]]
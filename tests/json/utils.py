@@ -1,4 +1,4 @@
-from typing import Any
+from typing import Any, Callable
 import numpy as np
 
 from serde_numpy import NumpyDeserializer
@@ -14,6 +14,13 @@ def deserialize_msgpack(msgpack_bytes: bytes, structure: dict):
     return deserializer.deserialize_msgpack(msgpack_bytes)
 
 
+def deserialize_class(input_bytes: bytes, cls: type, deserialize_func: Callable):
+    deserializer = NumpyDeserializer.from_class(cls)
+    if deserialize_func is deserialize_json:
+        return deserializer.deserialize_json(input_bytes)
+    return deserializer.deserialize_msgpack(input_bytes)
+
+
 def assert_same_structure(dict_1: dict, dict_2: dict):
     assert set(dict_1.keys()) == set(dict_2.keys()), f"Dict 1 keys: {dict_1.keys()}  Dict 2 keys: {dict_2.keys()}"
     for k, v in dict_1.items():